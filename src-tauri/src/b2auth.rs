@@ -0,0 +1,295 @@
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use zeroize::Zeroizing;
+
+use crate::storage::B2Credentials;
+
+const AUTHORIZE_URL: &str = "https://api.backblazeb2.com/b2api/v2/b2_authorize_account";
+// Account tokens last 24 hours; renew well before that.
+const ACCOUNT_TOKEN_LIFETIME: Duration = Duration::from_secs(23 * 60 * 60);
+// A cached download authorization is reused for a link with the same TTL
+// while it has at least the TTL less this share of it (at least a minute)
+// left, so a link never expires much earlier than asked, and never later.
+const REFRESH_DIVISOR: u32 = 10;
+const MIN_REFRESH_MARGIN: Duration = Duration::from_secs(60);
+
+/// A B2 download authorization: a token that reads every file whose name
+/// starts with the prefix it was issued for, until it expires.
+#[derive(Clone)]
+pub struct DownloadAuth {
+    /// `https://f00x.backblazeb2.com`, where `/file/<bucket>/<name>` is served.
+    pub download_url: String,
+    pub token: String,
+}
+
+struct Account {
+    account_id: String,
+    api_url: String,
+    download_url: String,
+    token: Zeroizing<String>,
+    expires: Instant,
+    /// Bucket name -> ID, looked up once per account.
+    bucket_ids: HashMap<String, String>,
+}
+
+struct CachedAuth {
+    token: String,
+    expires: Instant,
+}
+
+/// (application key ID, bucket, file name prefix, lifetime in seconds)
+type AuthKey = (String, String, String, u64);
+
+#[derive(Default)]
+struct State {
+    /// By application key ID, so switching profiles never mixes accounts.
+    accounts: HashMap<String, Account>,
+    downloads: HashMap<AuthKey, CachedAuth>,
+}
+
+/// App-wide cache of B2 native API authorizations: one account token for
+/// every link, and download tokens reused when the same prefix is linked
+/// again with the same TTL. Held behind an async lock: concurrent uploads
+/// wait for the first account request instead of each sending their own.
+pub struct DownloadAuthCache {
+    http: reqwest::Client,
+    state: Mutex<State>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AuthorizeResponse {
+    account_id: String,
+    authorization_token: String,
+    api_url: String,
+    download_url: String,
+    #[serde(default)]
+    allowed: Allowed,
+}
+
+/// What a restricted application key may touch.
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct Allowed {
+    bucket_id: Option<String>,
+    bucket_name: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ListBucketsResponse {
+    buckets: Vec<Bucket>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Bucket {
+    bucket_id: String,
+    bucket_name: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DownloadAuthResponse {
+    authorization_token: String,
+}
+
+#[derive(Deserialize)]
+struct ApiError {
+    code: String,
+    message: String,
+}
+
+/// A failed B2 API call. `expired` is set when the account token was
+/// rejected, so the caller can authorize again and retry.
+struct CallError {
+    message: String,
+    expired: bool,
+}
+
+impl DownloadAuthCache {
+    pub fn new() -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// A token for every file under `prefix` in `bucket` that stays valid for
+    /// about `lifetime` seconds, and never longer. Only a token issued for the
+    /// same lifetime is reused, so a link can't outlive its TTL.
+    pub async fn authorize(
+        &self,
+        creds: &B2Credentials,
+        bucket: &str,
+        prefix: &str,
+        lifetime: u64,
+    ) -> Result<DownloadAuth, String> {
+        let mut state = self.state.lock().await;
+        let now = Instant::now();
+        state.downloads.retain(|_, auth| auth.expires > now);
+
+        let duration = Duration::from_secs(lifetime);
+        let margin = (duration / REFRESH_DIVISOR).max(MIN_REFRESH_MARGIN).min(duration);
+        let cache_key = (creds.key_id.clone(), bucket.to_string(), prefix.to_string(), lifetime);
+        if let Some(cached) = state
+            .downloads
+            .get(&cache_key)
+            .filter(|a| a.expires >= now + duration - margin && a.expires <= now + duration)
+        {
+            let account = state.accounts.get(&creds.key_id).ok_or("B2 account authorization missing")?;
+            return Ok(DownloadAuth {
+                download_url: account.download_url.clone(),
+                token: cached.token.clone(),
+            });
+        }
+
+        // One retry with a fresh account token if B2 says ours has expired
+        let mut retried = false;
+        let (download_url, token) = loop {
+            match self.request_download_auth(&mut state, creds, bucket, prefix, lifetime).await {
+                Ok(result) => break result,
+                Err(e) if e.expired && !retried => {
+                    state.accounts.remove(&creds.key_id);
+                    retried = true;
+                }
+                Err(e) => return Err(e.message),
+            }
+        };
+
+        state.downloads.insert(
+            cache_key,
+            CachedAuth {
+                token: token.clone(),
+                expires: now + duration,
+            },
+        );
+        Ok(DownloadAuth { download_url, token })
+    }
+
+    async fn request_download_auth(
+        &self,
+        state: &mut State,
+        creds: &B2Credentials,
+        bucket: &str,
+        prefix: &str,
+        lifetime: u64,
+    ) -> Result<(String, String), CallError> {
+        let account = self.account(state, creds).await?;
+        let bucket_id = match account.bucket_ids.get(bucket) {
+            Some(id) => id.clone(),
+            None => {
+                let id = self.bucket_id(account, bucket).await?;
+                account.bucket_ids.insert(bucket.to_string(), id.clone());
+                id
+            }
+        };
+        let response: DownloadAuthResponse = self
+            .call(
+                account,
+                "b2_get_download_authorization",
+                json!({
+                    "bucketId": bucket_id,
+                    "fileNamePrefix": prefix,
+                    "validDurationInSeconds": lifetime,
+                }),
+            )
+            .await?;
+        Ok((account.download_url.clone(), response.authorization_token))
+    }
+
+    /// The cached account authorization for `creds`, authorizing again if
+    /// there is none or it's about to expire.
+    async fn account<'a>(&self, state: &'a mut State, creds: &B2Credentials) -> Result<&'a mut Account, CallError> {
+        let now = Instant::now();
+        if state.accounts.get(&creds.key_id).is_none_or(|a| a.expires <= now) {
+            let response = self
+                .http
+                .get(AUTHORIZE_URL)
+                .basic_auth(&creds.key_id, Some(&creds.app_key))
+                .send()
+                .await
+                .map_err(|e| call_error(format!("B2 authorization failed: {}", e)))?;
+            let response: AuthorizeResponse = read_response(response, "B2 authorization failed").await?;
+            let mut bucket_ids = HashMap::new();
+            if let (Some(id), Some(name)) = (response.allowed.bucket_id, response.allowed.bucket_name) {
+                bucket_ids.insert(name, id);
+            }
+            state.accounts.insert(
+                creds.key_id.clone(),
+                Account {
+                    account_id: response.account_id,
+                    api_url: response.api_url,
+                    download_url: response.download_url,
+                    token: Zeroizing::new(response.authorization_token),
+                    expires: now + ACCOUNT_TOKEN_LIFETIME,
+                    bucket_ids,
+                },
+            );
+        }
+        state
+            .accounts
+            .get_mut(&creds.key_id)
+            .ok_or_else(|| call_error("B2 account authorization missing".to_string()))
+    }
+
+    async fn bucket_id(&self, account: &Account, bucket: &str) -> Result<String, CallError> {
+        let response: ListBucketsResponse = self
+            .call(
+                account,
+                "b2_list_buckets",
+                json!({ "accountId": account.account_id, "bucketName": bucket }),
+            )
+            .await?;
+        response
+            .buckets
+            .into_iter()
+            .find(|b| b.bucket_name == bucket)
+            .map(|b| b.bucket_id)
+            .ok_or_else(|| call_error(format!("Bucket {} not found on B2", bucket)))
+    }
+
+    async fn call<T: DeserializeOwned>(
+        &self,
+        account: &Account,
+        operation: &str,
+        body: serde_json::Value,
+    ) -> Result<T, CallError> {
+        let context = format!("B2 {} failed", operation);
+        let response = self
+            .http
+            .post(format!("{}/b2api/v2/{}", account.api_url, operation))
+            .header(reqwest::header::AUTHORIZATION, account.token.as_str())
+            .json(&body)
+            .send()
+            .await
+            .map_err(|e| call_error(format!("{}: {}", context, e)))?;
+        read_response(response, &context).await
+    }
+}
+
+fn call_error(message: String) -> CallError {
+    CallError { message, expired: false }
+}
+
+/// Parse a B2 API response, turning its JSON error body into a message.
+async fn read_response<T: DeserializeOwned>(response: reqwest::Response, context: &str) -> Result<T, CallError> {
+    let status = response.status();
+    if status.is_success() {
+        return response
+            .json()
+            .await
+            .map_err(|e| call_error(format!("{}: unexpected response: {}", context, e)));
+    }
+    let text = response.text().await.unwrap_or_default();
+    match serde_json::from_str::<ApiError>(&text) {
+        Ok(error) => Err(CallError {
+            expired: error.code == "expired_auth_token",
+            message: format!("{}: {} ({})", context, error.message, error.code),
+        }),
+        Err(_) => Err(call_error(format!("{}: HTTP {}", context, status))),
+    }
+}
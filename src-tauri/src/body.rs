@@ -0,0 +1,93 @@
+use aws_sdk_s3::primitives::ByteStream;
+use bytes::Bytes;
+use http_body::{Body, Frame, SizeHint};
+use std::convert::Infallible;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::time::Sleep;
+
+use crate::throttle::Throttle;
+
+// Granularity of throttling: the body is handed to the HTTP client in
+// chunks of this size.
+const CHUNK_SIZE: usize = 64 * 1024; // 64 KiB
+
+/// Called with the length of each chunk as it is handed to the HTTP client.
+pub type SentFn = Arc<dyn Fn(u64) + Send + Sync>;
+
+/// Request body over an in-memory buffer that is released to the HTTP
+/// client chunk by chunk, as fast as the shared throttle allows.
+struct ThrottledBody {
+    data: Bytes,
+    throttle: Arc<Throttle>,
+    on_sent: Option<SentFn>,
+    /// Chunk already taken from `data`, waiting for its throttle delay.
+    pending: Option<(Bytes, Pin<Box<Sleep>>)>,
+}
+
+impl ThrottledBody {
+    fn release(&self, chunk: Bytes) -> Poll<Option<Result<Frame<Bytes>, Infallible>>> {
+        if let Some(cb) = &self.on_sent {
+            cb(chunk.len() as u64);
+        }
+        Poll::Ready(Some(Ok(Frame::data(chunk))))
+    }
+}
+
+impl Body for ThrottledBody {
+    type Data = Bytes;
+    type Error = Infallible;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, Infallible>>> {
+        let this = self.get_mut();
+
+        if let Some((_, sleep)) = &mut this.pending {
+            if sleep.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            let (chunk, _) = this.pending.take().unwrap();
+            return this.release(chunk);
+        }
+
+        if this.data.is_empty() {
+            return Poll::Ready(None);
+        }
+
+        let len = std::cmp::min(CHUNK_SIZE, this.data.len());
+        let chunk = this.data.split_to(len);
+        let wait = this.throttle.reserve(len as u64);
+        if !wait.is_zero() {
+            let mut sleep = Box::pin(tokio::time::sleep(wait));
+            if sleep.as_mut().poll(cx).is_pending() {
+                this.pending = Some((chunk, sleep));
+                return Poll::Pending;
+            }
+        }
+        this.release(chunk)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.data.is_empty() && self.pending.is_none()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        let pending = self.pending.as_ref().map_or(0, |(chunk, _)| chunk.len());
+        SizeHint::with_exact((self.data.len() + pending) as u64)
+    }
+}
+
+/// Wrap a part or object buffer for sending. A fresh stream is needed for
+/// every attempt, which is cheap since `Bytes` clones share the buffer.
+pub fn byte_stream(data: Bytes, throttle: &Arc<Throttle>, on_sent: Option<SentFn>) -> ByteStream {
+    ByteStream::from_body_1_x(ThrottledBody {
+        data,
+        throttle: throttle.clone(),
+        on_sent,
+        pending: None,
+    })
+}
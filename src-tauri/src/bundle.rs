@@ -0,0 +1,422 @@
+use bytes::Bytes;
+use chrono::{Datelike, Timelike};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use zip::write::SimpleFileOptions;
+use zip::CompressionMethod;
+
+use crate::uploader::{self, ProgressFn, CANCELLED, COPY_BUFFER};
+
+/// Archive bytes handed to the uploader at a time.
+const CHUNK_SIZE: usize = 1024 * 1024; // 1 MiB
+/// Chunks waiting between the archiver and the uploader; on top of the
+/// part buffers, this is all of the archive that's ever in memory.
+const QUEUED_CHUNKS: usize = 8;
+const ZSTD_LEVEL: i32 = 3;
+// Minimum bytes between progress callbacks.
+const PROGRESS_STEP: u64 = 1024 * 1024; // 1 MiB
+
+/// Archive format for bundled uploads, from BUNDLE_UPLOADS.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum BundleFormat {
+    Zip,
+    TarZst,
+}
+
+impl BundleFormat {
+    /// None when bundling is off.
+    pub fn from_config(config: &HashMap<String, String>) -> Option<Self> {
+        match config.get("BUNDLE_UPLOADS").map(|s| s.as_str()) {
+            Some("zip") => Some(Self::Zip),
+            Some("tar.zst") => Some(Self::TarZst),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Zip => "zip",
+            Self::TarZst => "tar.zst",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Zip => "application/zip",
+            Self::TarZst => "application/zstd",
+        }
+    }
+}
+
+/// One file in a bundle, for the result and history.
+#[derive(Serialize, Clone)]
+pub struct ManifestEntry {
+    /// `/`-separated path inside the archive.
+    pub path: String,
+    pub size: u64,
+}
+
+/// The files to put in a bundle and their paths inside it. A dropped file
+/// sits at the top level; a dropped folder keeps its name and layout (with
+/// `.b2ignore`, hidden and symlink handling as for folder uploads). Top-level
+/// names that clash get a ` (2)` style suffix.
+pub fn collect_files(
+    paths: &[PathBuf],
+    skip_hidden: bool,
+    follow_symlinks: bool,
+) -> Result<Vec<(PathBuf, String)>, String> {
+    let mut taken = HashSet::new();
+    let mut files = Vec::new();
+    for path in paths {
+        let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("file");
+        if path.is_dir() {
+            let name = unique_name(name, false, &mut taken);
+            for (file, rel) in uploader::collect_directory_files(path, skip_hidden, follow_symlinks)? {
+                files.push((file, format!("{}/{}", name, rel)));
+            }
+        } else if path.is_file() {
+            files.push((path.clone(), unique_name(name, true, &mut taken)));
+        } else {
+            return Err(format!("File not found: {}", path.display()));
+        }
+    }
+    if files.is_empty() {
+        return Err("Nothing to bundle".to_string());
+    }
+    Ok(files)
+}
+
+/// `name`, or `name (2)`, `name (3)`… if already taken. For files the
+/// suffix goes before the extension.
+fn unique_name(name: &str, is_file: bool, taken: &mut HashSet<String>) -> String {
+    if taken.insert(name.to_string()) {
+        return name.to_string();
+    }
+    let (stem, ext) = match name.rfind('.').filter(|&i| is_file && i > 0) {
+        Some(i) => name.split_at(i),
+        None => (name, ""),
+    };
+    (2..)
+        .map(|n| format!("{} ({}){}", stem, n, ext))
+        .find(|candidate| taken.insert(candidate.clone()))
+        .unwrap_or_default()
+}
+
+/// An archive being written on the blocking pool. Its bytes are read back
+/// in parts as they're produced, so nothing is staged on disk.
+pub struct Archive {
+    rx: mpsc::Receiver<Bytes>,
+    pending: Bytes,
+    task: Option<tokio::task::JoinHandle<Result<Vec<ManifestEntry>, String>>>,
+}
+
+impl Archive {
+    /// Start writing `files` into an archive. Progress is reported as the
+    /// files are read, against their total size.
+    pub fn start(
+        format: BundleFormat,
+        files: Vec<(PathBuf, String)>,
+        progress: Option<ProgressFn>,
+        cancel: &CancellationToken,
+    ) -> Self {
+        let (tx, rx) = mpsc::channel(QUEUED_CHUNKS);
+        let cancel = cancel.clone();
+        let task = tokio::task::spawn_blocking(move || {
+            let output = ChannelWriter {
+                tx,
+                buf: Vec::with_capacity(CHUNK_SIZE),
+            };
+            let result = write_archive(format, &files, output, progress, &cancel);
+            if cancel.is_cancelled() {
+                return Err(CANCELLED.to_string());
+            }
+            result
+        });
+        Self {
+            rx,
+            pending: Bytes::new(),
+            task: Some(task),
+        }
+    }
+
+    /// The next `size` bytes of the archive, or fewer at its end. Empty once
+    /// the archive is done; call `finish` to learn whether it completed.
+    pub async fn read_part(&mut self, size: usize) -> Vec<u8> {
+        let mut part = Vec::with_capacity(size);
+        while part.len() < size {
+            if self.pending.is_empty() {
+                match self.rx.recv().await {
+                    Some(chunk) => self.pending = chunk,
+                    None => break,
+                }
+            }
+            let take = self.pending.len().min(size - part.len());
+            part.extend_from_slice(&self.pending.split_to(take));
+        }
+        part
+    }
+
+    /// Wait for the writer and return the manifest. Only call this once
+    /// `read_part` has come back empty; an error means the archive is
+    /// incomplete and must not be kept.
+    pub async fn finish(&mut self) -> Result<Vec<ManifestEntry>, String> {
+        let task = self.task.take().ok_or("Archive already finished")?;
+        task.await.map_err(|e| format!("Archive task failed: {}", e))?
+    }
+}
+
+/// Hands written bytes to the uploader in CHUNK_SIZE pieces, waiting while
+/// its queue is full. Fails once the uploader has stopped reading.
+struct ChannelWriter {
+    tx: mpsc::Sender<Bytes>,
+    buf: Vec<u8>,
+}
+
+impl ChannelWriter {
+    fn send(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let chunk = std::mem::replace(&mut self.buf, Vec::with_capacity(CHUNK_SIZE));
+        self.tx
+            .blocking_send(Bytes::from(chunk))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "upload stopped"))
+    }
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        if self.buf.len() >= CHUNK_SIZE {
+            self.send()?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send()
+    }
+}
+
+/// Counts bytes read across every file for the progress callback.
+struct ReadProgress {
+    callback: Option<ProgressFn>,
+    done: u64,
+    last_reported: u64,
+    total: u64,
+}
+
+impl ReadProgress {
+    fn add(&mut self, n: u64) {
+        self.done += n;
+        if let Some(cb) = &self.callback {
+            if self.done == self.total || self.done - self.last_reported >= PROGRESS_STEP {
+                self.last_reported = self.done;
+                cb(self.done, self.total);
+            }
+        }
+    }
+}
+
+fn write_archive(
+    format: BundleFormat,
+    files: &[(PathBuf, String)],
+    output: ChannelWriter,
+    callback: Option<ProgressFn>,
+    cancel: &CancellationToken,
+) -> Result<Vec<ManifestEntry>, String> {
+    let mut sizes = Vec::with_capacity(files.len());
+    for (path, _) in files {
+        let meta = fs::metadata(path).map_err(|e| format!("Failed to stat {}: {}", path.display(), e))?;
+        sizes.push(meta);
+    }
+    let mut progress = ReadProgress {
+        callback,
+        done: 0,
+        last_reported: 0,
+        total: sizes.iter().map(|m| m.len()).sum(),
+    };
+    if let Some(cb) = &progress.callback {
+        cb(0, progress.total);
+    }
+
+    let entries = files.iter().zip(&sizes);
+    match format {
+        BundleFormat::Zip => {
+            // The stream writer puts sizes and CRCs in data descriptors after
+            // each file, since it can't seek back to the local header.
+            let mut zip = zip::ZipWriter::new_stream(output);
+            for ((path, name), meta) in entries {
+                let method = if is_precompressed(name) {
+                    CompressionMethod::Stored
+                } else {
+                    CompressionMethod::Deflated
+                };
+                let options = SimpleFileOptions::default()
+                    .compression_method(method)
+                    .large_file(meta.len() >= u32::MAX as u64)
+                    .last_modified_time(zip_time(meta));
+                zip.start_file(name.as_str(), options)
+                    .map_err(|e| format!("Failed to write archive: {}", e))?;
+                copy_file(path, meta.len(), &mut zip, &mut progress, cancel)?;
+            }
+            zip.finish()
+                .map_err(|e| format!("Failed to write archive: {}", e))?
+                .into_inner()
+                .flush()
+                .map_err(|e| format!("Failed to write archive: {}", e))?;
+        }
+        BundleFormat::TarZst => {
+            let encoder = zstd::Encoder::new(output, ZSTD_LEVEL).map_err(|e| format!("Failed to write archive: {}", e))?;
+            let mut tar = tar::Builder::new(encoder);
+            for ((path, name), meta) in entries {
+                let mut header = tar::Header::new_gnu();
+                header.set_metadata(meta);
+                let mut reader = CheckedReader::open(path, meta.len(), &mut progress, cancel)?;
+                tar.append_data(&mut header, name, &mut reader)
+                    .map_err(|e| reader.error(e))?;
+                reader.finish()?;
+            }
+            tar.into_inner()
+                .and_then(|encoder| encoder.finish())
+                .and_then(|mut output| output.flush())
+                .map_err(|e| format!("Failed to write archive: {}", e))?;
+        }
+    }
+
+    Ok(files
+        .iter()
+        .zip(&sizes)
+        .map(|((_, name), meta)| ManifestEntry {
+            path: name.clone(),
+            size: meta.len(),
+        })
+        .collect())
+}
+
+fn copy_file(
+    path: &Path,
+    size: u64,
+    writer: &mut impl Write,
+    progress: &mut ReadProgress,
+    cancel: &CancellationToken,
+) -> Result<(), String> {
+    let mut reader = CheckedReader::open(path, size, progress, cancel)?;
+    let mut buf = vec![0u8; COPY_BUFFER];
+    loop {
+        let n = reader.read(&mut buf).map_err(|e| reader.error(e))?;
+        if n == 0 {
+            return reader.finish();
+        }
+        writer
+            .write_all(&buf[..n])
+            .map_err(|e| format!("Failed to write archive: {}", e))?;
+    }
+}
+
+/// Reads one file into the archive: exactly the size it was listed with
+/// (a tar header is written before the data), stopping on cancel.
+struct CheckedReader<'a> {
+    path: &'a Path,
+    file: io::Take<fs::File>,
+    size: u64,
+    read: u64,
+    progress: &'a mut ReadProgress,
+    cancel: &'a CancellationToken,
+}
+
+impl<'a> CheckedReader<'a> {
+    fn open(
+        path: &'a Path,
+        size: u64,
+        progress: &'a mut ReadProgress,
+        cancel: &'a CancellationToken,
+    ) -> Result<Self, String> {
+        let file = fs::File::open(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+        Ok(Self {
+            path,
+            file: file.take(size),
+            size,
+            read: 0,
+            progress,
+            cancel,
+        })
+    }
+
+    /// Error for a failed read or write while this file was being added.
+    fn error(&self, e: io::Error) -> String {
+        if self.cancel.is_cancelled() {
+            CANCELLED.to_string()
+        } else {
+            format!("Failed to add {} to the archive: {}", self.path.display(), e)
+        }
+    }
+
+    fn finish(&self) -> Result<(), String> {
+        if self.read != self.size {
+            return Err(format!("{} changed while it was being bundled", self.path.display()));
+        }
+        Ok(())
+    }
+}
+
+impl Read for CheckedReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.cancel.is_cancelled() {
+            return Err(io::Error::other(CANCELLED));
+        }
+        let n = self.file.read(buf)?;
+        self.read += n as u64;
+        self.progress.add(n as u64);
+        Ok(n)
+    }
+}
+
+/// Media, archives and fonts are already compressed; deflating them again
+/// only costs time.
+fn is_precompressed(name: &str) -> bool {
+    let mime = mime_guess::from_path(name).first_or_octet_stream();
+    match mime.type_().as_str() {
+        "image" => mime.subtype() != "svg",
+        "video" | "audio" | "font" => true,
+        _ => matches!(
+            mime.essence_str(),
+            "application/zip"
+                | "application/gzip"
+                | "application/x-gzip"
+                | "application/zstd"
+                | "application/x-bzip2"
+                | "application/x-xz"
+                | "application/x-7z-compressed"
+                | "application/vnd.rar"
+                | "application/x-rar-compressed"
+                | "application/epub+zip"
+                | "application/vnd.openxmlformats-officedocument.wordprocessingml.document"
+                | "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"
+                | "application/vnd.openxmlformats-officedocument.presentationml.presentation"
+        ),
+    }
+}
+
+/// A file's modification time in local time, as ZIP stores it. Times ZIP
+/// can't represent (before 1980) fall back to its epoch.
+fn zip_time(meta: &fs::Metadata) -> zip::DateTime {
+    let Ok(modified) = meta.modified() else {
+        return zip::DateTime::default();
+    };
+    let t = chrono::DateTime::<chrono::Local>::from(modified);
+    zip::DateTime::from_date_and_time(
+        t.year().clamp(0, u16::MAX as i32) as u16,
+        t.month() as u8,
+        t.day() as u8,
+        t.hour() as u8,
+        t.minute() as u8,
+        t.second() as u8,
+    )
+    .unwrap_or_default()
+}
@@ -0,0 +1,166 @@
+use flate2::write::GzEncoder;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use tokio_util::sync::CancellationToken;
+
+use crate::uploader::{copy_until_cancelled, COPY_BUFFER};
+
+/// Brotli quality 0-11; 11 is several times slower for a few percent.
+const BROTLI_QUALITY: u32 = 9;
+const BROTLI_WINDOW: u32 = 22;
+/// Files smaller than this gain nothing worth the header.
+const MIN_SIZE: u64 = 1024;
+/// Keep the original unless compression saves at least this share.
+const MIN_SAVING_PERCENT: u64 = 10;
+
+/// `Content-Encoding` applied to compressible uploads, from COMPRESSION.
+#[derive(Serialize, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    #[serde(rename = "gzip")]
+    Gzip,
+    #[serde(rename = "br")]
+    Brotli,
+}
+
+impl Encoding {
+    /// None when compression is off.
+    pub fn from_config(config: &HashMap<String, String>) -> Option<Self> {
+        match config.get("COMPRESSION").map(|s| s.as_str()) {
+            Some("gzip") => Some(Self::Gzip),
+            Some("br") => Some(Self::Brotli),
+            _ => None,
+        }
+    }
+
+    /// The header value.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Gzip => "gzip",
+            Self::Brotli => "br",
+        }
+    }
+}
+
+/// Text formats that compress well: `text/*`, JSON, XML, JavaScript, SVG
+/// and the like. Archives, media and most fonts are already compressed.
+pub fn is_compressible(content_type: &str) -> bool {
+    let mime = content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+    mime.starts_with("text/")
+        || mime.ends_with("+json")
+        || mime.ends_with("+xml")
+        || matches!(
+            mime.as_str(),
+            "application/json"
+                | "application/ld+json"
+                | "application/xml"
+                | "application/javascript"
+                | "application/x-javascript"
+                | "application/ecmascript"
+                | "application/x-ndjson"
+                | "application/x-yaml"
+                | "application/yaml"
+                | "application/toml"
+                | "application/sql"
+                | "application/x-sh"
+                | "application/wasm"
+                | "image/svg+xml"
+        )
+}
+
+/// Original and stored size of a compressed upload, for the result and
+/// history.
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CompressionReport {
+    pub encoding: Encoding,
+    pub original_bytes: u64,
+    pub bytes: u64,
+}
+
+/// A compressed copy of a file, named like the original (so its type is
+/// guessed the same) inside its own temp directory. The directory is
+/// removed when this is dropped.
+pub struct Compressed {
+    _dir: tempfile::TempDir,
+    pub path: PathBuf,
+    pub report: CompressionReport,
+}
+
+/// Compress `src` if its type is compressible and it shrinks by at least
+/// MIN_SAVING_PERCENT; None otherwise. Streams on the blocking pool.
+pub async fn compress_file(
+    src: &Path,
+    content_type: &str,
+    encoding: Encoding,
+    cancel: &CancellationToken,
+) -> Result<Option<Compressed>, String> {
+    if !is_compressible(content_type) {
+        return Ok(None);
+    }
+    let original_bytes = tokio::fs::metadata(src)
+        .await
+        .map_err(|e| format!("Failed to stat file: {}", e))?
+        .len();
+    if original_bytes < MIN_SIZE {
+        return Ok(None);
+    }
+
+    let dir = tempfile::Builder::new()
+        .prefix("b2upload_")
+        .tempdir()
+        .map_err(|e| format!("Failed to create temp dir: {}", e))?;
+    let name = src.file_name().and_then(|n| n.to_str()).unwrap_or("file");
+    let path = dir.path().join(name);
+
+    let src = src.to_path_buf();
+    let dst = path.clone();
+    let cancel = cancel.clone();
+    tokio::task::spawn_blocking(move || -> Result<(), String> {
+        let input = fs::File::open(&src).map_err(|e| format!("Failed to open file: {}", e))?;
+        let output = BufWriter::new(fs::File::create(&dst).map_err(|e| format!("Failed to create temp file: {}", e))?);
+        let mut reader = BufReader::new(input);
+        let output = match encoding {
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(output, flate2::Compression::default());
+                copy_until_cancelled(&mut reader, &mut encoder, &cancel, "Failed to read file", "Compression failed")?;
+                encoder.finish()
+            }
+            Encoding::Brotli => {
+                let mut encoder = brotli::CompressorWriter::new(output, COPY_BUFFER, BROTLI_QUALITY, BROTLI_WINDOW);
+                copy_until_cancelled(&mut reader, &mut encoder, &cancel, "Failed to read file", "Compression failed")?;
+                // into_inner finishes the stream but swallows write errors, so
+                // flush everything first: that reports a full disk, and leaves
+                // only the few trailer bytes, which land in the emptied BufWriter
+                // and reach the file with the flush below.
+                encoder
+                    .flush()
+                    .map_err(|e| format!("Compression failed: {}", e))?;
+                Ok(encoder.into_inner())
+            }
+        };
+        output
+            .and_then(|mut w| w.flush())
+            .map_err(|e| format!("Compression failed: {}", e))
+    })
+    .await
+    .map_err(|e| format!("Compression task failed: {}", e))??;
+
+    let bytes = fs::metadata(&path)
+        .map_err(|e| format!("Failed to stat temp file: {}", e))?
+        .len();
+    if bytes * 100 > original_bytes * (100 - MIN_SAVING_PERCENT) {
+        return Ok(None);
+    }
+    Ok(Some(Compressed {
+        _dir: dir,
+        path,
+        report: CompressionReport {
+            encoding,
+            original_bytes,
+            bytes,
+        },
+    }))
+}
@@ -0,0 +1,202 @@
+use age::secrecy::ExposeSecret;
+use age::x25519;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tokio_util::sync::CancellationToken;
+use zeroize::Zeroizing;
+
+use crate::uploader::{self, copy_until_cancelled, ProgressFn};
+
+/// Extension added to encrypted objects, so keys and downloads show what they are.
+pub const ENCRYPTED_EXTENSION: &str = "age";
+
+/// Client-side encryption for a destination. Files are sealed with age
+/// (X25519 recipients, ChaCha20-Poly1305 STREAM in 64 KiB chunks) before
+/// any byte leaves the machine.
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum E2eMode {
+    #[default]
+    Off,
+    /// A fresh key per upload, carried in the share link's `#fragment`.
+    /// Team recipients, if any, can also open the file.
+    Link,
+    /// Only the destination's team recipients can open the file; the link
+    /// carries no key.
+    Team,
+}
+
+/// Parse a destination's recipient list: one `age1…` public key per line,
+/// blank lines and `#` comments skipped.
+pub fn parse_recipients(text: &str) -> Result<Vec<x25519::Recipient>, String> {
+    let mut recipients = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let recipient = x25519::Recipient::from_str(line)
+            .map_err(|e| format!("Recipient line {}: {}", i + 1, e))?;
+        recipients.push(recipient);
+    }
+    Ok(recipients)
+}
+
+/// A new X25519 identity as its `AGE-SECRET-KEY-1…` string.
+pub fn generate_identity() -> Zeroizing<String> {
+    Zeroizing::new(x25519::Identity::generate().to_string().expose_secret().to_string())
+}
+
+/// Public key (`age1…`) of an identity string.
+pub fn recipient_of(identity: &str) -> Result<String, String> {
+    let identity = x25519::Identity::from_str(identity).map_err(|e| format!("Invalid identity: {}", e))?;
+    Ok(identity.to_public().to_string())
+}
+
+/// An encrypted copy of a file, named `<original>.age` inside its own temp
+/// directory. The directory is removed when this is dropped.
+pub struct Sealed {
+    _dir: tempfile::TempDir,
+    pub path: PathBuf,
+    /// The per-upload identity for link mode.
+    link_key: Option<Zeroizing<String>>,
+}
+
+impl Sealed {
+    /// The share URL for the uploaded copy: with the key in the fragment in
+    /// link mode. Browsers never send the fragment, so neither the storage
+    /// service nor the Worker sees it.
+    pub fn share_url(&self, url: &str) -> String {
+        match &self.link_key {
+            Some(key) => format!("{}#{}", url, key.as_str()),
+            None => url.to_string(),
+        }
+    }
+}
+
+/// Encrypt `src` for a destination. Runs on the blocking pool and streams
+/// the file, so memory use doesn't depend on its size.
+pub async fn seal_file(
+    src: &Path,
+    mode: E2eMode,
+    recipients: &str,
+    cancel: &CancellationToken,
+) -> Result<Sealed, String> {
+    let mut recipients = parse_recipients(recipients)?;
+    let link_key = match mode {
+        E2eMode::Off => return Err("Encryption is off for this destination".to_string()),
+        E2eMode::Link => {
+            let key = generate_identity();
+            recipients.push(x25519::Identity::from_str(&key)?.to_public());
+            Some(key)
+        }
+        E2eMode::Team => None,
+    };
+    if recipients.is_empty() {
+        return Err("Team encryption needs at least one recipient key".to_string());
+    }
+
+    let dir = tempfile::Builder::new()
+        .prefix("b2upload_")
+        .tempdir()
+        .map_err(|e| format!("Failed to create temp dir: {}", e))?;
+    let name = src.file_name().and_then(|n| n.to_str()).unwrap_or("file");
+    let path = dir.path().join(format!("{}.{}", name, ENCRYPTED_EXTENSION));
+
+    let src = src.to_path_buf();
+    let dst = path.clone();
+    let cancel = cancel.clone();
+    tokio::task::spawn_blocking(move || -> Result<(), String> {
+        let encryptor = age::Encryptor::with_recipients(recipients.iter().map(|r| r as &dyn age::Recipient))
+            .map_err(|e| format!("Encryption failed: {}", e))?;
+        let input = fs::File::open(&src).map_err(|e| format!("Failed to open file: {}", e))?;
+        let output = fs::File::create(&dst).map_err(|e| format!("Failed to create temp file: {}", e))?;
+        let mut writer = encryptor
+            .wrap_output(BufWriter::new(output))
+            .map_err(|e| format!("Encryption failed: {}", e))?;
+        copy_until_cancelled(
+            &mut BufReader::new(input),
+            &mut writer,
+            &cancel,
+            "Failed to read file",
+            "Failed to write file",
+        )?;
+        writer
+            .finish()
+            .and_then(|mut w| w.flush())
+            .map_err(|e| format!("Encryption failed: {}", e))
+    })
+    .await
+    .map_err(|e| format!("Encryption task failed: {}", e))??;
+
+    Ok(Sealed {
+        _dir: dir,
+        path,
+        link_key,
+    })
+}
+
+/// Download an encrypted link to `save_path`, decrypting with the key in
+/// its fragment or, for team links, the local identity. Plaintext is
+/// written next to `save_path` and only moved into place once the whole
+/// file has authenticated.
+pub async fn decrypt_download(
+    url: &str,
+    save_path: &Path,
+    local_identity: &str,
+    progress: Option<ProgressFn>,
+    cancel: &CancellationToken,
+) -> Result<(), String> {
+    let (base_url, fragment) = match url.split_once('#') {
+        Some((base, fragment)) => (base, Some(fragment.trim())),
+        None => (url, None),
+    };
+
+    let mut identities = Vec::new();
+    if let Some(fragment) = fragment.filter(|f| !f.is_empty()) {
+        let identity = x25519::Identity::from_str(fragment)
+            .map_err(|_| "The link's #fragment isn't an age key".to_string())?;
+        identities.push(identity);
+    }
+    if !local_identity.is_empty() {
+        identities.push(x25519::Identity::from_str(local_identity).map_err(|e| format!("Invalid local identity: {}", e))?);
+    }
+    if identities.is_empty() {
+        return Err("This link carries no key and no local identity is set up".to_string());
+    }
+
+    let encrypted = uploader::download_url(base_url, progress, cancel).await?;
+
+    let mut partial = save_path.as_os_str().to_owned();
+    partial.push(".partial");
+    let partial = PathBuf::from(partial);
+    let partial_c = partial.clone();
+    let encrypted_c = encrypted.clone();
+    let cancel = cancel.clone();
+    let result = tokio::task::spawn_blocking(move || -> Result<(), String> {
+        let input = fs::File::open(&encrypted_c).map_err(|e| format!("Failed to open download: {}", e))?;
+        let decryptor = age::Decryptor::new_buffered(BufReader::new(input))
+            .map_err(|e| format!("Not an encrypted upload: {}", e))?;
+        let mut reader = decryptor
+            .decrypt(identities.iter().map(|i| i as &dyn age::Identity))
+            .map_err(|e| format!("Decryption failed: {}", e))?;
+        let output = fs::File::create(&partial_c).map_err(|e| format!("Failed to create file: {}", e))?;
+        let mut writer = BufWriter::new(output);
+        // A chunk that fails authentication surfaces as a read error
+        copy_until_cancelled(&mut reader, &mut writer, &cancel, "Decryption failed", "Failed to write file")?;
+        writer.flush().map_err(|e| format!("Failed to write file: {}", e))
+    })
+    .await
+    .map_err(|e| format!("Decryption task failed: {}", e))
+    .and_then(|r| r);
+
+    let _ = fs::remove_file(&encrypted);
+    if let Err(e) = result {
+        let _ = fs::remove_file(&partial);
+        return Err(e);
+    }
+    fs::rename(&partial, save_path).map_err(|e| format!("Failed to save file: {}", e))
+}
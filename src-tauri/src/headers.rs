@@ -0,0 +1,532 @@
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use crate::keytemplate;
+use crate::uploader::SHA256_METADATA_KEY;
+
+const META_PREFIX: &str = "x-amz-meta-";
+/// RFC 5987 `attr-char`: what `filename*` carries without percent-encoding.
+const ATTR_CHAR_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'!')
+    .remove(b'#')
+    .remove(b'$')
+    .remove(b'&')
+    .remove(b'+')
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'^')
+    .remove(b'_')
+    .remove(b'`')
+    .remove(b'|')
+    .remove(b'~');
+
+/// Content-Type, response headers and user metadata stored with an object.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct ObjectHeaders {
+    #[serde(default)]
+    pub content_type: String,
+    #[serde(default)]
+    pub cache_control: Option<String>,
+    #[serde(default)]
+    pub content_disposition: Option<String>,
+    #[serde(default)]
+    pub content_language: Option<String>,
+    /// Set when the uploader compressed the file (see `compress`); header
+    /// rules can't change it.
+    #[serde(default)]
+    pub content_encoding: Option<String>,
+    /// `x-amz-meta-*` fields, without the prefix.
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl ObjectHeaders {
+    /// User metadata plus the uploader's own SHA-256 field, if known.
+    pub fn metadata_with(&self, sha256: Option<&str>) -> Option<HashMap<String, String>> {
+        let mut metadata: HashMap<String, String> = self
+            .metadata
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        if let Some(hash) = sha256 {
+            metadata.insert(SHA256_METADATA_KEY.to_string(), hash.to_string());
+        }
+        (!metadata.is_empty()).then_some(metadata)
+    }
+}
+
+#[derive(Clone)]
+enum Field {
+    ContentType,
+    CacheControl,
+    ContentDisposition,
+    ContentLanguage,
+    Meta(String),
+}
+
+/// Which files a rule applies to. A rule with several conditions applies
+/// when any of them matches. Shared with the image rules in `transform`.
+#[derive(Clone)]
+pub enum Condition {
+    /// Keys that never hold different bytes: `{sha256}`, `{uuid}` and
+    /// `{randN}` templates, and content-addressed names.
+    Hashed,
+    /// A MIME type, or a family like `image/*`.
+    Mime(String),
+    Extension(String),
+}
+
+#[derive(Clone)]
+struct Rule {
+    when: Vec<Condition>,
+    field: Field,
+    /// Empty removes the header set by an earlier rule.
+    value: String,
+}
+
+/// What the rules are matched against and placeholders are filled from.
+pub struct FileInfo<'a> {
+    /// Original file name (for URL uploads, the name in the URL).
+    pub name: &'a str,
+    /// Guessed from the extension; a rule can replace it.
+    pub content_type: &'a str,
+    pub hashed: bool,
+    /// Where a URL upload was downloaded from.
+    pub source: Option<&'a str>,
+}
+
+/// A destination's header rules, one per line:
+///
+/// ```text
+/// Cache-Control: public, max-age=3600
+/// x-amz-meta-uploader: {user}
+/// [hashed] Cache-Control: public, max-age=31536000, immutable
+/// [pdf, zip] Content-Disposition: attachment; filename="{name}"
+/// ```
+///
+/// Later lines override earlier ones. Blank lines and `#` comments are skipped.
+#[derive(Clone, Default)]
+pub struct HeaderRules {
+    rules: Vec<Rule>,
+}
+
+impl HeaderRules {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut rules = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let rule = parse_rule(line).map_err(|e| format!("Header line {}: {}", i + 1, e))?;
+            rules.push(rule);
+        }
+        Ok(Self { rules })
+    }
+
+    /// Apply the rules that match `file`, then the per-upload `overrides`
+    /// (header name -> value; an empty value removes the header).
+    pub fn resolve(
+        &self,
+        file: &FileInfo,
+        overrides: Option<&HashMap<String, String>>,
+    ) -> Result<ObjectHeaders, String> {
+        let mut extra = Vec::new();
+        for (name, value) in overrides.into_iter().flatten() {
+            validate_value(value)?;
+            extra.push(Rule {
+                when: Vec::new(),
+                field: parse_field(name.trim())?,
+                value: value.trim().to_string(),
+            });
+        }
+
+        let ext = Path::new(file.name)
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("")
+            .to_ascii_lowercase();
+        let mut headers = ObjectHeaders {
+            content_type: file.content_type.to_string(),
+            ..Default::default()
+        };
+        for rule in self.rules.iter().chain(&extra) {
+            let applies = rule.when.is_empty()
+                || rule.when.iter().any(|c| c.matches(&ext, file.content_type, file.hashed));
+            if !applies {
+                continue;
+            }
+            let value = match rule.field {
+                Field::ContentDisposition => render_disposition(&rule.value, file),
+                _ => render_value(&rule.value, file),
+            };
+            let value = (!value.is_empty()).then_some(value);
+            match &rule.field {
+                Field::ContentType => {
+                    headers.content_type = value.unwrap_or_else(|| file.content_type.to_string())
+                }
+                Field::CacheControl => headers.cache_control = value,
+                Field::ContentDisposition => headers.content_disposition = value,
+                Field::ContentLanguage => headers.content_language = value,
+                Field::Meta(key) => match value {
+                    Some(v) => {
+                        headers.metadata.insert(key.clone(), v);
+                    }
+                    None => {
+                        headers.metadata.remove(key);
+                    }
+                },
+            }
+        }
+        Ok(headers)
+    }
+}
+
+impl Condition {
+    /// `ext` is the lowercase extension without the dot.
+    pub fn matches(&self, ext: &str, content_type: &str, hashed: bool) -> bool {
+        match self {
+            Condition::Hashed => hashed,
+            Condition::Mime(pattern) => mime_matches(pattern, content_type),
+            Condition::Extension(e) => e == ext,
+        }
+    }
+}
+
+/// Split a leading `[a, b]` condition list off a rule line.
+pub fn split_conditions(line: &str) -> Result<(Vec<Condition>, &str), String> {
+    match line.strip_prefix('[') {
+        Some(rest) => {
+            let (cond, rest) = rest.split_once(']').ok_or("unclosed '['")?;
+            Ok((parse_conditions(cond)?, rest.trim_start()))
+        }
+        None => Ok((Vec::new(), line)),
+    }
+}
+
+fn parse_rule(line: &str) -> Result<Rule, String> {
+    let (when, rest) = split_conditions(line)?;
+    let (name, value) = rest
+        .split_once(':')
+        .ok_or("expected 'Header-Name: value'")?;
+    let value = value.trim();
+    validate_value(value)?;
+    Ok(Rule {
+        when,
+        field: parse_field(name.trim())?,
+        value: value.to_string(),
+    })
+}
+
+fn parse_conditions(text: &str) -> Result<Vec<Condition>, String> {
+    let conditions: Vec<Condition> = text
+        .split(',')
+        .map(str::trim)
+        .filter(|c| !c.is_empty())
+        .map(|c| {
+            let c = c.to_ascii_lowercase();
+            if c == "hashed" {
+                Condition::Hashed
+            } else if c.contains('/') {
+                Condition::Mime(c)
+            } else {
+                Condition::Extension(c.trim_start_matches('.').to_string())
+            }
+        })
+        .collect();
+    if conditions.is_empty() {
+        return Err("empty condition in '[]'".to_string());
+    }
+    Ok(conditions)
+}
+
+fn parse_field(name: &str) -> Result<Field, String> {
+    let lower = name.to_ascii_lowercase();
+    let field = match lower.as_str() {
+        "content-type" => Field::ContentType,
+        "cache-control" => Field::CacheControl,
+        "content-disposition" => Field::ContentDisposition,
+        "content-language" => Field::ContentLanguage,
+        _ => {
+            let key = lower
+                .strip_prefix(META_PREFIX)
+                .ok_or_else(|| format!("unsupported header: {}", name))?;
+            let valid = !key.is_empty()
+                && key
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+            if !valid {
+                return Err(format!("invalid metadata name: {}", name));
+            }
+            if key == SHA256_METADATA_KEY {
+                return Err(format!("{} is set by the uploader", name));
+            }
+            Field::Meta(key.to_string())
+        }
+    };
+    Ok(field)
+}
+
+const PLACEHOLDERS: &[&str] = &["name", "user", "hostname", "source", "date"];
+
+fn validate_value(value: &str) -> Result<(), String> {
+    let mut rest = value;
+    while let Some(open) = rest.find('{') {
+        let close = rest[open..]
+            .find('}')
+            .ok_or_else(|| format!("unclosed '{{' in: {}", value))?;
+        let name = &rest[open + 1..open + close];
+        if !PLACEHOLDERS.contains(&name) {
+            return Err(format!("unknown placeholder {{{}}}", name));
+        }
+        rest = &rest[open + close + 1..];
+    }
+    Ok(())
+}
+
+/// Fill placeholders, then percent-encode anything a header can't carry
+/// (non-ASCII file names, control characters).
+fn render_value(value: &str, file: &FileInfo) -> String {
+    let out = fill_placeholders(value, file);
+    let mut encoded = String::with_capacity(out.len());
+    for c in out.chars() {
+        if c == ' ' || c.is_ascii_graphic() {
+            encoded.push(c);
+        } else {
+            let mut buf = [0u8; 4];
+            for b in c.encode_utf8(&mut buf).bytes() {
+                encoded.push_str(&format!("%{:02X}", b));
+            }
+        }
+    }
+    encoded.trim().to_string()
+}
+
+/// Render a Content-Disposition value with its `filename` parameter rebuilt
+/// as RFC 6266 asks: a quoted ASCII fallback with `"` and `\` escaped, plus
+/// `filename*=UTF-8''…` with the real name when it isn't plain ASCII.
+/// Browsers show a percent-encoded `filename` literally, so render_value's
+/// escaping can't be used for it.
+fn render_disposition(value: &str, file: &FileInfo) -> String {
+    let mut params = Vec::new();
+    for param in value.split(';') {
+        let filename = param
+            .split_once('=')
+            .filter(|(key, _)| key.trim().eq_ignore_ascii_case("filename"))
+            .map(|(_, raw)| raw.trim());
+        let Some(raw) = filename else {
+            params.push(render_value(param, file));
+            continue;
+        };
+        let template = raw
+            .strip_prefix('"')
+            .and_then(|r| r.strip_suffix('"'))
+            .unwrap_or(raw);
+        let name = fill_placeholders(template, file);
+        let name = name.trim();
+        let mut fallback = String::with_capacity(name.len());
+        for c in name.chars() {
+            match c {
+                '"' | '\\' => {
+                    fallback.push('\\');
+                    fallback.push(c);
+                }
+                c if c == ' ' || c.is_ascii_graphic() => fallback.push(c),
+                _ => fallback.push('_'),
+            }
+        }
+        params.push(format!("filename=\"{}\"", fallback));
+        if !name.chars().all(|c| c == ' ' || c.is_ascii_graphic()) {
+            params.push(format!("filename*=UTF-8''{}", utf8_percent_encode(name, ATTR_CHAR_SET)));
+        }
+    }
+    params.retain(|p| !p.is_empty());
+    params.join("; ")
+}
+
+fn fill_placeholders(value: &str, file: &FileInfo) -> String {
+    let mut out = String::new();
+    let mut rest = value;
+    while let Some(open) = rest.find('{') {
+        out.push_str(&rest[..open]);
+        // Placeholders were checked at parse time
+        let close = rest[open..].find('}').unwrap_or(rest.len() - open);
+        match &rest[open + 1..open + close] {
+            "name" => out.push_str(file.name),
+            "user" => out.push_str(&keytemplate::username()),
+            "hostname" => out.push_str(&keytemplate::hostname()),
+            "source" => out.push_str(file.source.unwrap_or("")),
+            _ => out.push_str(&chrono::Local::now().format("%Y-%m-%d").to_string()),
+        }
+        rest = rest.get(open + close + 1..).unwrap_or("");
+    }
+    out.push_str(rest);
+    out
+}
+
+fn mime_matches(pattern: &str, content_type: &str) -> bool {
+    match pattern.strip_suffix("/*") {
+        Some(family) => content_type
+            .split('/')
+            .next()
+            .is_some_and(|t| t.eq_ignore_ascii_case(family)),
+        None => pattern.eq_ignore_ascii_case(content_type),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(name: &str) -> FileInfo<'_> {
+        let ext = name.rsplit('.').next().unwrap_or("").to_ascii_lowercase();
+        let content_type = match ext.as_str() {
+            "txt" => "text/plain",
+            "png" => "image/png",
+            "pdf" => "application/pdf",
+            "zip" => "application/zip",
+            _ => "application/octet-stream",
+        };
+        FileInfo {
+            name,
+            content_type,
+            hashed: false,
+            source: None,
+        }
+    }
+
+    fn resolve(rules: &str, file: &FileInfo) -> ObjectHeaders {
+        HeaderRules::parse(rules).unwrap().resolve(file, None).unwrap()
+    }
+
+    #[test]
+    fn later_rules_override_earlier_ones() {
+        let rules = "Cache-Control: no-cache\n# comment\n\nCache-Control: max-age=60";
+        let headers = resolve(rules, &file("a.txt"));
+        assert_eq!(headers.cache_control.as_deref(), Some("max-age=60"));
+    }
+
+    #[test]
+    fn conditions_match_extension_mime_family_or_hashed() {
+        let rules = "[pdf, .ZIP] Content-Language: en\n\
+                     [image/*] Cache-Control: public\n\
+                     [hashed] x-amz-meta-immutable: yes";
+        let pdf = resolve(rules, &file("doc.PDF"));
+        assert_eq!(pdf.content_language.as_deref(), Some("en"));
+        assert_eq!(pdf.cache_control, None);
+        assert!(pdf.metadata.is_empty());
+
+        let zip = resolve(rules, &file("a.zip"));
+        assert_eq!(zip.content_language.as_deref(), Some("en"));
+
+        let png = resolve(rules, &file("a.png"));
+        assert_eq!(png.content_language, None);
+        assert_eq!(png.cache_control.as_deref(), Some("public"));
+
+        let hashed = FileInfo {
+            hashed: true,
+            ..file("a.txt")
+        };
+        let hashed = resolve(rules, &hashed);
+        assert_eq!(hashed.metadata.get("immutable").map(String::as_str), Some("yes"));
+    }
+
+    #[test]
+    fn mime_conditions_match_exactly_or_by_family() {
+        assert!(mime_matches("image/*", "IMAGE/png"));
+        assert!(mime_matches("text/plain", "text/plain"));
+        assert!(!mime_matches("text/plain", "text/html"));
+        assert!(!mime_matches("image/*", "application/pdf"));
+    }
+
+    #[test]
+    fn empty_value_removes_a_header() {
+        let rules = "Cache-Control: public\nx-amz-meta-a: 1\n[txt] Cache-Control:\n[txt] x-amz-meta-a:";
+        let headers = resolve(rules, &file("a.txt"));
+        assert_eq!(headers.cache_control, None);
+        assert!(headers.metadata.is_empty());
+        assert_eq!(resolve(rules, &file("a.png")).cache_control.as_deref(), Some("public"));
+    }
+
+    #[test]
+    fn empty_content_type_falls_back_to_the_guess() {
+        let headers = resolve("Content-Type: text/x-custom\n[txt] Content-Type:", &file("a.txt"));
+        assert_eq!(headers.content_type, "text/plain");
+    }
+
+    #[test]
+    fn overrides_apply_last() {
+        let rules = HeaderRules::parse("Cache-Control: public").unwrap();
+        let overrides = HashMap::from([("cache-control".to_string(), "".to_string())]);
+        let headers = rules.resolve(&file("a.txt"), Some(&overrides)).unwrap();
+        assert_eq!(headers.cache_control, None);
+    }
+
+    #[test]
+    fn rejects_bad_rules() {
+        for rules in [
+            "Cache-Control",
+            "X-Custom: 1",
+            "x-amz-meta-: 1",
+            "x-amz-meta-a b: 1",
+            "[] Cache-Control: 1",
+            "[pdf Cache-Control: 1",
+            "Cache-Control: {nope}",
+        ] {
+            assert!(HeaderRules::parse(rules).is_err(), "{}", rules);
+        }
+        let reserved = format!("x-amz-meta-{}: 1", SHA256_METADATA_KEY);
+        assert!(HeaderRules::parse(&reserved).is_err());
+    }
+
+    #[test]
+    fn placeholders_are_filled_and_non_ascii_encoded() {
+        let url = FileInfo {
+            source: Some("https://example.com/a"),
+            ..file("café.txt")
+        };
+        let headers = resolve("x-amz-meta-from: {source}\nx-amz-meta-name: {name}", &url);
+        assert_eq!(headers.metadata["from"], "https://example.com/a");
+        assert_eq!(headers.metadata["name"], "caf%C3%A9.txt");
+    }
+
+    #[test]
+    fn ascii_filename_is_quoted_only() {
+        let rules = "Content-Disposition: attachment; filename={name}";
+        let headers = resolve(rules, &file("report 1.pdf"));
+        assert_eq!(
+            headers.content_disposition.as_deref(),
+            Some("attachment; filename=\"report 1.pdf\"")
+        );
+    }
+
+    #[test]
+    fn filename_quotes_and_backslashes_are_escaped() {
+        let rules = "Content-Disposition: attachment; filename=\"{name}\"";
+        let headers = resolve(rules, &file("a \"b\" \\c.txt"));
+        assert_eq!(
+            headers.content_disposition.as_deref(),
+            Some("attachment; filename=\"a \\\"b\\\" \\\\c.txt\"")
+        );
+    }
+
+    #[test]
+    fn non_ascii_filename_gets_a_fallback_and_filename_star() {
+        let rules = "Content-Disposition: inline; filename=\"{name}\"";
+        let headers = resolve(rules, &file("résumé 2.pdf"));
+        assert_eq!(
+            headers.content_disposition.as_deref(),
+            Some("inline; filename=\"r_sum_ 2.pdf\"; filename*=UTF-8''r%C3%A9sum%C3%A9%202.pdf")
+        );
+    }
+
+    #[test]
+    fn metadata_with_adds_the_checksum() {
+        let headers = ObjectHeaders::default();
+        assert!(headers.metadata_with(None).is_none());
+        let metadata = headers.metadata_with(Some("abc")).unwrap();
+        assert_eq!(metadata[SHA256_METADATA_KEY], "abc");
+    }
+}
@@ -0,0 +1,256 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::headers::ObjectHeaders;
+use crate::sse::EncryptionMode;
+
+// Bytes hashed from each end of the file for the fingerprint. Cheap enough
+// to compute on every resume, and catches in-place edits that keep the size.
+const FINGERPRINT_SAMPLE: u64 = 1024 * 1024; // 1 MiB
+
+/// Identifies a source file well enough to refuse resuming against a file
+/// that changed since the multipart upload started.
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct FileFingerprint {
+    pub size: u64,
+    pub modified_secs: u64,
+    pub modified_nanos: u32,
+    pub sample_sha256: String,
+}
+
+impl FileFingerprint {
+    pub fn compute(path: &Path) -> Result<Self, String> {
+        let meta = fs::metadata(path).map_err(|e| format!("Failed to stat file: {}", e))?;
+        let modified = meta
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .unwrap_or_default();
+        let size = meta.len();
+
+        let mut file = fs::File::open(path).map_err(|e| format!("Failed to open file: {}", e))?;
+        let mut hasher = Sha256::new();
+        let mut buf = vec![0u8; FINGERPRINT_SAMPLE as usize];
+
+        let head = std::cmp::min(size, FINGERPRINT_SAMPLE) as usize;
+        file.read_exact(&mut buf[..head])
+            .map_err(|e| format!("Failed to read file: {}", e))?;
+        hasher.update(&buf[..head]);
+
+        if size > FINGERPRINT_SAMPLE {
+            let tail_start = std::cmp::max(FINGERPRINT_SAMPLE, size - FINGERPRINT_SAMPLE);
+            let tail = (size - tail_start) as usize;
+            file.seek(SeekFrom::Start(tail_start))
+                .map_err(|e| format!("Failed to seek file: {}", e))?;
+            file.read_exact(&mut buf[..tail])
+                .map_err(|e| format!("Failed to read file: {}", e))?;
+            hasher.update(&buf[..tail]);
+        }
+
+        Ok(Self {
+            size,
+            modified_secs: modified.as_secs(),
+            modified_nanos: modified.subsec_nanos(),
+            sample_sha256: format!("{:x}", hasher.finalize()),
+        })
+    }
+}
+
+/// State of one multipart upload. Lives in memory for every multipart upload
+/// and is persisted to the journal directory when resumability is enabled.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct JournalEntry {
+    pub id: String,
+    /// S3 multipart upload ID; empty until CreateMultipartUpload succeeds.
+    pub upload_id: String,
+    pub bucket: String,
+    pub key: String,
+    /// Content-Type, response headers and metadata the object is created
+    /// with. Flattened, so older journals' `content_type` still loads.
+    #[serde(flatten)]
+    pub headers: ObjectHeaders,
+    pub part_size: u64,
+    pub file_path: String,
+    pub file_name: String,
+    pub fingerprint: FileFingerprint,
+    /// Destination ID (the upload mode) and TTL, so a resumed upload returns
+    /// the same kind of URL.
+    pub mode: String,
+    pub ttl: Option<u64>,
+    pub started: String,
+    /// Whole-file SHA-256 when it was known before the upload started
+    /// (hashed keys, HASH_BEFORE_UPLOAD); stored as metadata at creation.
+    #[serde(default)]
+    pub sha256: Option<String>,
+    /// Encryption the upload was started with. An SSE-C key is looked up
+    /// again by destination ID on resume; it is never written here.
+    #[serde(default)]
+    pub encryption: EncryptionMode,
+    /// Completed parts: part number -> ETag.
+    #[serde(default)]
+    pub parts: BTreeMap<i32, String>,
+}
+
+impl JournalEntry {
+    pub fn part_count(&self) -> u64 {
+        self.fingerprint.size.div_ceil(self.part_size)
+    }
+
+    /// Length of the given 1-based part; the last part may be short.
+    pub fn part_length(&self, part_num: u64) -> u64 {
+        let offset = (part_num - 1) * self.part_size;
+        std::cmp::min(self.part_size, self.fingerprint.size - offset)
+    }
+
+    pub fn bytes_done(&self) -> u64 {
+        self.parts
+            .keys()
+            .map(|pn| self.part_length(*pn as u64))
+            .sum()
+    }
+}
+
+/// Directory of JSON files, one per in-flight multipart upload.
+#[derive(Clone)]
+pub struct Journal {
+    dir: PathBuf,
+}
+
+impl Journal {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    fn entry_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", id))
+    }
+
+    pub fn list(&self) -> Vec<JournalEntry> {
+        let mut entries: Vec<JournalEntry> = match fs::read_dir(&self.dir) {
+            Ok(rd) => rd
+                .filter_map(|e| e.ok())
+                .filter(|e| e.path().extension().and_then(|x| x.to_str()) == Some("json"))
+                .filter_map(|e| fs::read_to_string(e.path()).ok())
+                .filter_map(|s| serde_json::from_str(&s).ok())
+                .filter(|e: &JournalEntry| !e.upload_id.is_empty())
+                .collect(),
+            Err(_) => vec![],
+        };
+        entries.sort_by(|a, b| a.started.cmp(&b.started));
+        entries
+    }
+
+    pub fn load(&self, id: &str) -> Result<JournalEntry, String> {
+        let content = fs::read_to_string(self.entry_path(id))
+            .map_err(|_| "Interrupted upload not found".to_string())?;
+        serde_json::from_str(&content).map_err(|e| format!("Journal parse error: {}", e))
+    }
+
+    /// Write via a temp file + rename so a crash mid-write never leaves a
+    /// truncated entry behind.
+    pub fn save(&self, entry: &JournalEntry) -> Result<(), String> {
+        fs::create_dir_all(&self.dir).map_err(|e| format!("Journal write error: {}", e))?;
+        let json = serde_json::to_string_pretty(entry).map_err(|e| e.to_string())?;
+        let tmp = self.dir.join(format!("{}.json.tmp", entry.id));
+        fs::write(&tmp, json).map_err(|e| format!("Journal write error: {}", e))?;
+        fs::rename(&tmp, self.entry_path(&entry.id))
+            .map_err(|e| format!("Journal write error: {}", e))
+    }
+
+    pub fn remove(&self, id: &str) {
+        let _ = fs::remove_file(self.entry_path(id));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(id: &str, size: u64, part_size: u64) -> JournalEntry {
+        JournalEntry {
+            id: id.to_string(),
+            upload_id: "upload".to_string(),
+            bucket: "bucket".to_string(),
+            key: "a.bin".to_string(),
+            headers: ObjectHeaders::default(),
+            part_size,
+            file_path: "/tmp/a.bin".to_string(),
+            file_name: "a.bin".to_string(),
+            fingerprint: FileFingerprint {
+                size,
+                ..Default::default()
+            },
+            mode: "b2".to_string(),
+            ttl: None,
+            started: id.to_string(),
+            sha256: None,
+            encryption: EncryptionMode::None,
+            parts: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn last_part_may_be_short() {
+        let mut e = entry("a", 25, 10);
+        assert_eq!(e.part_count(), 3);
+        assert_eq!(e.part_length(1), 10);
+        assert_eq!(e.part_length(3), 5);
+        e.parts.insert(1, "e1".to_string());
+        e.parts.insert(3, "e3".to_string());
+        assert_eq!(e.bytes_done(), 15);
+        assert_eq!(entry("b", 20, 10).part_count(), 2);
+    }
+
+    #[test]
+    fn fingerprint_sees_same_size_edits() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("f");
+        let mut data = vec![0u8; 3 * FINGERPRINT_SAMPLE as usize];
+        fs::write(&path, &data).unwrap();
+        let before = FileFingerprint::compute(&path).unwrap();
+        assert_eq!(before.size, data.len() as u64);
+        assert!(FileFingerprint::compute(&path).unwrap() == before);
+
+        *data.last_mut().unwrap() = 1;
+        fs::write(&path, &data).unwrap();
+        let after = FileFingerprint::compute(&path).unwrap();
+        assert_ne!(after.sample_sha256, before.sample_sha256);
+    }
+
+    #[test]
+    fn entries_round_trip_and_list_in_start_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let journal = Journal::new(dir.path().join("journal"));
+        let mut later = entry("2026-02", 10, 5);
+        later.parts.insert(1, "etag".to_string());
+        journal.save(&later).unwrap();
+        journal.save(&entry("2026-01", 10, 5)).unwrap();
+        // Not started yet: nothing to resume
+        let mut pending = entry("2026-03", 10, 5);
+        pending.upload_id.clear();
+        journal.save(&pending).unwrap();
+
+        let ids: Vec<String> = journal.list().into_iter().map(|e| e.id).collect();
+        assert_eq!(ids, ["2026-01", "2026-02"]);
+        assert_eq!(journal.load("2026-02").unwrap().parts[&1], "etag");
+
+        journal.remove("2026-02");
+        assert!(journal.load("2026-02").is_err());
+    }
+
+    #[test]
+    fn loads_entries_with_a_plain_content_type() {
+        let mut json = serde_json::to_value(entry("old", 10, 5)).unwrap();
+        let fields = json.as_object_mut().unwrap();
+        fields.retain(|k, _| !matches!(k.as_str(), "sha256" | "encryption" | "parts" | "metadata"));
+        fields.insert("content_type".to_string(), "image/png".into());
+        let old: JournalEntry = serde_json::from_value(json).unwrap();
+        assert_eq!(old.headers.content_type, "image/png");
+        assert!(old.parts.is_empty());
+    }
+}
@@ -0,0 +1,464 @@
+use chrono::{DateTime, Datelike, Local, Timelike, Utc};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use uuid::Uuid;
+
+// Alphabet for {randN}: lowercase so keys stay case-insensitive-safe.
+const RAND_ALPHABET: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+const MAX_RAND_LEN: usize = 32;
+
+// Serializes counter updates across concurrent uploads.
+static COUNTER_LOCK: Mutex<()> = Mutex::new(());
+
+/// One piece of a parsed template.
+enum Part {
+    Literal(String),
+    Token { name: String, arg: Option<String> },
+}
+
+/// A parsed object key template such as `{folder}/{yyyy}/{mm}/{stem}-{rand6}.{ext}`.
+pub struct KeyTemplate {
+    parts: Vec<Part>,
+}
+
+/// What a template is rendered against.
+pub struct KeyContext<'a> {
+    pub folder: &'a str,
+    /// Original file or directory name.
+    pub name: &'a str,
+    /// Directories have no extension; `{ext}` and the dot before it drop out.
+    pub is_dir: bool,
+    pub sha256: Option<&'a str>,
+    pub counter: Option<u64>,
+}
+
+impl KeyTemplate {
+    pub fn parse(template: &str) -> Result<Self, String> {
+        let mut parts = Vec::new();
+        let mut rest = template;
+        while let Some(open) = rest.find(['{', '}']) {
+            if rest[open..].starts_with('}') {
+                return Err(format!("Unmatched '}}' in key template: {}", template));
+            }
+            if open > 0 {
+                parts.push(Part::Literal(rest[..open].to_string()));
+            }
+            let close = rest[open..]
+                .find('}')
+                .ok_or_else(|| format!("Unclosed '{{' in key template: {}", template))?;
+            let inner = &rest[open + 1..open + close];
+            let (name, arg) = match inner.split_once(':') {
+                Some((name, arg)) => (name.trim(), Some(arg.trim().to_string())),
+                None => (inner.trim(), None),
+            };
+            validate_token(name, arg.as_deref())?;
+            parts.push(Part::Token {
+                name: name.to_string(),
+                arg,
+            });
+            rest = &rest[open + close + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Literal(rest.to_string()));
+        }
+        Ok(Self { parts })
+    }
+
+    fn tokens(&self) -> impl Iterator<Item = &str> {
+        self.parts.iter().filter_map(|p| match p {
+            Part::Token { name, .. } => Some(name.as_str()),
+            Part::Literal(_) => None,
+        })
+    }
+
+    pub fn needs_sha256(&self) -> bool {
+        self.tokens().any(|t| t == "sha256")
+    }
+
+    pub fn uses_counter(&self) -> bool {
+        self.tokens().any(|t| t == "counter")
+    }
+
+    /// Whether every render yields a fresh key, so existence checks can be skipped.
+    pub fn is_unique(&self) -> bool {
+        self.tokens().any(|t| t == "uuid" || rand_len(t).is_some())
+    }
+
+    pub fn render(&self, ctx: &KeyContext) -> Result<String, String> {
+        let now_local = Local::now();
+        let now_utc = now_local.with_timezone(&Utc);
+        let (stem, ext) = split_name(ctx.name, ctx.is_dir);
+
+        let mut out = String::new();
+        for part in &self.parts {
+            let (name, arg) = match part {
+                Part::Literal(text) => {
+                    out.push_str(text);
+                    continue;
+                }
+                Part::Token { name, arg } => (name.as_str(), arg.as_deref()),
+            };
+            let value = match name {
+                "folder" => ctx.folder.to_string(),
+                "name" => ctx.name.to_string(),
+                "stem" => stem.to_string(),
+                "ext" => {
+                    if ext.is_empty() && out.ends_with('.') {
+                        out.pop();
+                    }
+                    ext.to_string()
+                }
+                "slug" => slugify(stem),
+                "sha256" => {
+                    let hash = ctx
+                        .sha256
+                        .ok_or("{sha256} can't be used for folder uploads")?;
+                    match arg {
+                        Some(n) => hash[..n.parse::<usize>().unwrap_or(64).min(64)].to_string(),
+                        None => hash.to_string(),
+                    }
+                }
+                "uuid" => Uuid::new_v4().to_string(),
+                "hostname" => hostname(),
+                "user" => username(),
+                "counter" => {
+                    let n = ctx.counter.unwrap_or(0);
+                    let width = arg.and_then(|w| w.parse::<usize>().ok()).unwrap_or(0);
+                    format!("{:0width$}", n, width = width)
+                }
+                _ => match rand_len(name) {
+                    Some(len) => random_id(len),
+                    None if arg == Some("utc") => format_date(name, &now_utc),
+                    None => format_date(name, &now_local),
+                },
+            };
+            out.push_str(&value);
+        }
+        normalize_key(&out)
+    }
+}
+
+fn validate_token(name: &str, arg: Option<&str>) -> Result<(), String> {
+    let arg_ok = match name {
+        "folder" | "name" | "stem" | "ext" | "slug" | "uuid" | "hostname" | "user" => arg.is_none(),
+        "sha256" => arg.is_none_or(|n| n.parse::<usize>().is_ok_and(|n| (1..=64).contains(&n))),
+        "counter" => arg.is_none_or(|n| n.parse::<usize>().is_ok_and(|n| n <= 12)),
+        "yyyy" | "yy" | "mm" | "dd" | "hh" | "min" | "ss" | "date" | "time" | "unix" => {
+            arg.is_none_or(|a| a == "utc" || a == "local")
+        }
+        _ if rand_len(name).is_some() => arg.is_none(),
+        _ => return Err(format!("Unknown key template token: {{{}}}", name)),
+    };
+    if arg_ok {
+        Ok(())
+    } else {
+        Err(format!(
+            "Invalid argument for {{{}}}: {}",
+            name,
+            arg.unwrap_or_default()
+        ))
+    }
+}
+
+/// Length of a `{randN}` token, 1-32.
+fn rand_len(name: &str) -> Option<usize> {
+    name.strip_prefix("rand")?
+        .parse::<usize>()
+        .ok()
+        .filter(|n| (1..=MAX_RAND_LEN).contains(n))
+}
+
+fn random_id(len: usize) -> String {
+    (0..len)
+        .map(|_| RAND_ALPHABET[fastrand::usize(..RAND_ALPHABET.len())] as char)
+        .collect()
+}
+
+fn format_date<Tz: chrono::TimeZone>(name: &str, now: &DateTime<Tz>) -> String
+where
+    Tz::Offset: std::fmt::Display,
+{
+    match name {
+        "yyyy" => format!("{:04}", now.year()),
+        "yy" => format!("{:02}", now.year() % 100),
+        "mm" => format!("{:02}", now.month()),
+        "dd" => format!("{:02}", now.day()),
+        "hh" => format!("{:02}", now.hour()),
+        "min" => format!("{:02}", now.minute()),
+        "ss" => format!("{:02}", now.second()),
+        "date" => now.format("%Y-%m-%d").to_string(),
+        "time" => now.format("%H%M%S").to_string(),
+        _ => now.timestamp().to_string(),
+    }
+}
+
+/// Stem and extension. Files without an extension get "bin", matching
+/// the UUID naming this replaces.
+fn split_name(name: &str, is_dir: bool) -> (&str, &str) {
+    if is_dir {
+        return (name, "");
+    }
+    let path = Path::new(name);
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or(name);
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("bin");
+    (stem, ext)
+}
+
+/// Lowercase ASCII letters and digits, everything else collapsed to '-'.
+fn slugify(s: &str) -> String {
+    let mut slug = String::new();
+    for c in s.chars().flat_map(char::to_lowercase) {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    let slug = slug.trim_end_matches('-');
+    if slug.is_empty() {
+        "file".to_string()
+    } else {
+        slug.to_string()
+    }
+}
+
+pub fn hostname() -> String {
+    gethostname::gethostname()
+        .to_string_lossy()
+        .split('.')
+        .next()
+        .unwrap_or("host")
+        .to_string()
+}
+
+pub fn username() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_else(|_| "user".to_string())
+}
+
+/// Drop empty segments (an unset `{folder}`) and reject ones that would
+/// escape the prefix.
+fn normalize_key(key: &str) -> Result<String, String> {
+    let segments: Vec<&str> = key.split('/').filter(|s| !s.is_empty()).collect();
+    if segments.is_empty() {
+        return Err("Key template rendered an empty key".to_string());
+    }
+    if segments.iter().any(|s| *s == "." || *s == "..") {
+        return Err("Key template rendered a '.' or '..' path segment".to_string());
+    }
+    Ok(segments.join("/"))
+}
+
+/// The template for a destination: its own if set, otherwise the layout the
+/// DATE_FOLDERS / UUID_FILENAMES / CONTENT_ADDRESSED toggles describe.
+pub fn template_for(
+    custom: Option<&str>,
+    config: &HashMap<String, String>,
+    is_dir: bool,
+) -> String {
+    if let Some(template) = custom.map(str::trim).filter(|t| !t.is_empty()) {
+        return template.to_string();
+    }
+    let use_date = config.get("DATE_FOLDERS").map(|s| s.as_str()).unwrap_or("on") != "off";
+    let use_uuid = config.get("UUID_FILENAMES").map(|s| s.as_str()).unwrap_or("on") != "off";
+    let content_addressed = config.get("CONTENT_ADDRESSED").map(|s| s.as_str()).unwrap_or("off") == "on";
+
+    // A date segment would give identical bytes a new key every day
+    if content_addressed && !is_dir {
+        return "{folder}/{sha256}.{ext}".to_string();
+    }
+    let mut template = "{folder}/".to_string();
+    if use_date {
+        template.push_str("{yyyy}/{mm}/{dd}/");
+    }
+    template.push_str(if use_uuid { "{uuid}.{ext}" } else { "{name}" });
+    template
+}
+
+/// Persistent `{counter}` value, shared by every upload.
+#[derive(Clone)]
+pub struct Counter {
+    path: PathBuf,
+}
+
+impl Counter {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    fn read(&self) -> u64 {
+        fs::read_to_string(&self.path)
+            .ok()
+            .and_then(|s| s.trim().parse().ok())
+            .unwrap_or(0)
+    }
+
+    /// The value the next upload will get, without using it up.
+    pub fn peek(&self) -> u64 {
+        let _lock = COUNTER_LOCK.lock().unwrap();
+        self.read() + 1
+    }
+
+    pub fn next(&self) -> Result<u64, String> {
+        let _lock = COUNTER_LOCK.lock().unwrap();
+        let value = self.read() + 1;
+        fs::write(&self.path, value.to_string())
+            .map_err(|e| format!("Failed to save key counter: {}", e))?;
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file<'a>(folder: &'a str, name: &'a str) -> KeyContext<'a> {
+        KeyContext {
+            folder,
+            name,
+            is_dir: false,
+            sha256: None,
+            counter: None,
+        }
+    }
+
+    fn render(template: &str, ctx: &KeyContext) -> String {
+        KeyTemplate::parse(template).unwrap().render(ctx).unwrap()
+    }
+
+    #[test]
+    fn renders_name_tokens() {
+        let ctx = file("shots", "My Photo (1).JPG");
+        assert_eq!(render("{folder}/{name}", &ctx), "shots/My Photo (1).JPG");
+        assert_eq!(render("{folder}/{stem}.{ext}", &ctx), "shots/My Photo (1).JPG");
+        assert_eq!(render("{slug}.{ext}", &ctx), "my-photo-1.JPG");
+    }
+
+    #[test]
+    fn empty_folder_drops_out() {
+        assert_eq!(render("{folder}/{name}", &file("", "a.txt")), "a.txt");
+    }
+
+    #[test]
+    fn files_without_extension_get_bin() {
+        assert_eq!(render("{stem}.{ext}", &file("", "README")), "README.bin");
+    }
+
+    #[test]
+    fn directories_drop_the_extension_and_its_dot() {
+        let ctx = KeyContext {
+            is_dir: true,
+            ..file("up", "photos.2024")
+        };
+        assert_eq!(render("{folder}/{stem}.{ext}", &ctx), "up/photos.2024");
+    }
+
+    #[test]
+    fn sha256_can_be_shortened() {
+        let hash = "ab".repeat(32);
+        let ctx = KeyContext {
+            sha256: Some(&hash),
+            ..file("", "a.png")
+        };
+        assert_eq!(render("{sha256}.{ext}", &ctx), format!("{}.png", hash));
+        assert_eq!(render("{sha256:8}.{ext}", &ctx), "abababab.png");
+    }
+
+    #[test]
+    fn sha256_without_a_hash_fails() {
+        let template = KeyTemplate::parse("{sha256}").unwrap();
+        assert!(template.render(&file("", "dir")).is_err());
+    }
+
+    #[test]
+    fn counter_is_zero_padded() {
+        let ctx = KeyContext {
+            counter: Some(42),
+            ..file("", "a.txt")
+        };
+        assert_eq!(render("{counter:5}-{name}", &ctx), "00042-a.txt");
+        assert_eq!(render("{counter}-{name}", &ctx), "42-a.txt");
+    }
+
+    #[test]
+    fn random_tokens_have_the_requested_length() {
+        let key = render("{rand6}", &file("", "a.txt"));
+        assert_eq!(key.len(), 6);
+        assert!(key.bytes().all(|b| RAND_ALPHABET.contains(&b)));
+        assert_eq!(render("{uuid}", &file("", "a.txt")).len(), 36);
+    }
+
+    #[test]
+    fn date_tokens_render_digits() {
+        let key = render("{yyyy}/{mm}/{dd}/{date:utc}", &file("", "a.txt"));
+        let parts: Vec<&str> = key.split('/').collect();
+        assert_eq!(parts[0].len(), 4);
+        assert_eq!(parts[1].len(), 2);
+        assert_eq!(parts[2].len(), 2);
+        assert_eq!(parts[3].len(), 10);
+    }
+
+    #[test]
+    fn rejects_bad_templates() {
+        for template in [
+            "{nope}",
+            "{name",
+            "name}",
+            "{rand0}",
+            "{rand33}",
+            "{sha256:65}",
+            "{counter:13}",
+            "{yyyy:mars}",
+            "{name:x}",
+        ] {
+            assert!(KeyTemplate::parse(template).is_err(), "{}", template);
+        }
+    }
+
+    #[test]
+    fn rejects_dot_segments_and_empty_keys() {
+        let template = KeyTemplate::parse("{folder}/{name}").unwrap();
+        assert!(template.render(&file("..", "a.txt")).is_err());
+        assert!(template.render(&file("", "")).is_err());
+    }
+
+    #[test]
+    fn reports_what_a_template_needs() {
+        let template = KeyTemplate::parse("{folder}/{sha256:12}-{counter}").unwrap();
+        assert!(template.needs_sha256());
+        assert!(template.uses_counter());
+        assert!(!template.is_unique());
+        assert!(KeyTemplate::parse("{rand4}").unwrap().is_unique());
+        assert!(KeyTemplate::parse("{uuid}.{ext}").unwrap().is_unique());
+    }
+
+    #[test]
+    fn template_follows_the_layout_toggles() {
+        let mut config = HashMap::new();
+        assert_eq!(
+            template_for(None, &config, false),
+            "{folder}/{yyyy}/{mm}/{dd}/{uuid}.{ext}"
+        );
+        config.insert("DATE_FOLDERS".to_string(), "off".to_string());
+        config.insert("UUID_FILENAMES".to_string(), "off".to_string());
+        assert_eq!(template_for(None, &config, false), "{folder}/{name}");
+        config.insert("CONTENT_ADDRESSED".to_string(), "on".to_string());
+        assert_eq!(template_for(None, &config, false), "{folder}/{sha256}.{ext}");
+        assert_eq!(template_for(None, &config, true), "{folder}/{name}");
+        assert_eq!(template_for(Some(" {name} "), &config, false), "{name}");
+        assert_eq!(template_for(Some("  "), &config, true), "{folder}/{name}");
+    }
+
+    #[test]
+    fn counter_persists_between_uploads() {
+        let dir = tempfile::tempdir().unwrap();
+        let counter = Counter::new(dir.path().join("counter"));
+        assert_eq!(counter.peek(), 1);
+        assert_eq!(counter.next().unwrap(), 1);
+        assert_eq!(counter.next().unwrap(), 2);
+        assert_eq!(Counter::new(dir.path().join("counter")).peek(), 3);
+    }
+}
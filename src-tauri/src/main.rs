@@ -1,32 +1,137 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+mod b2auth;
+mod body;
+mod bundle;
+mod compress;
+mod e2e;
+mod headers;
+mod journal;
+mod keytemplate;
+mod provider;
+mod retry;
+mod sanitize;
+mod sse;
 mod storage;
+mod throttle;
+mod transform;
 mod uploader;
+mod variants;
 
 use serde_json::{json, Value};
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
 use tauri::Emitter;
 use tauri::LogicalSize;
 use tauri::Manager;
 use tauri_plugin_clipboard_manager::ClipboardExt;
+use tokio_util::sync::CancellationToken;
 
 const DEFAULT_PARALLELISM: usize = 4;
 
-fn build_progress_callback(
+/// Emits "upload-progress" events for one upload. Progress events carry a
+/// phase ("downloading" for URL imports, "uploading" otherwise) so the UI can
+/// label what the bar means, plus the number of requests retried so far.
+#[derive(Clone)]
+struct UploadEvents {
     app: tauri::AppHandle,
     upload_id: String,
-) -> uploader::ProgressFn {
-    Arc::new(move |done, total| {
+    retries: Arc<AtomicU32>,
+}
+
+impl UploadEvents {
+    fn new(app: &tauri::AppHandle, upload_id: &str) -> Self {
+        Self {
+            app: app.clone(),
+            upload_id: upload_id.to_string(),
+            retries: Arc::new(AtomicU32::new(0)),
+        }
+    }
+
+    fn progress(&self, phase: &'static str) -> uploader::ProgressFn {
+        let events = self.clone();
+        Arc::new(move |done, total| {
+            let _ = events.app.emit(
+                "upload-progress",
+                json!({
+                    "uploadId": events.upload_id,
+                    "phase": phase,
+                    "bytesDone": done,
+                    "bytesTotal": total,
+                    "retries": events.retries.load(Ordering::SeqCst),
+                }),
+            );
+        })
+    }
+
+    /// Retry policy from config that reports each retry as a "retrying" event.
+    fn retry_policy(&self, config: &HashMap<String, String>) -> retry::RetryPolicy {
+        let events = self.clone();
+        retry::RetryPolicy {
+            on_retry: Some(Arc::new(move |operation, attempt, delay, reason| {
+                let retries = events.retries.fetch_add(1, Ordering::SeqCst) + 1;
+                let _ = events.app.emit(
+                    "upload-progress",
+                    json!({
+                        "uploadId": events.upload_id,
+                        "phase": "retrying",
+                        "operation": operation,
+                        "attempt": attempt,
+                        "delayMs": delay.as_millis() as u64,
+                        "reason": reason,
+                        "retries": retries,
+                    }),
+                );
+            })),
+            ..retry::RetryPolicy::from_config(config)
+        }
+    }
+}
+
+/// Cancellation tokens of the uploads currently running, keyed by the
+/// frontend's upload ID.
+#[derive(Default)]
+struct ActiveUploads(Mutex<HashMap<String, CancellationToken>>);
+
+impl ActiveUploads {
+    fn register(&self, upload_id: &str) -> ActiveUpload<'_> {
+        let token = CancellationToken::new();
+        self.0
+            .lock()
+            .unwrap()
+            .insert(upload_id.to_string(), token.clone());
+        ActiveUpload {
+            uploads: self,
+            upload_id: upload_id.to_string(),
+            token,
+        }
+    }
+}
+
+/// Unregisters the upload's token when the command returns.
+struct ActiveUpload<'a> {
+    uploads: &'a ActiveUploads,
+    upload_id: String,
+    token: CancellationToken,
+}
+
+impl Drop for ActiveUpload<'_> {
+    fn drop(&mut self) {
+        self.uploads.0.lock().unwrap().remove(&self.upload_id);
+    }
+}
+
+/// Tell the UI a cancelled upload has fully stopped (parts aborted, temp
+/// files gone) before passing the result on.
+fn report_cancel<T>(app: &tauri::AppHandle, upload_id: &str, result: Result<T, String>) -> Result<T, String> {
+    if matches!(&result, Err(e) if e == uploader::CANCELLED) {
         let _ = app.emit(
             "upload-progress",
-            json!({
-                "uploadId": upload_id,
-                "bytesDone": done,
-                "bytesTotal": total,
-            }),
+            json!({ "uploadId": upload_id, "phase": "cancelled" }),
         );
-    })
+    }
+    result
 }
 
 fn parallelism_from_config(config: &HashMap<String, String>) -> usize {
@@ -37,6 +142,107 @@ fn parallelism_from_config(config: &HashMap<String, String>) -> usize {
         .unwrap_or(DEFAULT_PARALLELISM)
 }
 
+/// The app-wide throttle, updated with the current limits.
+fn upload_throttle(
+    app: &tauri::AppHandle,
+    config: &HashMap<String, String>,
+) -> Arc<throttle::Throttle> {
+    let throttle = app.state::<Arc<throttle::Throttle>>().inner().clone();
+    throttle.configure(config);
+    throttle
+}
+
+/// MULTIPART_PART_SIZE is in MiB; "auto", empty or 0 picks it per file.
+fn part_size_from_config(config: &HashMap<String, String>) -> Option<u64> {
+    config
+        .get("MULTIPART_PART_SIZE")
+        .and_then(|s| s.parse::<u64>().ok())
+        .filter(|n| *n > 0)
+        .map(|mib| mib.saturating_mul(1024 * 1024))
+}
+
+/// HASH_BEFORE_UPLOAD: hash multipart files before sending them, at the
+/// cost of reading them twice.
+fn hash_first_from_config(config: &HashMap<String, String>) -> bool {
+    config.get("HASH_BEFORE_UPLOAD").map(|s| s.as_str()) == Some("on")
+}
+
+fn journal(app: &tauri::AppHandle) -> journal::Journal {
+    journal::Journal::new(storage::journal_dir(app))
+}
+
+fn key_counter(app: &tauri::AppHandle) -> keytemplate::Counter {
+    keytemplate::Counter::new(storage::key_counter_path(app))
+}
+
+fn download_auth(app: &tauri::AppHandle) -> Arc<b2auth::DownloadAuthCache> {
+    app.state::<Arc<b2auth::DownloadAuthCache>>().inner().clone()
+}
+
+/// Display name for history entries. Falls back to the ID when the
+/// destination has since been removed.
+fn destination_name(app: &tauri::AppHandle, id: &str) -> String {
+    storage::find_destination(app, id)
+        .map(|d| d.name)
+        .unwrap_or_else(|_| id.to_string())
+}
+
+fn record_history(
+    app: &tauri::AppHandle,
+    file_name: String,
+    outcome: &uploader::UploadOutcome,
+    mode: &str,
+) {
+    let now = chrono::Local::now();
+    let datetime = now.format("%Y-%m-%d %H:%M:%S").to_string();
+
+    let mut entry = json!({
+        "file": file_name,
+        "url": outcome.url,
+        "datetime": datetime,
+        "mode": mode,
+        "destination": destination_name(app, mode),
+        "key": outcome.key,
+        "sha256": outcome.sha256,
+        "reused": outcome.reused,
+    });
+    if !outcome.sha256_stored {
+        entry["sha256Stored"] = json!(false);
+    }
+    if !outcome.stripped.is_empty() {
+        entry["stripped"] = json!(outcome.stripped);
+    }
+    if !outcome.variants.is_empty() {
+        entry["variants"] = json!(outcome.variants);
+    }
+    if let Some(srcset) = &outcome.srcset {
+        entry["srcset"] = json!(srcset);
+    }
+    if let Some(transform) = &outcome.transform {
+        entry["transform"] = json!(transform);
+    }
+    if let Some(compression) = &outcome.compression {
+        entry["compression"] = json!(compression);
+    }
+
+    let history_mutex = app.state::<storage::HistoryMutex>();
+    let _lock = history_mutex.0.lock().unwrap();
+    storage::add_history(app, entry);
+}
+
+/// What the upload commands hand back: the link, plus any image variants,
+/// their `srcset` and what the image rules did.
+fn upload_result(outcome: &uploader::UploadOutcome) -> Value {
+    json!({
+        "url": outcome.url,
+        "variants": outcome.variants,
+        "srcset": outcome.srcset,
+        "transform": outcome.transform,
+        "compression": outcome.compression,
+        "sha256Stored": outcome.sha256_stored,
+    })
+}
+
 #[tauri::command]
 async fn get_settings(app: tauri::AppHandle) -> Result<HashMap<String, String>, String> {
     storage::get_settings(&app)
@@ -56,117 +262,450 @@ async fn has_settings(app: tauri::AppHandle) -> Result<bool, String> {
 #[tauri::command]
 async fn upload_file(
     app: tauri::AppHandle,
-    history_mutex: tauri::State<'_, storage::HistoryMutex>,
     upload_id: String,
     file_path: String,
     mode: String,
     auto_clip: bool,
     ttl: Option<u64>,
-) -> Result<String, String> {
+    headers: Option<HashMap<String, String>>,
+) -> Result<Value, String> {
     let config = storage::get_config(&app);
-    let creds = storage::B2Credentials::load()?;
-    let parallelism = parallelism_from_config(&config);
-    let progress = build_progress_callback(app.clone(), upload_id);
-    let url = uploader::upload_file(
-        &file_path,
-        &mode,
-        &config,
-        &creds,
-        ttl,
-        parallelism,
-        Some(progress),
-    )
-    .await?;
+    let creds = storage::B2Credentials::load(&app)?;
+    let dest = storage::find_destination(&app, &mode)?;
+    let active_uploads = app.state::<ActiveUploads>();
+    let active = active_uploads.register(&upload_id);
+    let events = UploadEvents::new(&app, &upload_id);
+    let opts = uploader::UploadOptions {
+        parallelism: parallelism_from_config(&config),
+        part_size: part_size_from_config(&config),
+        progress: Some(events.progress("uploading")),
+        journal: Some(journal(&app)),
+        cancel: active.token.clone(),
+        retry: events.retry_policy(&config),
+        throttle: upload_throttle(&app, &config),
+        counter: key_counter(&app),
+        download_auth: download_auth(&app),
+        headers,
+        source_url: None,
+        hash_first: hash_first_from_config(&config),
+    };
+    let result = uploader::upload_file(&file_path, &dest, &config, &creds, ttl, &opts).await;
+    let outcome = report_cancel(&app, &upload_id, result)?;
 
     if auto_clip {
         app.clipboard()
-            .write_text(&url)
+            .write_text(&outcome.url)
             .map_err(|e| e.to_string())?;
     }
 
-    let now = chrono::Local::now();
-    let datetime = now.format("%Y-%m-%d %H:%M:%S").to_string();
     let file_name = std::path::Path::new(&file_path)
         .file_name()
         .and_then(|n| n.to_str())
         .unwrap_or("unknown")
         .to_string();
+    record_history(&app, file_name, &outcome, &mode);
 
-    let entry = json!({
-        "file": file_name,
-        "url": url,
-        "datetime": datetime,
-        "mode": mode,
-    });
-
-    {
-        let _lock = history_mutex.0.lock().unwrap();
-        storage::add_history(&app, entry);
-    }
-
-    Ok(url)
+    Ok(upload_result(&outcome))
 }
 
 #[tauri::command]
 async fn download_and_upload_url(
     app: tauri::AppHandle,
-    history_mutex: tauri::State<'_, storage::HistoryMutex>,
     upload_id: String,
     url: String,
     mode: String,
     auto_clip: bool,
     ttl: Option<u64>,
-) -> Result<String, String> {
-    // Download the URL to a temp file
-    let tmp_path = uploader::download_url(&url).await?;
+    headers: Option<HashMap<String, String>>,
+) -> Result<Value, String> {
+    let dest = storage::find_destination(&app, &mode)?;
+    let active_uploads = app.state::<ActiveUploads>();
+    let active = active_uploads.register(&upload_id);
+    let events = UploadEvents::new(&app, &upload_id);
+
+    // Stream the URL to a temp file
+    let downloaded = uploader::download_url(&url, Some(events.progress("downloading")), &active.token).await;
+    let tmp_path = report_cancel(&app, &upload_id, downloaded)?;
 
     // Upload the temp file
+    // No journal: the temp file is removed below, so there'd be nothing to
+    // resume from.
     let config = storage::get_config(&app);
-    let creds = storage::B2Credentials::load()?;
-    let parallelism = parallelism_from_config(&config);
-    let progress = build_progress_callback(app.clone(), upload_id);
-    let result_url = uploader::upload_file(
-        &tmp_path,
-        &mode,
-        &config,
-        &creds,
-        ttl,
-        parallelism,
-        Some(progress),
-    )
-    .await;
+    let creds = storage::B2Credentials::load(&app)?;
+    let opts = uploader::UploadOptions {
+        parallelism: parallelism_from_config(&config),
+        part_size: part_size_from_config(&config),
+        progress: Some(events.progress("uploading")),
+        journal: None,
+        cancel: active.token.clone(),
+        retry: events.retry_policy(&config),
+        throttle: upload_throttle(&app, &config),
+        counter: key_counter(&app),
+        download_auth: download_auth(&app),
+        headers,
+        source_url: Some(url.clone()),
+        hash_first: hash_first_from_config(&config),
+    };
+    let outcome = uploader::upload_file(&tmp_path, &dest, &config, &creds, ttl, &opts).await;
 
     // Clean up temp file regardless of upload result
     let _ = std::fs::remove_file(&tmp_path);
 
-    let result_url = result_url?;
+    let outcome = report_cancel(&app, &upload_id, outcome)?;
 
     if auto_clip {
         app.clipboard()
-            .write_text(&result_url)
+            .write_text(&outcome.url)
             .map_err(|e| e.to_string())?;
     }
 
-    let now = chrono::Local::now();
-    let datetime = now.format("%Y-%m-%d %H:%M:%S").to_string();
-
     // Use the URL's filename for the history entry
     let url_path = url.split('?').next().unwrap_or(&url);
-    let file_name = url_path.split('/').last().unwrap_or("download").to_string();
+    let file_name = url_path.rsplit('/').next().unwrap_or("download").to_string();
+    record_history(&app, file_name, &outcome, &mode);
 
-    let entry = json!({
-        "file": file_name,
-        "url": result_url,
-        "datetime": datetime,
+    Ok(upload_result(&outcome))
+}
+
+/// Render the object key an upload would get, from the (possibly unsaved)
+/// prefix and template in the settings form.
+#[tauri::command]
+fn preview_object_key(
+    app: tauri::AppHandle,
+    prefix: String,
+    template: Option<String>,
+    file_name: Option<String>,
+) -> Result<String, String> {
+    let config = storage::get_config(&app);
+    let dest = storage::Destination {
+        id: String::new(),
+        name: String::new(),
+        prefix,
+        key_template: template.unwrap_or_default(),
+        headers: String::new(),
+        image_rules: String::new(),
+        encryption: sse::EncryptionMode::None,
+        e2e: e2e::E2eMode::Off,
+        recipients: String::new(),
+    };
+    uploader::preview_object_key(
+        &dest,
+        &config,
+        file_name.as_deref().unwrap_or("photo.jpg"),
+        &key_counter(&app),
+    )
+}
+
+/// Destinations with `hasToken` / `hasSseKey` flags. Token and key values
+/// never leave the backend.
+#[tauri::command]
+fn get_destinations(app: tauri::AppHandle) -> Result<Vec<Value>, String> {
+    let with_token = storage::get_destination_token_ids(&app)?;
+    let with_sse_key = storage::get_destination_sse_key_ids(&app)?;
+    Ok(storage::get_destinations(&app)
+        .into_iter()
+        .map(|d| {
+            json!({
+                "hasToken": with_token.contains(&d.id),
+                "hasSseKey": with_sse_key.contains(&d.id),
+                "id": d.id,
+                "name": d.name,
+                "prefix": d.prefix,
+                "keyTemplate": d.key_template,
+                "headers": d.headers,
+                "imageRules": d.image_rules,
+                "encryption": d.encryption,
+                "e2e": d.e2e,
+                "recipients": d.recipients,
+            })
+        })
+        .collect())
+}
+
+#[tauri::command]
+fn save_destinations(
+    app: tauri::AppHandle,
+    destinations: Vec<storage::DestinationInput>,
+) -> Result<bool, String> {
+    storage::save_destinations(&app, destinations)?;
+    Ok(true)
+}
+
+/// Signing key IDs with an `active` flag. Secrets never leave the backend.
+#[tauri::command]
+fn get_signing_keys(app: tauri::AppHandle) -> Result<Vec<Value>, String> {
+    Ok(storage::get_signing_keys(&app)?
+        .into_iter()
+        .map(|(id, active)| json!({ "id": id, "active": active }))
+        .collect())
+}
+
+#[tauri::command]
+fn save_signing_keys(app: tauri::AppHandle, keys: Vec<storage::SigningKeyInput>) -> Result<bool, String> {
+    storage::save_signing_keys(&app, keys)?;
+    Ok(true)
+}
+
+/// Copy the Worker's SIGNING_KEYS secret to the clipboard, so the secrets
+/// don't pass through the frontend.
+#[tauri::command]
+fn copy_worker_signing_keys(app: tauri::AppHandle) -> Result<(), String> {
+    let layout = storage::worker_signing_keys(&app)?;
+    app.clipboard().write_text(layout.as_str()).map_err(|e| e.to_string())
+}
+
+/// This machine's age public key, for other team members' recipient lists.
+#[tauri::command]
+fn get_age_recipient(app: tauri::AppHandle) -> Result<String, String> {
+    storage::age_recipient(&app)
+}
+
+/// Download an end-to-end encrypted link and save the decrypted file.
+#[tauri::command]
+async fn decrypt_download(
+    app: tauri::AppHandle,
+    upload_id: String,
+    url: String,
+    save_path: String,
+) -> Result<String, String> {
+    let creds = storage::B2Credentials::load(&app)?;
+    let active_uploads = app.state::<ActiveUploads>();
+    let active = active_uploads.register(&upload_id);
+    let events = UploadEvents::new(&app, &upload_id);
+    let result = e2e::decrypt_download(
+        &url,
+        std::path::Path::new(&save_path),
+        &creds.age_identity,
+        Some(events.progress("downloading")),
+        &active.token,
+    )
+    .await;
+    report_cancel(&app, &upload_id, result)?;
+    Ok(save_path)
+}
+
+#[tauri::command]
+fn is_directory(path: String) -> bool {
+    std::path::Path::new(&path).is_dir()
+}
+
+#[tauri::command]
+async fn upload_directory(
+    app: tauri::AppHandle,
+    upload_id: String,
+    dir_path: String,
+    mode: String,
+    auto_clip: bool,
+    ttl: Option<u64>,
+    headers: Option<HashMap<String, String>>,
+) -> Result<uploader::DirectoryOutcome, String> {
+    let config = storage::get_config(&app);
+    let creds = storage::B2Credentials::load(&app)?;
+    let dest = storage::find_destination(&app, &mode)?;
+    let active_uploads = app.state::<ActiveUploads>();
+    let active = active_uploads.register(&upload_id);
+    let events = UploadEvents::new(&app, &upload_id);
+    let opts = uploader::UploadOptions {
+        parallelism: parallelism_from_config(&config),
+        part_size: part_size_from_config(&config),
+        progress: Some(events.progress("uploading")),
+        journal: Some(journal(&app)),
+        cancel: active.token.clone(),
+        retry: events.retry_policy(&config),
+        throttle: upload_throttle(&app, &config),
+        counter: key_counter(&app),
+        download_auth: download_auth(&app),
+        headers,
+        source_url: None,
+        hash_first: hash_first_from_config(&config),
+    };
+    let result = uploader::upload_directory(&dir_path, &dest, &config, &creds, ttl, &opts).await;
+    let outcome = report_cancel(&app, &upload_id, result)?;
+
+    if auto_clip {
+        app.clipboard()
+            .write_text(&outcome.prefix_url)
+            .map_err(|e| e.to_string())?;
+    }
+
+    // One history entry for the whole directory rather than one per file
+    let dir_name = std::path::Path::new(&dir_path)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("directory")
+        .to_string();
+    let uploaded = outcome.files.iter().filter(|f| f.error.is_none()).count();
+    let mut entry = json!({
+        "file": format!("{}/", dir_name),
+        "url": outcome.prefix_url,
+        "datetime": chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
         "mode": mode,
+        "destination": dest.name,
+        "key": outcome.prefix,
+        "files": uploaded,
     });
+    let mut stripped: Vec<&str> = Vec::new();
+    for kind in outcome.files.iter().flat_map(|f| &f.stripped) {
+        if !stripped.contains(kind) {
+            stripped.push(kind);
+        }
+    }
+    if !stripped.is_empty() {
+        entry["stripped"] = json!(stripped);
+    }
+    {
+        let history_mutex = app.state::<storage::HistoryMutex>();
+        let _lock = history_mutex.0.lock().unwrap();
+        storage::add_history(&app, entry);
+    }
+
+    Ok(outcome)
+}
+
+/// Upload several dropped files and folders as one archive.
+#[tauri::command]
+async fn upload_bundle(
+    app: tauri::AppHandle,
+    upload_id: String,
+    paths: Vec<String>,
+    mode: String,
+    auto_clip: bool,
+    ttl: Option<u64>,
+    headers: Option<HashMap<String, String>>,
+) -> Result<uploader::BundleOutcome, String> {
+    let config = storage::get_config(&app);
+    let creds = storage::B2Credentials::load(&app)?;
+    let dest = storage::find_destination(&app, &mode)?;
+    let format = bundle::BundleFormat::from_config(&config).unwrap_or(bundle::BundleFormat::Zip);
+    let active_uploads = app.state::<ActiveUploads>();
+    let active = active_uploads.register(&upload_id);
+    let events = UploadEvents::new(&app, &upload_id);
+    // No journal: the archive is written on the fly and can't be re-read
+    let opts = uploader::UploadOptions {
+        parallelism: parallelism_from_config(&config),
+        part_size: part_size_from_config(&config),
+        progress: Some(events.progress("uploading")),
+        journal: None,
+        cancel: active.token.clone(),
+        retry: events.retry_policy(&config),
+        throttle: upload_throttle(&app, &config),
+        counter: key_counter(&app),
+        download_auth: download_auth(&app),
+        headers,
+        source_url: None,
+        hash_first: hash_first_from_config(&config),
+    };
+    let result = uploader::upload_bundle(&paths, &dest, &config, &creds, ttl, format, &opts).await;
+    let outcome = report_cancel(&app, &upload_id, result)?;
+
+    if auto_clip {
+        app.clipboard()
+            .write_text(&outcome.url)
+            .map_err(|e| e.to_string())?;
+    }
 
+    let entry = json!({
+        "file": outcome.name,
+        "url": outcome.url,
+        "datetime": chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+        "mode": mode,
+        "destination": dest.name,
+        "key": outcome.key,
+        "sha256": outcome.sha256,
+        "sha256Stored": outcome.sha256_stored,
+        "size": outcome.size,
+        "files": outcome.manifest.len(),
+        "manifest": outcome.manifest,
+    });
     {
+        let history_mutex = app.state::<storage::HistoryMutex>();
         let _lock = history_mutex.0.lock().unwrap();
         storage::add_history(&app, entry);
     }
 
-    Ok(result_url)
+    Ok(outcome)
+}
+
+/// Multipart uploads that were interrupted (crash, sleep, network drop) and
+/// can be picked up where they left off.
+#[tauri::command]
+fn list_resumable_uploads(app: tauri::AppHandle) -> Vec<Value> {
+    journal(&app)
+        .list()
+        .iter()
+        .map(|e| {
+            json!({
+                "id": e.id,
+                "file": e.file_name,
+                "filePath": e.file_path,
+                "key": e.key,
+                "mode": e.mode,
+                "destination": destination_name(&app, &e.mode),
+                "started": e.started,
+                "bytesDone": e.bytes_done(),
+                "bytesTotal": e.fingerprint.size,
+            })
+        })
+        .collect()
+}
+
+#[tauri::command]
+async fn resume_upload(
+    app: tauri::AppHandle,
+    upload_id: String,
+    journal_id: String,
+    auto_clip: bool,
+) -> Result<String, String> {
+    let config = storage::get_config(&app);
+    let creds = storage::B2Credentials::load(&app)?;
+    let active_uploads = app.state::<ActiveUploads>();
+    let active = active_uploads.register(&upload_id);
+    let events = UploadEvents::new(&app, &upload_id);
+    let opts = uploader::UploadOptions {
+        parallelism: parallelism_from_config(&config),
+        part_size: part_size_from_config(&config),
+        progress: Some(events.progress("uploading")),
+        journal: Some(journal(&app)),
+        cancel: active.token.clone(),
+        retry: events.retry_policy(&config),
+        throttle: upload_throttle(&app, &config),
+        counter: key_counter(&app),
+        download_auth: download_auth(&app),
+        headers: None,
+        source_url: None,
+        hash_first: hash_first_from_config(&config),
+    };
+    let result = uploader::resume_upload(&journal_id, &config, &creds, &opts).await;
+    let (entry, outcome) = report_cancel(&app, &upload_id, result)?;
+
+    if auto_clip {
+        app.clipboard()
+            .write_text(&outcome.url)
+            .map_err(|e| e.to_string())?;
+    }
+
+    record_history(&app, entry.file_name, &outcome, &entry.mode);
+
+    Ok(outcome.url)
+}
+
+/// Stop an in-flight upload. Returns false if it already finished.
+#[tauri::command]
+fn cancel_upload(active_uploads: tauri::State<'_, ActiveUploads>, upload_id: String) -> bool {
+    match active_uploads.0.lock().unwrap().get(&upload_id) {
+        Some(token) => {
+            token.cancel();
+            true
+        }
+        None => false,
+    }
+}
+
+#[tauri::command]
+async fn discard_resumable_upload(app: tauri::AppHandle, journal_id: String) -> Result<(), String> {
+    let config = storage::get_config(&app);
+    let creds = storage::B2Credentials::load(&app)?;
+    uploader::discard_upload(&journal(&app), &journal_id, &config, &creds).await
 }
 
 #[tauri::command]
@@ -203,8 +742,57 @@ fn delete_history_entry(
 }
 
 #[tauri::command]
-async fn get_saved_secret_keys() -> Result<Vec<String>, String> {
-    storage::get_saved_secret_keys()
+async fn get_saved_secret_keys(app: tauri::AppHandle) -> Result<Vec<String>, String> {
+    storage::get_saved_secret_keys(&app)
+}
+
+#[tauri::command]
+fn list_profiles(app: tauri::AppHandle) -> storage::Profiles {
+    storage::get_profiles(&app)
+}
+
+/// Uploads read their profile's files as they go (journal, history), so
+/// the active profile can't change under one.
+fn ensure_idle(active_uploads: &ActiveUploads) -> Result<(), String> {
+    if active_uploads.0.lock().unwrap().is_empty() {
+        Ok(())
+    } else {
+        Err("Finish or cancel running uploads before switching profiles".to_string())
+    }
+}
+
+#[tauri::command]
+fn switch_profile(
+    app: tauri::AppHandle,
+    active_uploads: tauri::State<'_, ActiveUploads>,
+    id: String,
+) -> Result<bool, String> {
+    ensure_idle(&active_uploads)?;
+    storage::switch_profile(&app, &id)?;
+    Ok(true)
+}
+
+/// Create an empty profile and switch to it. Returns the new profile's ID.
+#[tauri::command]
+fn create_profile(
+    app: tauri::AppHandle,
+    active_uploads: tauri::State<'_, ActiveUploads>,
+    name: String,
+) -> Result<String, String> {
+    ensure_idle(&active_uploads)?;
+    storage::create_profile(&app, &name)
+}
+
+#[tauri::command]
+fn rename_profile(app: tauri::AppHandle, id: String, name: String) -> Result<bool, String> {
+    storage::rename_profile(&app, &id, &name)?;
+    Ok(true)
+}
+
+#[tauri::command]
+fn delete_profile(app: tauri::AppHandle, id: String) -> Result<bool, String> {
+    storage::delete_profile(&app, &id)?;
+    Ok(true)
 }
 
 #[tauri::command]
@@ -212,7 +800,7 @@ async fn test_connection(
     app: tauri::AppHandle,
 ) -> Result<String, String> {
     let config = storage::get_config(&app);
-    let creds = storage::B2Credentials::load()?;
+    let creds = storage::B2Credentials::load(&app)?;
     uploader::test_connection(&config, &creds).await
 }
 
@@ -231,6 +819,9 @@ fn main() {
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_notification::init())
         .manage(storage::HistoryMutex::new())
+        .manage(ActiveUploads::default())
+        .manage(Arc::new(throttle::Throttle::new()))
+        .manage(Arc::new(b2auth::DownloadAuthCache::new()))
         .setup(|app| {
             let path = app
                 .path()
@@ -244,8 +835,28 @@ fn main() {
             save_settings,
             has_settings,
             get_saved_secret_keys,
+            list_profiles,
+            switch_profile,
+            create_profile,
+            rename_profile,
+            delete_profile,
             upload_file,
             download_and_upload_url,
+            preview_object_key,
+            get_destinations,
+            save_destinations,
+            get_signing_keys,
+            save_signing_keys,
+            copy_worker_signing_keys,
+            get_age_recipient,
+            decrypt_download,
+            is_directory,
+            upload_directory,
+            upload_bundle,
+            list_resumable_uploads,
+            resume_upload,
+            discard_resumable_upload,
+            cancel_upload,
             test_connection,
             copy_to_clipboard,
             get_history,
@@ -0,0 +1,141 @@
+use aws_sdk_s3::config::RequestChecksumCalculation;
+use std::collections::HashMap;
+use url::Url;
+
+/// Where and how to reach an S3-compatible service, from the S3_* settings.
+pub struct Provider {
+    pub endpoint_url: String,
+    pub region: String,
+    pub path_style: bool,
+    pub checksums: RequestChecksumCalculation,
+}
+
+/// Per-service defaults: the region used when S3_REGION is blank (and the
+/// endpoint doesn't name one) and whether buckets go in the path.
+struct Defaults {
+    /// Endpoint suffix after `s3.REGION`, for reading the region off the host.
+    host_suffix: Option<&'static str>,
+    region: &'static str,
+    path_style: bool,
+}
+
+fn defaults(provider: &str) -> Option<Defaults> {
+    let d = match provider {
+        "b2" => Defaults {
+            host_suffix: Some(".backblazeb2.com"),
+            region: "us-east-005",
+            path_style: true,
+        },
+        // R2 signs with the pseudo-region "auto"
+        "r2" => Defaults {
+            host_suffix: None,
+            region: "auto",
+            path_style: true,
+        },
+        // AWS is phasing out path-style requests
+        "aws" => Defaults {
+            host_suffix: Some(".amazonaws.com"),
+            region: "us-east-1",
+            path_style: false,
+        },
+        "wasabi" => Defaults {
+            host_suffix: Some(".wasabisys.com"),
+            region: "us-east-1",
+            path_style: true,
+        },
+        "minio" | "custom" => Defaults {
+            host_suffix: None,
+            region: "us-east-1",
+            path_style: true,
+        },
+        _ => return None,
+    };
+    Some(d)
+}
+
+impl Provider {
+    pub fn from_config(config: &HashMap<String, String>) -> Result<Self, String> {
+        let get = |key: &str| config.get(key).map(|s| s.trim()).unwrap_or("");
+
+        let provider = match get("S3_PROVIDER") {
+            "" => "b2",
+            p => p,
+        };
+        let defaults = defaults(provider).ok_or_else(|| format!("Unknown S3 provider: {}", provider))?;
+
+        let endpoint = get("S3_ENDPOINT");
+        if endpoint.is_empty() {
+            return Err("Missing S3_ENDPOINT".to_string());
+        }
+        // A scheme typed into the endpoint field wins over S3_SCHEME
+        let (scheme, host) = match endpoint.split_once("://") {
+            Some((scheme, host)) => (scheme, host),
+            None => match get("S3_SCHEME") {
+                "" => ("https", endpoint),
+                scheme => (scheme, endpoint),
+            },
+        };
+        if scheme != "https" && scheme != "http" {
+            return Err(format!("Unsupported endpoint scheme: {}", scheme));
+        }
+        let host = host.trim_end_matches('/');
+        let url = Url::parse(&format!("{}://{}", scheme, host))
+            .map_err(|e| format!("Invalid S3 endpoint {}: {}", endpoint, e))?;
+        // Keeps the brackets of an IPv6 literal such as [::1]
+        let hostname = url
+            .host_str()
+            .ok_or_else(|| format!("Invalid S3 endpoint: {}", endpoint))?;
+
+        let endpoint_url = match get("S3_PORT") {
+            "" => format!("{}://{}", scheme, host),
+            port => {
+                let port: u16 = port
+                    .parse()
+                    .ok()
+                    .filter(|p| *p > 0)
+                    .ok_or_else(|| format!("Invalid S3 port: {}", port))?;
+                // S3_PORT replaces a port typed into the endpoint
+                let path = url.path().trim_end_matches('/');
+                format!("{}://{}:{}{}", scheme, hostname, port, path)
+            }
+        };
+
+        let region = match get("S3_REGION") {
+            "" => defaults
+                .host_suffix
+                .and_then(|suffix| region_from_host(hostname, suffix))
+                .unwrap_or(defaults.region)
+                .to_string(),
+            region => region.to_string(),
+        };
+
+        let path_style = match get("S3_ADDRESSING") {
+            "path" => true,
+            "virtual" => false,
+            _ => defaults.path_style,
+        };
+
+        // Content-MD5 covers integrity everywhere. The SDK's own checksums
+        // send streamed bodies aws-chunked with a trailer, which only some
+        // services (AWS, MinIO) accept.
+        let checksums = if get("S3_CHECKSUMS") == "sdk" {
+            RequestChecksumCalculation::WhenSupported
+        } else {
+            RequestChecksumCalculation::WhenRequired
+        };
+
+        Ok(Self {
+            endpoint_url,
+            region,
+            path_style,
+            checksums,
+        })
+    }
+}
+
+/// Region from a `s3.REGION.<suffix>` host, e.g. `s3.us-east-005.backblazeb2.com`.
+fn region_from_host<'a>(host: &'a str, suffix: &str) -> Option<&'a str> {
+    host.strip_suffix(suffix)?
+        .strip_prefix("s3.")
+        .filter(|region| !region.is_empty() && !region.contains('.'))
+}
@@ -0,0 +1,137 @@
+use aws_sdk_s3::error::{ProvideErrorMetadata, SdkError};
+use aws_smithy_runtime_api::client::orchestrator::HttpResponse;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+const DEFAULT_RETRIES: u32 = 4;
+const MAX_RETRIES: u32 = 10;
+const BASE_DELAY: Duration = Duration::from_millis(500);
+const MAX_DELAY: Duration = Duration::from_secs(30);
+// Upper bound on a server-supplied Retry-After, so a bogus header can't
+// stall an upload indefinitely.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(120);
+
+// S3 error codes B2 uses for conditions that clear up on their own.
+const TRANSIENT_CODES: &[&str] = &[
+    "InternalError",
+    "RequestTimeout",
+    "ServiceUnavailable",
+    "SlowDown",
+    "TooManyRequests",
+];
+
+/// Called before each retry with (operation, attempt number, delay, reason).
+pub type RetryFn = Arc<dyn Fn(&str, u32, Duration, &str) + Send + Sync>;
+
+/// How many times a failed request is re-sent and how long to wait between
+/// attempts. The SDK's own retries are disabled in `build_client`, so this is
+/// the only retry layer.
+#[derive(Clone)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt; 0 disables retrying.
+    pub retries: u32,
+    pub on_retry: Option<RetryFn>,
+}
+
+impl RetryPolicy {
+    pub fn from_config(config: &HashMap<String, String>) -> Self {
+        let retries = config
+            .get("UPLOAD_RETRIES")
+            .and_then(|s| s.parse::<u32>().ok())
+            .map(|n| n.min(MAX_RETRIES))
+            .unwrap_or(DEFAULT_RETRIES);
+        Self {
+            retries,
+            on_retry: None,
+        }
+    }
+
+    /// Run `op` until it succeeds, fails with a non-transient error, or the
+    /// retries run out. `op` is called again for every attempt, so request
+    /// bodies must be rebuilt inside it.
+    pub async fn run<T, E, F, Fut>(&self, operation: &str, mut op: F) -> Result<T, SdkError<E, HttpResponse>>
+    where
+        E: ProvideErrorMetadata,
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, SdkError<E, HttpResponse>>>,
+    {
+        let mut attempt = 0;
+        loop {
+            let err = match op().await {
+                Ok(value) => return Ok(value),
+                Err(err) => err,
+            };
+            if attempt >= self.retries || !is_transient(&err) {
+                return Err(err);
+            }
+            attempt += 1;
+
+            let delay = match retry_after(&err) {
+                Some(after) => after,
+                None => backoff(attempt),
+            };
+            if let Some(cb) = &self.on_retry {
+                cb(operation, attempt, delay, &describe(&err));
+            }
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+/// Network failures, timeouts, throttling and 5xx responses are worth
+/// retrying; anything else (403, 404, checksum mismatch) will fail again.
+fn is_transient<E: ProvideErrorMetadata>(err: &SdkError<E, HttpResponse>) -> bool {
+    match err {
+        SdkError::TimeoutError(_) | SdkError::DispatchFailure(_) | SdkError::ResponseError(_) => true,
+        SdkError::ServiceError(service) => {
+            let status = service.raw().status().as_u16();
+            status >= 500
+                || status == 408
+                || status == 429
+                || service
+                    .err()
+                    .code()
+                    .is_some_and(|code| TRANSIENT_CODES.contains(&code))
+        }
+        _ => false,
+    }
+}
+
+/// Retry-After in its delta-seconds form. B2 doesn't send the HTTP-date form.
+fn retry_after<E>(err: &SdkError<E, HttpResponse>) -> Option<Duration> {
+    let secs = err
+        .raw_response()?
+        .headers()
+        .get("retry-after")?
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    Some(Duration::from_secs(secs).min(MAX_RETRY_AFTER))
+}
+
+/// Exponential backoff with full jitter: a random delay up to
+/// BASE_DELAY * 2^(attempt - 1), capped at MAX_DELAY.
+fn backoff(attempt: u32) -> Duration {
+    let ceiling = BASE_DELAY
+        .saturating_mul(1 << (attempt - 1).min(16))
+        .min(MAX_DELAY);
+    ceiling.mul_f64(fastrand::f64())
+}
+
+fn describe<E: ProvideErrorMetadata>(err: &SdkError<E, HttpResponse>) -> String {
+    match err {
+        SdkError::TimeoutError(_) => "timeout".to_string(),
+        SdkError::DispatchFailure(_) => "connection failed".to_string(),
+        SdkError::ResponseError(_) => "bad response".to_string(),
+        SdkError::ServiceError(service) => {
+            let status = service.raw().status().as_u16();
+            match service.err().code() {
+                Some(code) => format!("HTTP {} {}", status, code),
+                None => format!("HTTP {}", status),
+            }
+        }
+        _ => "request failed".to_string(),
+    }
+}
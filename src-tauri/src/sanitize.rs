@@ -0,0 +1,676 @@
+use lopdf::Object;
+use std::fs;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use tokio_util::sync::CancellationToken;
+
+use crate::uploader::CANCELLED;
+
+const EXIF_HEADER: &[u8] = b"Exif\0\0";
+const EXIF_ORIENTATION: u16 = 0x0112;
+const TIFF_SHORT: u16 = 3;
+/// UUID of the box some MP4 writers keep XMP in.
+const XMP_UUID: [u8; 16] = [
+    0xBE, 0x7A, 0xCF, 0xCB, 0x97, 0xA9, 0x42, 0xE8, 0x9C, 0x71, 0x99, 0x94, 0x91, 0xE3, 0xAF, 0xAC,
+];
+/// ftyp brands of HEIF-based images, which keep EXIF in items this module
+/// can't rewrite.
+const HEIF_BRANDS: &[&[u8]] = &[b"heic", b"heix", b"hevc", b"heim", b"heis", b"mif1", b"msf1", b"avif", b"avis"];
+
+/// Whether a file starting with `head` is a HEIC or AVIF image.
+pub fn is_heif(head: &[u8]) -> bool {
+    head.get(4..8) == Some(b"ftyp") && head.get(8..12).is_some_and(|brand| HEIF_BRANDS.contains(&brand))
+}
+
+/// A copy of a file with location and identifying metadata removed, named
+/// like the original inside its own temp directory. The directory is
+/// removed when this is dropped.
+pub struct Sanitized {
+    _dir: tempfile::TempDir,
+    pub path: PathBuf,
+    /// What was found and removed, e.g. "EXIF" or "document info". Empty
+    /// if the file was already clean.
+    pub removed: Vec<&'static str>,
+}
+
+enum Format {
+    Jpeg,
+    Png,
+    WebP,
+    Pdf,
+    /// MP4, MOV and other ISO base media files.
+    IsoMedia,
+}
+
+/// Write a cleaned copy of `src`, or return None for formats that aren't
+/// handled (the file is uploaded as-is). Runs on the blocking pool.
+pub async fn strip_metadata(src: &Path, cancel: &CancellationToken) -> Result<Option<Sanitized>, String> {
+    let src = src.to_path_buf();
+    let cancel = cancel.clone();
+    tokio::task::spawn_blocking(move || strip_blocking(&src, &cancel))
+        .await
+        .map_err(|e| format!("Metadata stripping failed: {}", e))?
+}
+
+fn strip_blocking(src: &Path, cancel: &CancellationToken) -> Result<Option<Sanitized>, String> {
+    let format = match detect(src)? {
+        Some(format) => format,
+        None => return Ok(None),
+    };
+
+    let dir = tempfile::Builder::new()
+        .prefix("b2upload_")
+        .tempdir()
+        .map_err(|e| format!("Failed to create temp dir: {}", e))?;
+    let name = src.file_name().and_then(|n| n.to_str()).unwrap_or("file");
+    let path = dir.path().join(name);
+
+    let removed = match format {
+        Format::Jpeg => rewrite(src, &path, strip_jpeg)?,
+        Format::Png => rewrite(src, &path, strip_png)?,
+        Format::WebP => rewrite(src, &path, strip_webp)?,
+        Format::Pdf => strip_pdf(src, &path)?,
+        Format::IsoMedia => {
+            // Boxes are blanked in place, so offsets in the file stay valid
+            fs::copy(src, &path).map_err(|e| format!("Failed to copy file: {}", e))?;
+            if cancel.is_cancelled() {
+                return Err(CANCELLED.to_string());
+            }
+            strip_iso_media(&path)?
+        }
+    };
+
+    Ok(Some(Sanitized {
+        _dir: dir,
+        path,
+        removed,
+    }))
+}
+
+/// Identify the format from the file's first bytes.
+fn detect(path: &Path) -> Result<Option<Format>, String> {
+    let mut head = [0u8; 12];
+    let mut file = fs::File::open(path).map_err(|e| format!("Failed to open file: {}", e))?;
+    let n = file
+        .read(&mut head)
+        .map_err(|e| format!("Failed to read file: {}", e))?;
+    let head = &head[..n];
+
+    let format = if head.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Format::Jpeg
+    } else if head.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Format::Png
+    } else if head.starts_with(b"RIFF") && head.get(8..12) == Some(b"WEBP") {
+        Format::WebP
+    } else if head.starts_with(b"%PDF-") {
+        Format::Pdf
+    } else if head.get(4..8) == Some(b"ftyp") {
+        if is_heif(head) {
+            return Err("Metadata can't be stripped from HEIC/AVIF images; convert the image or turn off metadata stripping".to_string());
+        }
+        Format::IsoMedia
+    } else if matches!(head.get(4..8), Some(b"moov" | b"mdat" | b"wide")) {
+        // QuickTime files from before ftyp
+        Format::IsoMedia
+    } else {
+        return Ok(None);
+    };
+    Ok(Some(format))
+}
+
+type Stripper = fn(&[u8]) -> Result<(Vec<u8>, Vec<&'static str>), String>;
+
+/// Run an in-memory stripper over a whole file. Used for images, which
+/// are small enough to hold.
+fn rewrite(src: &Path, dst: &Path, strip: Stripper) -> Result<Vec<&'static str>, String> {
+    let data = fs::read(src).map_err(|e| format!("Failed to read file: {}", e))?;
+    let (clean, removed) = strip(&data)?;
+    fs::write(dst, clean).map_err(|e| format!("Failed to write temp file: {}", e))?;
+    Ok(removed)
+}
+
+fn note(removed: &mut Vec<&'static str>, what: &'static str) {
+    if !removed.contains(&what) {
+        removed.push(what);
+    }
+}
+
+// --- EXIF orientation ---
+
+/// Orientation (1-8) from a TIFF-structured EXIF block, if set.
+fn tiff_orientation(tiff: &[u8]) -> Option<u16> {
+    let big_endian = match tiff.get(..2)? {
+        b"MM" => true,
+        b"II" => false,
+        _ => return None,
+    };
+    let u16_at = |i: usize| {
+        let b: [u8; 2] = tiff.get(i..i + 2)?.try_into().ok()?;
+        Some(if big_endian { u16::from_be_bytes(b) } else { u16::from_le_bytes(b) })
+    };
+    let u32_at = |i: usize| {
+        let b: [u8; 4] = tiff.get(i..i + 4)?.try_into().ok()?;
+        Some(if big_endian { u32::from_be_bytes(b) } else { u32::from_le_bytes(b) })
+    };
+
+    let ifd = u32_at(4)? as usize;
+    for n in 0..u16_at(ifd)? as usize {
+        let entry = ifd + 2 + n * 12;
+        if u16_at(entry)? == EXIF_ORIENTATION && u16_at(entry + 2)? == TIFF_SHORT {
+            return u16_at(entry + 8).filter(|o| (1..=8).contains(o));
+        }
+    }
+    None
+}
+
+/// A TIFF block holding nothing but the orientation tag.
+fn orientation_only_tiff(orientation: u16) -> Vec<u8> {
+    let mut tiff = b"MM\0\x2a".to_vec();
+    tiff.extend(8u32.to_be_bytes()); // IFD0 right after the header
+    tiff.extend(1u16.to_be_bytes()); // one entry
+    tiff.extend(EXIF_ORIENTATION.to_be_bytes());
+    tiff.extend(TIFF_SHORT.to_be_bytes());
+    tiff.extend(1u32.to_be_bytes());
+    tiff.extend(orientation.to_be_bytes());
+    tiff.extend([0, 0]);
+    tiff.extend(0u32.to_be_bytes()); // no next IFD
+    tiff
+}
+
+/// The orientation-only replacement for an EXIF block, or None when the
+/// image is upright anyway.
+fn kept_orientation(tiff: &[u8]) -> Option<Vec<u8>> {
+    tiff_orientation(tiff)
+        .filter(|o| *o != 1)
+        .map(orientation_only_tiff)
+}
+
+// --- JPEG ---
+
+/// Drop EXIF (keeping orientation), XMP, IPTC, comments, maker segments
+/// and anything after the end of the image, which is where phones append
+/// preview images with their own EXIF.
+fn strip_jpeg(data: &[u8]) -> Result<(Vec<u8>, Vec<&'static str>), String> {
+    let malformed = || "Malformed JPEG".to_string();
+    let mut out = Vec::with_capacity(data.len());
+    let mut removed = Vec::new();
+    out.extend_from_slice(&data[..2]);
+    let mut pos = 2;
+
+    loop {
+        if data.get(pos) != Some(&0xFF) {
+            return Err(malformed());
+        }
+        // Markers may be preceded by 0xFF fill bytes
+        while data.get(pos + 1) == Some(&0xFF) {
+            pos += 1;
+        }
+        let marker = *data.get(pos + 1).ok_or_else(malformed)?;
+        match marker {
+            0xD9 => {
+                out.extend_from_slice(&data[pos..pos + 2]);
+                if data.len() > pos + 2 {
+                    note(&mut removed, "trailing data");
+                }
+                break;
+            }
+            0x01 | 0xD0..=0xD7 => {
+                out.extend_from_slice(&data[pos..pos + 2]);
+                pos += 2;
+                continue;
+            }
+            _ => {}
+        }
+
+        let len = data
+            .get(pos + 2..pos + 4)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]) as usize)
+            .ok_or_else(malformed)?;
+        let end = pos + 2 + len;
+        if len < 2 || end > data.len() {
+            return Err(malformed());
+        }
+        let payload = &data[pos + 4..end];
+        match marker {
+            0xE1 if payload.starts_with(EXIF_HEADER) => {
+                note(&mut removed, "EXIF");
+                if let Some(tiff) = kept_orientation(&payload[EXIF_HEADER.len()..]) {
+                    let segment_len = (2 + EXIF_HEADER.len() + tiff.len()) as u16;
+                    out.extend_from_slice(&[0xFF, 0xE1]);
+                    out.extend(segment_len.to_be_bytes());
+                    out.extend_from_slice(EXIF_HEADER);
+                    out.extend(tiff);
+                }
+            }
+            0xE1 if payload.starts_with(b"http://ns.adobe.com/") => note(&mut removed, "XMP"),
+            0xE2 if payload.starts_with(b"MPF\0") => note(&mut removed, "embedded previews"),
+            0xED => note(&mut removed, "IPTC"),
+            0xFE => note(&mut removed, "comment"),
+            // APP0 (JFIF), APP2 (ICC profile) and APP14 (Adobe color
+            // transform) change how the image looks
+            0xE0 | 0xE2 | 0xEE => out.extend_from_slice(&data[pos..end]),
+            0xE1..=0xEF => note(&mut removed, "maker data"),
+            _ => out.extend_from_slice(&data[pos..end]),
+        }
+        pos = end;
+
+        if marker == 0xDA {
+            // Entropy-coded data runs to the next marker that isn't a
+            // stuffed 0xFF00 or a restart marker
+            let mut i = pos;
+            loop {
+                match (data.get(i), data.get(i + 1)) {
+                    (None, _) | (Some(0xFF), None) => {
+                        // Truncated file: keep what's there
+                        out.extend_from_slice(&data[pos..]);
+                        return Ok((out, removed));
+                    }
+                    (Some(0xFF), Some(0x00 | 0xD0..=0xD7)) => i += 2,
+                    (Some(0xFF), Some(0xFF)) => i += 1,
+                    (Some(0xFF), Some(_)) => break,
+                    _ => i += 1,
+                }
+            }
+            out.extend_from_slice(&data[pos..i]);
+            pos = i;
+        }
+    }
+    Ok((out, removed))
+}
+
+// --- PNG ---
+
+/// Drop text chunks (author, software, comments), timestamps and EXIF,
+/// keeping orientation.
+fn strip_png(data: &[u8]) -> Result<(Vec<u8>, Vec<&'static str>), String> {
+    let malformed = || "Malformed PNG".to_string();
+    let mut out = Vec::with_capacity(data.len());
+    let mut removed = Vec::new();
+    out.extend_from_slice(&data[..8]);
+    let mut pos = 8;
+
+    while pos < data.len() {
+        let len = data
+            .get(pos..pos + 4)
+            .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize)
+            .ok_or_else(malformed)?;
+        let end = pos + 12 + len;
+        if end > data.len() {
+            return Err(malformed());
+        }
+        let kind = &data[pos + 4..pos + 8];
+        match kind {
+            b"tEXt" | b"zTXt" | b"iTXt" => note(&mut removed, "text"),
+            b"tIME" => note(&mut removed, "timestamp"),
+            b"eXIf" => {
+                note(&mut removed, "EXIF");
+                if let Some(tiff) = kept_orientation(&data[pos + 8..pos + 8 + len]) {
+                    out.extend((tiff.len() as u32).to_be_bytes());
+                    let start = out.len();
+                    out.extend_from_slice(b"eXIf");
+                    out.extend(tiff);
+                    let crc = crc32fast::hash(&out[start..]);
+                    out.extend(crc.to_be_bytes());
+                }
+            }
+            _ => out.extend_from_slice(&data[pos..end]),
+        }
+        pos = end;
+        if kind == b"IEND" {
+            if pos < data.len() {
+                note(&mut removed, "trailing data");
+            }
+            break;
+        }
+    }
+    Ok((out, removed))
+}
+
+// --- WebP ---
+
+const VP8X_FLAG_EXIF: u8 = 0x08;
+const VP8X_FLAG_XMP: u8 = 0x04;
+
+/// Drop the EXIF (keeping orientation) and XMP chunks and clear their
+/// flags in the VP8X header.
+fn strip_webp(data: &[u8]) -> Result<(Vec<u8>, Vec<&'static str>), String> {
+    let malformed = || "Malformed WebP".to_string();
+    let riff_size = data
+        .get(4..8)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize)
+        .ok_or_else(malformed)?;
+    let riff_end = (8 + riff_size).min(data.len());
+    let mut out = Vec::with_capacity(data.len());
+    let mut removed = Vec::new();
+    out.extend_from_slice(&data[..12]);
+    let mut pos = 12;
+    let mut vp8x_at = None;
+    let mut kept_exif = false;
+
+    while pos + 8 <= riff_end {
+        let size = u32::from_le_bytes([data[pos + 4], data[pos + 5], data[pos + 6], data[pos + 7]]) as usize;
+        // Chunks are padded to an even length
+        let end = pos + 8 + size + (size & 1);
+        if pos + 8 + size > data.len() {
+            return Err(malformed());
+        }
+        let end = end.min(data.len());
+        match &data[pos..pos + 4] {
+            b"EXIF" => {
+                note(&mut removed, "EXIF");
+                let exif = &data[pos + 8..pos + 8 + size];
+                let tiff = exif.strip_prefix(EXIF_HEADER).unwrap_or(exif);
+                if let Some(tiff) = kept_orientation(tiff) {
+                    out.extend_from_slice(b"EXIF");
+                    out.extend((tiff.len() as u32).to_le_bytes());
+                    out.extend(tiff);
+                    kept_exif = true;
+                }
+            }
+            b"XMP " => note(&mut removed, "XMP"),
+            kind => {
+                if kind == b"VP8X" {
+                    vp8x_at = Some(out.len());
+                }
+                out.extend_from_slice(&data[pos..end]);
+            }
+        }
+        pos = end;
+    }
+    if pos < data.len() {
+        note(&mut removed, "trailing data");
+    }
+
+    if let Some(at) = vp8x_at {
+        let flags = &mut out[at + 8];
+        *flags &= !VP8X_FLAG_XMP;
+        if !kept_exif {
+            *flags &= !VP8X_FLAG_EXIF;
+        }
+    }
+    let riff_size = (out.len() - 8) as u32;
+    out[4..8].copy_from_slice(&riff_size.to_le_bytes());
+    Ok((out, removed))
+}
+
+// --- PDF ---
+
+/// Drop the document info dictionary (author, creator tool, dates), XMP
+/// metadata streams and application data, then write a fresh file so no
+/// earlier revision keeps them.
+fn strip_pdf(src: &Path, dst: &Path) -> Result<Vec<&'static str>, String> {
+    let mut doc = lopdf::Document::load(src).map_err(|e| format!("Failed to read PDF: {}", e))?;
+    if doc.is_encrypted() {
+        return Err("Metadata can't be stripped from encrypted PDFs".to_string());
+    }
+
+    let mut removed = Vec::new();
+    if doc.trailer.remove(b"Info").is_some() {
+        note(&mut removed, "document info");
+    }
+    for object in doc.objects.values_mut() {
+        let dict = match object {
+            Object::Dictionary(dict) => dict,
+            Object::Stream(stream) => &mut stream.dict,
+            _ => continue,
+        };
+        if dict.remove(b"Metadata").is_some() {
+            note(&mut removed, "XMP");
+        }
+        if dict.remove(b"PieceInfo").is_some() {
+            note(&mut removed, "application data");
+        }
+    }
+    doc.prune_objects();
+    doc.save(dst).map_err(|e| format!("Failed to write PDF: {}", e))?;
+    Ok(removed)
+}
+
+// --- MP4 / MOV ---
+
+/// Turn user data (`udta`, where cameras and phones put location, make and
+/// model) and metadata (`meta`) boxes under `moov` and each `trak` into
+/// zero-filled `free` boxes, plus top-level XMP. Sizes don't change, so
+/// sample offsets stay valid and the file is edited in place.
+fn strip_iso_media(path: &Path) -> Result<Vec<&'static str>, String> {
+    let mut file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+        .map_err(|e| format!("Failed to open temp file: {}", e))?;
+    let len = file
+        .metadata()
+        .map_err(|e| format!("Failed to stat temp file: {}", e))?
+        .len();
+    let mut removed = Vec::new();
+    blank_boxes(&mut file, 0, len, 0, &mut removed).map_err(|e| match e {
+        BoxError::Io(e) => format!("Failed to rewrite video: {}", e),
+        BoxError::Malformed => "Malformed MP4/MOV file".to_string(),
+    })?;
+    Ok(removed)
+}
+
+enum BoxError {
+    Io(std::io::Error),
+    Malformed,
+}
+
+impl From<std::io::Error> for BoxError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+fn blank_boxes(
+    file: &mut fs::File,
+    start: u64,
+    end: u64,
+    depth: u32,
+    removed: &mut Vec<&'static str>,
+) -> Result<(), BoxError> {
+    let mut pos = start;
+    while pos + 8 <= end {
+        let mut header = [0u8; 8];
+        file.seek(SeekFrom::Start(pos))?;
+        file.read_exact(&mut header)?;
+        let kind: [u8; 4] = [header[4], header[5], header[6], header[7]];
+        let (size, header_len) = match u32::from_be_bytes([header[0], header[1], header[2], header[3]]) {
+            // 64-bit size follows the type
+            1 => {
+                let mut large = [0u8; 8];
+                file.read_exact(&mut large)?;
+                (u64::from_be_bytes(large), 16)
+            }
+            // Runs to the end of the enclosing box
+            0 => (end - pos, 8),
+            n => (n as u64, 8),
+        };
+        if size < header_len || pos + size > end {
+            return Err(BoxError::Malformed);
+        }
+
+        match (&kind, depth) {
+            (b"moov", 0) | (b"trak", 1) => {
+                blank_boxes(file, pos + header_len, pos + size, depth + 1, removed)?;
+            }
+            (b"udta" | b"meta", 0..=2) => {
+                blank_box(file, pos, header_len, size)?;
+                note(removed, "user data");
+            }
+            (b"uuid", 0) if size >= header_len + 16 => {
+                let mut uuid = [0u8; 16];
+                file.read_exact(&mut uuid)?;
+                if uuid == XMP_UUID {
+                    blank_box(file, pos, header_len, size)?;
+                    note(removed, "XMP");
+                }
+            }
+            _ => {}
+        }
+        pos += size;
+    }
+    Ok(())
+}
+
+fn blank_box(file: &mut fs::File, pos: u64, header_len: u64, size: u64) -> Result<(), BoxError> {
+    file.seek(SeekFrom::Start(pos + 4))?;
+    file.write_all(b"free")?;
+    file.seek(SeekFrom::Start(pos + header_len))?;
+    let zeros = [0u8; 64 * 1024];
+    let mut left = size - header_len;
+    while left > 0 {
+        let n = left.min(zeros.len() as u64) as usize;
+        file.write_all(&zeros[..n])?;
+        left -= n as u64;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(marker: u8, payload: &[u8]) -> Vec<u8> {
+        let mut seg = vec![0xFF, marker];
+        seg.extend(((payload.len() + 2) as u16).to_be_bytes());
+        seg.extend_from_slice(payload);
+        seg
+    }
+
+    fn exif(orientation: u16) -> Vec<u8> {
+        let mut payload = EXIF_HEADER.to_vec();
+        payload.extend(orientation_only_tiff(orientation));
+        payload
+    }
+
+    /// SOI, the given segments, a scan with stuffed bytes and a restart
+    /// marker, EOI.
+    fn jpeg(segments: &[Vec<u8>]) -> Vec<u8> {
+        let mut data = vec![0xFF, 0xD8];
+        for seg in segments {
+            data.extend(seg);
+        }
+        data.extend(segment(0xDA, &[1, 1, 0, 0, 63, 0]));
+        data.extend([0x12, 0xFF, 0x00, 0x34, 0xFF, 0xD0, 0x56]);
+        data.extend([0xFF, 0xD9]);
+        data
+    }
+
+    #[test]
+    fn jpeg_keeps_only_orientation_from_exif() {
+        let jfif = segment(0xE0, b"JFIF\0\x01\x02\0\0\x01\0\x01\0\0");
+        let data = jpeg(&[
+            jfif.clone(),
+            segment(0xE1, &exif(6)),
+            segment(0xFE, b"shot on my phone"),
+            segment(0xED, b"Photoshop 3.0\0"),
+        ]);
+        let (clean, removed) = strip_jpeg(&data).unwrap();
+        assert_eq!(clean, jpeg(&[jfif, segment(0xE1, &exif(6))]));
+        assert_eq!(removed, ["EXIF", "comment", "IPTC"]);
+    }
+
+    #[test]
+    fn jpeg_drops_upright_exif_xmp_and_trailing_data() {
+        let mut xmp = b"http://ns.adobe.com/xap/1.0/\0".to_vec();
+        xmp.extend_from_slice(b"<x:xmpmeta/>");
+        let mut data = jpeg(&[segment(0xE1, &exif(1)), segment(0xE1, &xmp)]);
+        data.extend_from_slice(b"preview");
+        let (clean, removed) = strip_jpeg(&data).unwrap();
+        assert_eq!(clean, jpeg(&[]));
+        assert_eq!(removed, ["EXIF", "XMP", "trailing data"]);
+    }
+
+    #[test]
+    fn clean_jpeg_is_unchanged() {
+        let data = jpeg(&[segment(0xE2, b"ICC_PROFILE\0")]);
+        let (clean, removed) = strip_jpeg(&data).unwrap();
+        assert_eq!(clean, data);
+        assert!(removed.is_empty());
+    }
+
+    #[test]
+    fn malformed_jpeg_is_an_error() {
+        assert!(strip_jpeg(&[0xFF, 0xD8, 0x00]).is_err());
+        // Segment length past the end of the data
+        assert!(strip_jpeg(&[0xFF, 0xD8, 0xFF, 0xE1, 0x10, 0x00, 0x00]).is_err());
+    }
+
+    #[test]
+    fn reads_orientation_in_either_byte_order() {
+        assert_eq!(tiff_orientation(&orientation_only_tiff(8)), Some(8));
+        let mut little = b"II\x2a\0".to_vec();
+        little.extend(8u32.to_le_bytes());
+        little.extend(1u16.to_le_bytes());
+        little.extend(EXIF_ORIENTATION.to_le_bytes());
+        little.extend(TIFF_SHORT.to_le_bytes());
+        little.extend(1u32.to_le_bytes());
+        little.extend(3u16.to_le_bytes());
+        little.extend([0, 0, 0, 0, 0, 0]);
+        assert_eq!(tiff_orientation(&little), Some(3));
+        assert_eq!(tiff_orientation(b"XX\0\0"), None);
+    }
+
+    fn mp4_box(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut b = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+        b.extend_from_slice(kind);
+        b.extend_from_slice(body);
+        b
+    }
+
+    fn strip_mp4(data: &[u8]) -> (Vec<u8>, Result<Vec<&'static str>, String>) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("clip.mp4");
+        fs::write(&path, data).unwrap();
+        let result = strip_iso_media(&path);
+        (fs::read(&path).unwrap(), result)
+    }
+
+    #[test]
+    fn mp4_user_data_is_blanked_in_place() {
+        let udta = mp4_box(b"udta", b"\xa9xyz+51.5074-000.1278/");
+        let meta = mp4_box(b"meta", b"Apple iPhone");
+        let tkhd = mp4_box(b"tkhd", &[7; 12]);
+        let trak = mp4_box(b"trak", &[tkhd.clone(), meta.clone()].concat());
+        let moov = mp4_box(b"moov", &[udta.clone(), trak].concat());
+        let mut xmp_body = XMP_UUID.to_vec();
+        xmp_body.extend_from_slice(b"<x:xmpmeta/>");
+        let xmp = mp4_box(b"uuid", &xmp_body);
+        let ftyp = mp4_box(b"ftyp", b"isom\0\0\x02\0");
+        let mdat = mp4_box(b"mdat", b"udta meta frames");
+        let data = [ftyp.clone(), moov, xmp.clone(), mdat.clone()].concat();
+
+        let (clean, removed) = strip_mp4(&data);
+        assert_eq!(removed.unwrap(), ["user data", "XMP"]);
+
+        let free = |b: &[u8]| mp4_box(b"free", &vec![0; b.len() - 8]);
+        let trak = mp4_box(b"trak", &[tkhd, free(&meta)].concat());
+        let moov = mp4_box(b"moov", &[free(&udta), trak].concat());
+        assert_eq!(clean, [ftyp, moov, free(&xmp), mdat].concat());
+    }
+
+    #[test]
+    fn mp4_without_metadata_is_unchanged() {
+        let data = [mp4_box(b"ftyp", b"isom"), mp4_box(b"mdat", b"udta")].concat();
+        let (clean, removed) = strip_mp4(&data);
+        assert!(removed.unwrap().is_empty());
+        assert_eq!(clean, data);
+    }
+
+    #[test]
+    fn mp4_box_past_the_end_is_an_error() {
+        let mut data = mp4_box(b"ftyp", b"isom");
+        data.extend(100u32.to_be_bytes());
+        data.extend_from_slice(b"moov");
+        let (_, removed) = strip_mp4(&data);
+        assert_eq!(removed.unwrap_err(), "Malformed MP4/MOV file");
+    }
+
+    #[test]
+    fn detects_heif_brands() {
+        assert!(is_heif(b"\0\0\0\x18ftypheic"));
+        assert!(is_heif(b"\0\0\0\x18ftypavif"));
+        assert!(!is_heif(b"\0\0\0\x18ftypisom"));
+        assert!(!is_heif(b"\0\0"));
+    }
+}
@@ -0,0 +1,112 @@
+use aws_sdk_s3::types::ServerSideEncryption;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use md5::{Digest, Md5};
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+use crate::storage::B2Credentials;
+
+/// SSE-C keys are AES-256.
+const SSE_C_KEY_LEN: usize = 32;
+const SSE_C_ALGORITHM: &str = "AES256";
+
+/// How a destination's objects are encrypted at rest, as saved in
+/// destinations.json. The SSE-C key itself lives in the keyring.
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum EncryptionMode {
+    /// Whatever the bucket's default encryption says.
+    #[default]
+    None,
+    /// Server-managed keys (`x-amz-server-side-encryption: AES256`).
+    SseB2,
+    /// Customer-provided key, sent with every request that touches the object.
+    SseC,
+}
+
+/// Encryption parameters for one upload, with the SSE-C key resolved.
+#[derive(Clone, Default)]
+pub enum Encryption {
+    #[default]
+    None,
+    SseB2,
+    SseC {
+        /// Base64 of the raw 256-bit key, as the header carries it.
+        key: Zeroizing<String>,
+        /// Base64 MD5 of the raw key, so the server can detect a mangled key.
+        key_md5: String,
+    },
+}
+
+impl Encryption {
+    /// Look up the key for an SSE-C destination.
+    pub fn resolve(mode: EncryptionMode, creds: &B2Credentials, destination_id: &str) -> Result<Self, String> {
+        match mode {
+            EncryptionMode::None => Ok(Self::None),
+            EncryptionMode::SseB2 => Ok(Self::SseB2),
+            EncryptionMode::SseC => {
+                let key = creds.destination_sse_key(destination_id);
+                if key.is_empty() {
+                    return Err("This destination uses SSE-C but has no encryption key; set one in settings".to_string());
+                }
+                let raw = decode_key(key)?;
+                Ok(Self::SseC {
+                    key: Zeroizing::new(key.to_string()),
+                    key_md5: STANDARD.encode(Md5::digest(&*raw)),
+                })
+            }
+        }
+    }
+
+    pub fn mode(&self) -> EncryptionMode {
+        match self {
+            Self::None => EncryptionMode::None,
+            Self::SseB2 => EncryptionMode::SseB2,
+            Self::SseC { .. } => EncryptionMode::SseC,
+        }
+    }
+
+    /// `x-amz-server-side-encryption`, for requests that create an object.
+    pub fn server_side_encryption(&self) -> Option<ServerSideEncryption> {
+        matches!(self, Self::SseB2).then_some(ServerSideEncryption::Aes256)
+    }
+
+    /// `x-amz-server-side-encryption-customer-algorithm`.
+    pub fn customer_algorithm(&self) -> Option<String> {
+        matches!(self, Self::SseC { .. }).then(|| SSE_C_ALGORITHM.to_string())
+    }
+
+    /// `x-amz-server-side-encryption-customer-key`.
+    pub fn customer_key(&self) -> Option<String> {
+        match self {
+            Self::SseC { key, .. } => Some(key.to_string()),
+            _ => None,
+        }
+    }
+
+    /// `x-amz-server-side-encryption-customer-key-MD5`.
+    pub fn customer_key_md5(&self) -> Option<String> {
+        match self {
+            Self::SseC { key_md5, .. } => Some(key_md5.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// Decode a base64 SSE-C key and check it is 256 bits.
+pub fn decode_key(key: &str) -> Result<Zeroizing<Vec<u8>>, String> {
+    let raw = Zeroizing::new(
+        STANDARD
+            .decode(key.trim())
+            .map_err(|_| "SSE-C key must be base64".to_string())?,
+    );
+    if raw.len() != SSE_C_KEY_LEN {
+        return Err(format!(
+            "SSE-C key must be {} bytes (base64 of a 256-bit key), got {}",
+            SSE_C_KEY_LEN,
+            raw.len()
+        ));
+    }
+    Ok(raw)
+}
@@ -1,32 +1,79 @@
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::path::PathBuf;
 use tauri::{AppHandle, Manager};
-use zeroize::{Zeroize, ZeroizeOnDrop};
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+use crate::e2e::{self, E2eMode};
+use crate::headers::HeaderRules;
+use crate::transform::ImageRules;
+use crate::sse::{self, EncryptionMode};
 
 const SERVICE: &str = "b2upload";
 const SECRETS_ACCOUNT: &str = "secrets";
+const DEFAULT_PROFILE: &str = "default";
 
 // Keys stored in config.json (non-sensitive)
 const CONFIG_KEYS: &[&str] = &[
     "DOMAIN",
     "BUCKET_NAME",
     "S3_ENDPOINT",
-    "FOLDER_1",
-    "FOLDER_2",
+    "S3_PROVIDER",
+    "S3_REGION",
+    "S3_PORT",
+    "S3_SCHEME",
+    "S3_ADDRESSING",
+    "S3_CHECKSUMS",
     "DATE_FOLDERS",
     "UUID_FILENAMES",
     "OVERWRITE_UPLOADS",
+    "CONTENT_ADDRESSED",
+    "HASH_BEFORE_UPLOAD",
+    "STRIP_METADATA",
+    "IMAGE_VARIANTS",
+    "IMAGE_VARIANT_WIDTHS",
+    "IMAGE_THUMBNAIL_SIZE",
+    "COMPRESSION",
+    "BUNDLE_UPLOADS",
+    "SKIP_HIDDEN_FILES",
+    "FOLLOW_SYMLINKS",
     "TOKEN_MODE",
     "DEFAULT_TTL",
+    "PRESIGNED_DISPOSITION",
     "NOTIFICATIONS",
     "MULTIPART_PARALLELISM",
+    "MULTIPART_PART_SIZE",
+    "UPLOAD_RETRIES",
+    "UPLOAD_LIMIT_MBPS",
+    "UPLOAD_LIMIT_SCHEDULE",
 ];
 
 // --- B2Credentials: sensitive data with automatic zeroization ---
 
+/// Static URL token for one destination, keyed by destination ID.
+#[derive(Serialize, Deserialize, Clone, Zeroize, ZeroizeOnDrop)]
+pub struct DestinationToken {
+    pub id: String,
+    pub token: String,
+}
+
+/// Base64 SSE-C key for one destination, keyed by destination ID.
+#[derive(Serialize, Deserialize, Clone, Zeroize, ZeroizeOnDrop)]
+pub struct DestinationSseKey {
+    pub id: String,
+    pub key: String,
+}
+
+/// A named HMAC secret for dynamic-token links. The ID goes in the link as
+/// `kid`, so the Worker knows which key to check it against.
+#[derive(Serialize, Deserialize, Clone, Zeroize, ZeroizeOnDrop)]
+pub struct SigningKey {
+    pub id: String,
+    pub secret: String,
+}
+
 #[derive(Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
 pub struct B2Credentials {
     #[serde(default)]
@@ -34,16 +81,37 @@ pub struct B2Credentials {
     #[serde(default)]
     pub app_key: String,
     #[serde(default)]
-    pub folder_1_token: String,
+    pub destination_tokens: Vec<DestinationToken>,
     #[serde(default)]
-    pub folder_2_token: String,
+    pub destination_sse_keys: Vec<DestinationSseKey>,
+    /// Signs links without a `kid`: the only key before signing keys
+    /// existed, and still accepted by the Worker until it's removed.
     #[serde(default)]
     pub token_secret: String,
+    #[serde(default)]
+    pub signing_keys: Vec<SigningKey>,
+    /// ID of the signing key new links are signed with.
+    #[serde(default)]
+    pub active_signing_key: String,
+    /// This machine's age identity, for opening team-encrypted uploads.
+    #[serde(default)]
+    pub age_identity: String,
+    // Pre-destination token fields, read once and moved into
+    // `destination_tokens` under the migrated "folder1"/"folder2" IDs.
+    #[serde(default, skip_serializing)]
+    folder_1_token: String,
+    #[serde(default, skip_serializing)]
+    folder_2_token: String,
 }
 
 impl B2Credentials {
-    pub fn load() -> Result<Self, String> {
-        let entry = keyring::Entry::new(SERVICE, SECRETS_ACCOUNT)
+    /// Load the active profile's credentials.
+    pub fn load(app: &AppHandle) -> Result<Self, String> {
+        Self::load_for(&active_profile(app))
+    }
+
+    fn load_for(profile: &str) -> Result<Self, String> {
+        let entry = keyring::Entry::new(SERVICE, &secrets_account(profile))
             .map_err(|e| e.to_string())?;
 
         let mut raw_json = match entry.get_password() {
@@ -52,28 +120,81 @@ impl B2Credentials {
                 return Ok(Self {
                     key_id: String::new(),
                     app_key: String::new(),
+                    destination_tokens: Vec::new(),
+                    destination_sse_keys: Vec::new(),
+                    token_secret: String::new(),
+                    signing_keys: Vec::new(),
+                    active_signing_key: String::new(),
+                    age_identity: String::new(),
                     folder_1_token: String::new(),
                     folder_2_token: String::new(),
-                    token_secret: String::new(),
                 });
             }
             Err(e) => return Err(format!("Keyring read error: {}", e)),
         };
 
-        let creds: B2Credentials = serde_json::from_str(&raw_json)
+        let mut creds: B2Credentials = serde_json::from_str(&raw_json)
             .map_err(|e| format!("Secrets parse error: {}", e))?;
 
         // Wipe the raw JSON buffer immediately after parsing
         raw_json.zeroize();
 
+        creds.migrate_folder_tokens();
         Ok(creds)
     }
 
-    fn save(&self) -> Result<(), String> {
+    fn migrate_folder_tokens(&mut self) {
+        for (id, legacy) in [
+            (LEGACY_FOLDER_IDS[0], &mut self.folder_1_token),
+            (LEGACY_FOLDER_IDS[1], &mut self.folder_2_token),
+        ] {
+            if legacy.is_empty() {
+                continue;
+            }
+            if !self.destination_tokens.iter().any(|t| t.id == id) {
+                self.destination_tokens.push(DestinationToken {
+                    id: id.to_string(),
+                    token: legacy.clone(),
+                });
+            }
+            legacy.zeroize();
+        }
+    }
+
+    /// Static token for a destination; empty if none is set.
+    pub fn destination_token(&self, id: &str) -> &str {
+        self.destination_tokens
+            .iter()
+            .find(|t| t.id == id)
+            .map(|t| t.token.as_str())
+            .unwrap_or("")
+    }
+
+    /// SSE-C key for a destination; empty if none is set.
+    pub fn destination_sse_key(&self, id: &str) -> &str {
+        self.destination_sse_keys
+            .iter()
+            .find(|k| k.id == id)
+            .map(|k| k.key.as_str())
+            .unwrap_or("")
+    }
+
+    /// The key new dynamic-token links are signed with, as (kid, secret):
+    /// the active signing key, or TOKEN_SECRET without a kid while no
+    /// signing keys are set up. None if there is neither.
+    pub fn link_signing_key(&self) -> Option<(Option<&str>, &str)> {
+        match self.signing_keys.iter().find(|k| k.id == self.active_signing_key) {
+            Some(key) => Some((Some(key.id.as_str()), key.secret.as_str())),
+            None if !self.token_secret.is_empty() => Some((None, self.token_secret.as_str())),
+            None => None,
+        }
+    }
+
+    fn save(&self, app: &AppHandle) -> Result<(), String> {
         let mut raw_json = serde_json::to_string(self)
             .map_err(|e| e.to_string())?;
 
-        let entry = keyring::Entry::new(SERVICE, SECRETS_ACCOUNT)
+        let entry = keyring::Entry::new(SERVICE, &secrets_account(&active_profile(app)))
             .map_err(|e| e.to_string())?;
 
         let result = entry.set_password(&raw_json)
@@ -86,12 +207,153 @@ impl B2Credentials {
     }
 }
 
-// --- Config file helpers ---
+/// Keyring account holding a profile's secrets. The default profile keeps
+/// the account used before profiles existed.
+fn secrets_account(profile: &str) -> String {
+    if profile == DEFAULT_PROFILE {
+        SECRETS_ACCOUNT.to_string()
+    } else {
+        format!("{}:{}", SECRETS_ACCOUNT, profile)
+    }
+}
 
-fn config_path(app: &AppHandle) -> PathBuf {
+// --- Profiles ---
+
+/// A named account/bucket setup with its own config, destinations,
+/// history, upload journal and keyring entry.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Profile {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Profiles {
+    pub active: String,
+    pub profiles: Vec<Profile>,
+}
+
+fn profiles_path(app: &AppHandle) -> PathBuf {
     let dir = app.path().app_data_dir().expect("no app data dir");
     fs::create_dir_all(&dir).ok();
-    dir.join("config.json")
+    dir.join("profiles.json")
+}
+
+pub fn get_profiles(app: &AppHandle) -> Profiles {
+    let stored: Option<Profiles> = fs::read_to_string(profiles_path(app))
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok());
+    match stored {
+        Some(profiles) if !profiles.profiles.is_empty() => profiles,
+        _ => Profiles {
+            active: DEFAULT_PROFILE.to_string(),
+            profiles: vec![Profile {
+                id: DEFAULT_PROFILE.to_string(),
+                name: "Default".to_string(),
+            }],
+        },
+    }
+}
+
+fn write_profiles(app: &AppHandle, profiles: &Profiles) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(profiles).map_err(|e| e.to_string())?;
+    fs::write(profiles_path(app), json).map_err(|e| format!("Profiles write error: {}", e))
+}
+
+pub fn active_profile(app: &AppHandle) -> String {
+    get_profiles(app).active
+}
+
+/// Directory holding a profile's files. The default profile uses the app
+/// data dir itself, where these files lived before profiles existed.
+fn profile_dir_for(app: &AppHandle, profile: &str) -> PathBuf {
+    let dir = app.path().app_data_dir().expect("no app data dir");
+    if profile == DEFAULT_PROFILE {
+        dir
+    } else {
+        dir.join("profiles").join(profile)
+    }
+}
+
+fn profile_dir(app: &AppHandle) -> PathBuf {
+    let dir = profile_dir_for(app, &active_profile(app));
+    fs::create_dir_all(&dir).ok();
+    dir
+}
+
+pub fn switch_profile(app: &AppHandle, id: &str) -> Result<(), String> {
+    let mut profiles = get_profiles(app);
+    if !profiles.profiles.iter().any(|p| p.id == id) {
+        return Err(format!("Unknown profile: {}", id));
+    }
+    profiles.active = id.to_string();
+    write_profiles(app, &profiles)
+}
+
+/// Add an empty profile and make it active. Returns its ID.
+pub fn create_profile(app: &AppHandle, name: &str) -> Result<String, String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("Profile name is required".to_string());
+    }
+    let mut profiles = get_profiles(app);
+    let id = uuid::Uuid::new_v4().simple().to_string()[..8].to_string();
+    profiles.profiles.push(Profile {
+        id: id.clone(),
+        name: name.to_string(),
+    });
+    profiles.active = id.clone();
+    write_profiles(app, &profiles)?;
+    Ok(id)
+}
+
+pub fn rename_profile(app: &AppHandle, id: &str, name: &str) -> Result<(), String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("Profile name is required".to_string());
+    }
+    let mut profiles = get_profiles(app);
+    let profile = profiles
+        .profiles
+        .iter_mut()
+        .find(|p| p.id == id)
+        .ok_or_else(|| format!("Unknown profile: {}", id))?;
+    profile.name = name.to_string();
+    write_profiles(app, &profiles)
+}
+
+/// Remove a profile with its files and keyring entry. The active profile
+/// can't be deleted; switch away from it first.
+pub fn delete_profile(app: &AppHandle, id: &str) -> Result<(), String> {
+    let mut profiles = get_profiles(app);
+    if profiles.active == id {
+        return Err("Switch to another profile before deleting this one".to_string());
+    }
+    if id == DEFAULT_PROFILE {
+        return Err("The default profile can't be deleted".to_string());
+    }
+    let before = profiles.profiles.len();
+    profiles.profiles.retain(|p| p.id != id);
+    if profiles.profiles.len() == before {
+        return Err(format!("Unknown profile: {}", id));
+    }
+
+    let entry = keyring::Entry::new(SERVICE, &secrets_account(id)).map_err(|e| e.to_string())?;
+    match entry.delete_credential() {
+        Ok(()) | Err(keyring::Error::NoEntry) => {}
+        Err(e) => return Err(format!("Keyring delete error: {}", e)),
+    }
+    let dir = profile_dir_for(app, id);
+    if dir.exists() {
+        fs::remove_dir_all(&dir).map_err(|e| format!("Failed to remove profile files: {}", e))?;
+    }
+    write_profiles(app, &profiles)
+}
+
+// --- Config file helpers ---
+
+fn config_path(app: &AppHandle) -> PathBuf {
+    profile_dir(app).join("config.json")
 }
 
 fn read_config(app: &AppHandle) -> HashMap<String, String> {
@@ -112,8 +374,6 @@ fn write_config(app: &AppHandle, config: &HashMap<String, String>) -> Result<(),
 const SECRET_KEYS: &[&str] = &[
     "B2_APPLICATION_KEY_ID",
     "B2_APPLICATION_KEY",
-    "FOLDER_1_TOKEN",
-    "FOLDER_2_TOKEN",
     "TOKEN_SECRET",
 ];
 
@@ -138,7 +398,7 @@ pub fn save_settings(app: &AppHandle, mut values: HashMap<String, String>) -> Re
     let mut config: HashMap<String, String> = HashMap::new();
 
     // Load existing credentials so empty fields preserve current values
-    let existing = B2Credentials::load()?;
+    let existing = B2Credentials::load(app)?;
 
     // Helper: use new value if non-empty, otherwise keep existing
     let merge = |new: Option<&String>, existing: &str| -> String {
@@ -151,9 +411,14 @@ pub fn save_settings(app: &AppHandle, mut values: HashMap<String, String>) -> Re
     let creds = B2Credentials {
         key_id: merge(values.get("B2_APPLICATION_KEY_ID"), &existing.key_id),
         app_key: merge(values.get("B2_APPLICATION_KEY"), &existing.app_key),
-        folder_1_token: merge(values.get("FOLDER_1_TOKEN"), &existing.folder_1_token),
-        folder_2_token: merge(values.get("FOLDER_2_TOKEN"), &existing.folder_2_token),
+        destination_tokens: existing.destination_tokens.clone(),
+        destination_sse_keys: existing.destination_sse_keys.clone(),
         token_secret: merge(values.get("TOKEN_SECRET"), &existing.token_secret),
+        signing_keys: existing.signing_keys.clone(),
+        active_signing_key: existing.active_signing_key.clone(),
+        age_identity: existing.age_identity.clone(),
+        folder_1_token: String::new(),
+        folder_2_token: String::new(),
     };
     // existing is dropped here -> ZeroizeOnDrop wipes fields
     drop(existing);
@@ -165,7 +430,7 @@ pub fn save_settings(app: &AppHandle, mut values: HashMap<String, String>) -> Re
     }
 
     write_config(app, &config)?;
-    creds.save()?;
+    creds.save(app)?;
     // creds is dropped here -> ZeroizeOnDrop wipes fields
 
     // Zeroize any secret values in the incoming HashMap
@@ -188,13 +453,11 @@ pub fn get_settings(app: &AppHandle) -> Result<HashMap<String, String>, String>
 
 /// Returns the names of secret keys that have stored (non-empty) values.
 /// The actual secret values are never exposed.
-pub fn get_saved_secret_keys() -> Result<Vec<String>, String> {
-    let creds = B2Credentials::load()?;
+pub fn get_saved_secret_keys(app: &AppHandle) -> Result<Vec<String>, String> {
+    let creds = B2Credentials::load(app)?;
     let mut keys = Vec::new();
     if !creds.key_id.is_empty() { keys.push("B2_APPLICATION_KEY_ID".to_string()); }
     if !creds.app_key.is_empty() { keys.push("B2_APPLICATION_KEY".to_string()); }
-    if !creds.folder_1_token.is_empty() { keys.push("FOLDER_1_TOKEN".to_string()); }
-    if !creds.folder_2_token.is_empty() { keys.push("FOLDER_2_TOKEN".to_string()); }
     if !creds.token_secret.is_empty() { keys.push("TOKEN_SECRET".to_string()); }
     // creds is dropped here -> ZeroizeOnDrop wipes fields
     Ok(keys)
@@ -202,19 +465,381 @@ pub fn get_saved_secret_keys() -> Result<Vec<String>, String> {
 
 pub fn has_settings(app: &AppHandle) -> Result<bool, String> {
     let config = read_config(app);
-    // Check non-sensitive connection keys from config
+    // Check non-sensitive connection keys from config; presigned and B2
+    // download links go straight to the bucket and need no DOMAIN
+    let no_worker = matches!(config.get("TOKEN_MODE").map(|s| s.as_str()), Some("presigned" | "b2"));
     let config_ok = ["DOMAIN", "BUCKET_NAME", "S3_ENDPOINT"].iter().all(|k| {
-        config.get(*k).map(|v| !v.is_empty()).unwrap_or(false)
+        (no_worker && *k == "DOMAIN") || config.get(*k).map(|v| !v.is_empty()).unwrap_or(false)
     });
     if !config_ok {
         return Ok(false);
     }
     // Check sensitive connection keys from credentials
-    let creds = B2Credentials::load()?;
+    let creds = B2Credentials::load(app)?;
     Ok(!creds.key_id.is_empty() && !creds.app_key.is_empty())
     // creds is dropped here -> ZeroizeOnDrop wipes fields
 }
 
+// --- Upload destinations ---
+
+// IDs given to the two fixed folders when they become destinations, so
+// history entries and journals written before the migration still match.
+const LEGACY_FOLDER_IDS: [&str; 2] = ["folder1", "folder2"];
+
+/// A named place uploads can go: a key prefix, an optional key template
+/// and (in the credentials) an optional static URL token.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Destination {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub prefix: String,
+    #[serde(default)]
+    pub key_template: String,
+    /// Header rules, one `[when] Header: value` per line; see `HeaderRules`.
+    #[serde(default)]
+    pub headers: String,
+    /// Image conversion rules, one `[when] action` per line; see `ImageRules`.
+    #[serde(default)]
+    pub image_rules: String,
+    #[serde(default)]
+    pub encryption: EncryptionMode,
+    /// Client-side encryption, applied before server-side encryption.
+    #[serde(default)]
+    pub e2e: E2eMode,
+    /// Team public keys (`age1…`), one per line.
+    #[serde(default)]
+    pub recipients: String,
+}
+
+/// A destination as edited in the settings form. An empty token or SSE-C
+/// key keeps the stored one, like the other secret fields.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DestinationInput {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub prefix: String,
+    #[serde(default)]
+    pub key_template: String,
+    #[serde(default)]
+    pub headers: String,
+    #[serde(default)]
+    pub image_rules: String,
+    #[serde(default)]
+    pub encryption: EncryptionMode,
+    #[serde(default)]
+    pub e2e: E2eMode,
+    #[serde(default)]
+    pub recipients: String,
+    #[serde(default)]
+    pub token: String,
+    #[serde(default)]
+    pub sse_key: String,
+}
+
+fn destinations_path(app: &AppHandle) -> PathBuf {
+    profile_dir(app).join("destinations.json")
+}
+
+/// Build destinations from the FOLDER_1 / FOLDER_2 settings that predate
+/// the destination list.
+fn migrate_folders(config: &HashMap<String, String>) -> Vec<Destination> {
+    LEGACY_FOLDER_IDS
+        .iter()
+        .enumerate()
+        .map(|(i, id)| {
+            let n = i + 1;
+            let prefix = config
+                .get(&format!("FOLDER_{}", n))
+                .cloned()
+                .unwrap_or_default();
+            let mut chars = prefix.chars();
+            let name = match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => format!("Folder {}", n),
+            };
+            Destination {
+                id: id.to_string(),
+                name,
+                prefix,
+                key_template: config
+                    .get(&format!("FOLDER_{}_KEY_TEMPLATE", n))
+                    .cloned()
+                    .unwrap_or_default(),
+                headers: String::new(),
+                image_rules: String::new(),
+                encryption: EncryptionMode::None,
+                e2e: E2eMode::Off,
+                recipients: String::new(),
+            }
+        })
+        .collect()
+}
+
+pub fn get_destinations(app: &AppHandle) -> Vec<Destination> {
+    let path = destinations_path(app);
+    if let Ok(content) = fs::read_to_string(&path) {
+        return serde_json::from_str(&content).unwrap_or_default();
+    }
+    // First run after the upgrade: persist the migrated list right away,
+    // since the next settings save drops the FOLDER_* keys from config.json.
+    let destinations = migrate_folders(&read_config(app));
+    if let Err(e) = write_destinations(app, &destinations) {
+        eprintln!("[destinations] Failed to save migrated folders: {}", e);
+    }
+    destinations
+}
+
+fn write_destinations(app: &AppHandle, destinations: &[Destination]) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(destinations).map_err(|e| e.to_string())?;
+    fs::write(destinations_path(app), json)
+        .map_err(|e| format!("Destinations write error: {}", e))
+}
+
+pub fn find_destination(app: &AppHandle, id: &str) -> Result<Destination, String> {
+    get_destinations(app)
+        .into_iter()
+        .find(|d| d.id == id)
+        .ok_or_else(|| format!("Unknown destination: {}", id))
+}
+
+/// IDs of destinations that have a stored static token.
+pub fn get_destination_token_ids(app: &AppHandle) -> Result<Vec<String>, String> {
+    let creds = B2Credentials::load(app)?;
+    Ok(creds
+        .destination_tokens
+        .iter()
+        .filter(|t| !t.token.is_empty())
+        .map(|t| t.id.clone())
+        .collect())
+}
+
+/// IDs of destinations that have a stored SSE-C key.
+pub fn get_destination_sse_key_ids(app: &AppHandle) -> Result<Vec<String>, String> {
+    let creds = B2Credentials::load(app)?;
+    Ok(creds
+        .destination_sse_keys
+        .iter()
+        .filter(|k| !k.key.is_empty())
+        .map(|k| k.id.clone())
+        .collect())
+}
+
+/// Public key of this machine's age identity, creating the identity on
+/// first use. Team members add it to a destination's recipients so they
+/// can open each other's uploads.
+pub fn age_recipient(app: &AppHandle) -> Result<String, String> {
+    let mut creds = B2Credentials::load(app)?;
+    if creds.age_identity.is_empty() {
+        creds.age_identity = e2e::generate_identity().to_string();
+        creds.save(app)?;
+    }
+    e2e::recipient_of(&creds.age_identity)
+    // creds is dropped here -> ZeroizeOnDrop wipes fields
+}
+
+/// Replace the destination list. Tokens and SSE-C keys of removed
+/// destinations are wiped.
+pub fn save_destinations(app: &AppHandle, mut inputs: Vec<DestinationInput>) -> Result<(), String> {
+    if inputs.is_empty() {
+        return Err("At least one destination is required".to_string());
+    }
+    let mut creds = B2Credentials::load(app)?;
+    let mut destinations: Vec<Destination> = Vec::with_capacity(inputs.len());
+    for input in &inputs {
+        let name = input.name.trim();
+        if input.id.is_empty() || name.is_empty() {
+            return Err("Every destination needs a name".to_string());
+        }
+        if destinations.iter().any(|d| d.id == input.id) {
+            return Err(format!("Duplicate destination ID: {}", input.id));
+        }
+        HeaderRules::parse(&input.headers).map_err(|e| format!("{}: {}", name, e))?;
+        ImageRules::parse(&input.image_rules).map_err(|e| format!("{}: {}", name, e))?;
+        if !input.sse_key.is_empty() {
+            sse::decode_key(&input.sse_key).map_err(|e| format!("{}: {}", name, e))?;
+        }
+        if input.encryption == EncryptionMode::SseC
+            && input.sse_key.is_empty()
+            && creds.destination_sse_key(&input.id).is_empty()
+        {
+            return Err(format!("{}: SSE-C needs an encryption key", name));
+        }
+        let recipients = e2e::parse_recipients(&input.recipients).map_err(|e| format!("{}: {}", name, e))?;
+        if input.e2e == E2eMode::Team && recipients.is_empty() {
+            return Err(format!("{}: team encryption needs at least one recipient key", name));
+        }
+        destinations.push(Destination {
+            id: input.id.clone(),
+            name: name.to_string(),
+            prefix: input.prefix.trim().trim_matches('/').to_string(),
+            key_template: input.key_template.trim().to_string(),
+            headers: input.headers.trim().to_string(),
+            image_rules: input.image_rules.trim().to_string(),
+            encryption: input.encryption,
+            e2e: input.e2e,
+            recipients: input.recipients.trim().to_string(),
+        });
+    }
+
+    creds
+        .destination_tokens
+        .retain(|t| destinations.iter().any(|d| d.id == t.id));
+    for input in inputs.iter().filter(|i| !i.token.is_empty()) {
+        match creds.destination_tokens.iter_mut().find(|t| t.id == input.id) {
+            Some(existing) => {
+                existing.token.zeroize();
+                existing.token = input.token.clone();
+            }
+            None => creds.destination_tokens.push(DestinationToken {
+                id: input.id.clone(),
+                token: input.token.clone(),
+            }),
+        }
+    }
+    creds
+        .destination_sse_keys
+        .retain(|k| destinations.iter().any(|d| d.id == k.id));
+    for input in inputs.iter().filter(|i| !i.sse_key.is_empty()) {
+        let key = input.sse_key.trim().to_string();
+        match creds.destination_sse_keys.iter_mut().find(|k| k.id == input.id) {
+            Some(existing) => {
+                existing.key.zeroize();
+                existing.key = key;
+            }
+            None => creds.destination_sse_keys.push(DestinationSseKey {
+                id: input.id.clone(),
+                key,
+            }),
+        }
+    }
+
+    write_destinations(app, &destinations)?;
+    creds.save(app)?;
+    // creds is dropped here -> ZeroizeOnDrop wipes fields
+
+    for input in &mut inputs {
+        input.token.zeroize();
+        input.sse_key.zeroize();
+    }
+    Ok(())
+}
+
+// --- Signing keys ---
+
+const MAX_SIGNING_KEY_ID_LEN: usize = 32;
+
+/// A signing key as edited in the settings form. An empty secret keeps the
+/// stored one.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SigningKeyInput {
+    pub id: String,
+    #[serde(default)]
+    pub secret: String,
+    #[serde(default)]
+    pub active: bool,
+}
+
+/// IDs of the stored signing keys and which one is active. Secrets never
+/// leave the backend.
+pub fn get_signing_keys(app: &AppHandle) -> Result<Vec<(String, bool)>, String> {
+    let creds = B2Credentials::load(app)?;
+    Ok(creds
+        .signing_keys
+        .iter()
+        .map(|k| (k.id.clone(), k.id == creds.active_signing_key))
+        .collect())
+}
+
+/// Replace the signing keys. Keys left out are retired: their secrets are
+/// wiped and links signed with them stop working once the Worker drops them
+/// too. Exactly one key must be active if there are any.
+pub fn save_signing_keys(app: &AppHandle, mut inputs: Vec<SigningKeyInput>) -> Result<(), String> {
+    let mut creds = B2Credentials::load(app)?;
+    let mut keys: Vec<SigningKey> = Vec::with_capacity(inputs.len());
+    for input in &inputs {
+        let id = input.id.trim();
+        if id.is_empty()
+            || id.len() > MAX_SIGNING_KEY_ID_LEN
+            || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(format!(
+                "Signing key IDs are 1-{} letters, digits, - or _, got \"{}\"",
+                MAX_SIGNING_KEY_ID_LEN, id
+            ));
+        }
+        if keys.iter().any(|k| k.id == id) {
+            return Err(format!("Duplicate signing key ID: {}", id));
+        }
+        let secret = if input.secret.is_empty() {
+            creds
+                .signing_keys
+                .iter()
+                .find(|k| k.id == id)
+                .map(|k| k.secret.clone())
+                .ok_or_else(|| format!("Signing key {} needs a secret", id))?
+        } else {
+            input.secret.clone()
+        };
+        keys.push(SigningKey {
+            id: id.to_string(),
+            secret,
+        });
+    }
+    let active: Vec<&SigningKeyInput> = inputs.iter().filter(|i| i.active).collect();
+    if !keys.is_empty() && active.len() != 1 {
+        return Err("Mark exactly one signing key as active".to_string());
+    }
+
+    creds.signing_keys.zeroize();
+    creds.signing_keys = keys;
+    creds.active_signing_key = active.first().map(|i| i.id.trim().to_string()).unwrap_or_default();
+    creds.save(app)?;
+    // creds is dropped here -> ZeroizeOnDrop wipes fields
+
+    for input in &mut inputs {
+        input.secret.zeroize();
+    }
+    Ok(())
+}
+
+/// The Worker's SIGNING_KEYS secret: a JSON object from key ID to secret,
+/// covering every key that hasn't been retired.
+pub fn worker_signing_keys(app: &AppHandle) -> Result<Zeroizing<String>, String> {
+    let creds = B2Credentials::load(app)?;
+    if creds.signing_keys.is_empty() {
+        return Err("No signing keys to export".to_string());
+    }
+    // Borrowed, so the only copy of the secrets is the zeroized output
+    let layout: BTreeMap<&str, &str> = creds
+        .signing_keys
+        .iter()
+        .map(|k| (k.id.as_str(), k.secret.as_str()))
+        .collect();
+    serde_json::to_string(&layout)
+        .map(Zeroizing::new)
+        .map_err(|e| e.to_string())
+    // creds is dropped here -> ZeroizeOnDrop wipes fields
+}
+
+// --- Multipart upload journal ---
+
+/// Directory holding one journal file per in-flight multipart upload.
+pub fn journal_dir(app: &AppHandle) -> PathBuf {
+    profile_dir(app).join("multipart")
+}
+
+// --- Key template counter ---
+
+/// File holding the last value handed out by the `{counter}` key token.
+pub fn key_counter_path(app: &AppHandle) -> PathBuf {
+    profile_dir(app).join("key_counter")
+}
+
 // --- History (JSON file) with mutex protection ---
 
 pub struct HistoryMutex(pub std::sync::Mutex<()>);
@@ -226,9 +851,7 @@ impl HistoryMutex {
 }
 
 fn history_path(app: &AppHandle) -> PathBuf {
-    let dir = app.path().app_data_dir().expect("no app data dir");
-    fs::create_dir_all(&dir).ok();
-    dir.join("history.json")
+    profile_dir(app).join("history.json")
 }
 
 pub fn get_history(app: &AppHandle) -> Vec<Value> {
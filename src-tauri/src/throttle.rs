@@ -0,0 +1,173 @@
+use chrono::{NaiveTime, Timelike};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// How much unused allowance can pile up, as a fraction of a second's worth
+// of bytes. Keeps bursts short after an idle period.
+const BURST_SECONDS: f64 = 0.25;
+// How often the active time window is re-checked.
+const SCHEDULE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A time-of-day window with its own rate limit, e.g. `19:00-07:00=0`.
+/// Windows that end before they start wrap past midnight.
+#[derive(Clone, PartialEq)]
+struct Window {
+    start_min: u32,
+    end_min: u32,
+    /// Bytes per second; 0 = unlimited.
+    limit: u64,
+}
+
+impl Window {
+    fn contains(&self, minute: u32) -> bool {
+        if self.start_min <= self.end_min {
+            minute >= self.start_min && minute < self.end_min
+        } else {
+            minute >= self.start_min || minute < self.end_min
+        }
+    }
+}
+
+struct State {
+    base_limit: u64,
+    windows: Vec<Window>,
+    /// Limit currently in force, in bytes per second; 0 = unlimited.
+    rate: u64,
+    /// Available allowance in bytes. Goes negative when callers reserve
+    /// more than is available; they then wait for it to refill.
+    tokens: f64,
+    last_refill: Instant,
+    last_schedule_check: Option<Instant>,
+}
+
+impl State {
+    fn burst(&self) -> f64 {
+        self.rate as f64 * BURST_SECONDS
+    }
+
+    fn apply_schedule(&mut self, now: Instant) {
+        let due = self
+            .last_schedule_check
+            .is_none_or(|t| now.duration_since(t) >= SCHEDULE_CHECK_INTERVAL);
+        if !due {
+            return;
+        }
+        self.last_schedule_check = Some(now);
+
+        let local = chrono::Local::now();
+        let minute = local.hour() * 60 + local.minute();
+        let rate = self
+            .windows
+            .iter()
+            .find(|w| w.contains(minute))
+            .map(|w| w.limit)
+            .unwrap_or(self.base_limit);
+        if rate != self.rate {
+            self.rate = rate;
+            self.tokens = 0.0;
+            self.last_refill = now;
+        }
+    }
+}
+
+/// Token bucket shared by every upload in the app, so the configured limit
+/// holds however many files and parts are in flight.
+pub struct Throttle {
+    state: Mutex<State>,
+}
+
+impl Throttle {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(State {
+                base_limit: 0,
+                windows: Vec::new(),
+                rate: 0,
+                tokens: 0.0,
+                last_refill: Instant::now(),
+                last_schedule_check: None,
+            }),
+        }
+    }
+
+    /// Pick up UPLOAD_LIMIT_MBPS and UPLOAD_LIMIT_SCHEDULE. Called when an
+    /// upload starts, so saved settings apply to the next upload.
+    pub fn configure(&self, config: &HashMap<String, String>) {
+        let base_limit = config
+            .get("UPLOAD_LIMIT_MBPS")
+            .and_then(|s| parse_mbps(s))
+            .unwrap_or(0);
+        let windows = config
+            .get("UPLOAD_LIMIT_SCHEDULE")
+            .map(|s| parse_schedule(s))
+            .unwrap_or_default();
+
+        let mut state = self.state.lock().unwrap();
+        if state.base_limit != base_limit || state.windows != windows {
+            state.base_limit = base_limit;
+            state.windows = windows;
+            state.last_schedule_check = None;
+        }
+    }
+
+    /// Take `bytes` from the bucket and return how long the caller must wait
+    /// before sending them.
+    pub fn reserve(&self, bytes: u64) -> Duration {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        state.apply_schedule(now);
+        if state.rate == 0 {
+            return Duration::ZERO;
+        }
+
+        let elapsed = now.duration_since(state.last_refill).as_secs_f64();
+        state.last_refill = now;
+        state.tokens = (state.tokens + elapsed * state.rate as f64).min(state.burst());
+        state.tokens -= bytes as f64;
+
+        if state.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-state.tokens / state.rate as f64)
+        }
+    }
+}
+
+/// Megabits per second to bytes per second. "0" and "" mean unlimited.
+fn parse_mbps(s: &str) -> Option<u64> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Some(0);
+    }
+    let mbps = s.parse::<f64>().ok().filter(|v| v.is_finite() && *v >= 0.0)?;
+    Some((mbps * 1_000_000.0 / 8.0) as u64)
+}
+
+fn parse_minute(s: &str) -> Option<u32> {
+    let time = NaiveTime::parse_from_str(s.trim(), "%H:%M").ok()?;
+    Some(time.hour() * 60 + time.minute())
+}
+
+/// Parse `HH:MM-HH:MM=MBPS` entries separated by commas. The first window
+/// containing the current time wins; malformed entries are skipped.
+fn parse_schedule(s: &str) -> Vec<Window> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .filter_map(|entry| {
+            let window = entry.split_once('=').and_then(|(range, limit)| {
+                let (start, end) = range.split_once('-')?;
+                Some(Window {
+                    start_min: parse_minute(start)?,
+                    end_min: parse_minute(end)?,
+                    limit: parse_mbps(limit)?,
+                })
+            });
+            if window.is_none() {
+                eprintln!("[throttle] Ignoring malformed schedule entry: {}", entry);
+            }
+            window
+        })
+        .collect()
+}
@@ -0,0 +1,523 @@
+use image::codecs::avif::AvifEncoder;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::metadata::Orientation;
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader};
+use serde::Serialize;
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use tokio_util::sync::CancellationToken;
+
+use crate::headers::{self, Condition};
+use crate::sanitize;
+use crate::uploader::CANCELLED;
+
+const DEFAULT_WEBP_QUALITY: u8 = 80;
+const DEFAULT_AVIF_QUALITY: u8 = 60;
+const DEFAULT_JPEG_QUALITY: u8 = 85;
+/// Quality for images that are only re-encoded to turn them upright.
+const UPRIGHT_QUALITY: u8 = 90;
+/// rav1e speed, 1 (slowest, smallest) to 10.
+const AVIF_SPEED: u8 = 6;
+/// oxipng preset, 0 (fastest) to 6; 2 is its own default.
+const OPTIMIZE_PRESET: u8 = 2;
+/// Widest and tallest image libwebp will encode.
+const WEBP_MAX_DIMENSION: u32 = 16383;
+
+/// What an image can be converted to.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    Jpeg,
+    Png,
+    WebP,
+    Avif,
+}
+
+impl OutputFormat {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "jpeg" | "jpg" => Some(Self::Jpeg),
+            "png" => Some(Self::Png),
+            "webp" => Some(Self::WebP),
+            "avif" => Some(Self::Avif),
+            _ => None,
+        }
+    }
+
+    fn of(format: ImageFormat) -> Option<Self> {
+        match format {
+            ImageFormat::Jpeg => Some(Self::Jpeg),
+            ImageFormat::Png => Some(Self::Png),
+            ImageFormat::WebP => Some(Self::WebP),
+            _ => None,
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Jpeg => "jpg",
+            Self::Png => "png",
+            Self::WebP => "webp",
+            Self::Avif => "avif",
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Self::Jpeg => "JPEG",
+            Self::Png => "PNG",
+            Self::WebP => "WebP",
+            Self::Avif => "AVIF",
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Quality {
+    /// 1-100.
+    Level(u8),
+    Lossless,
+}
+
+#[derive(Clone, Copy)]
+struct Conversion {
+    format: OutputFormat,
+    quality: Quality,
+}
+
+impl Conversion {
+    /// Re-encode in the image's own format, as close to the original as it gets.
+    fn upright(format: OutputFormat, data: &[u8]) -> Self {
+        let quality = match format {
+            OutputFormat::Png => Quality::Lossless,
+            OutputFormat::WebP if is_lossless_webp(data) => Quality::Lossless,
+            _ => Quality::Level(UPRIGHT_QUALITY),
+        };
+        Self { format, quality }
+    }
+
+    fn describe(self, from: OutputFormat) -> String {
+        let quality = match (self.format, self.quality) {
+            (OutputFormat::Png, _) => String::new(),
+            (_, Quality::Lossless) => " (lossless)".to_string(),
+            (_, Quality::Level(q)) => format!(" (quality {})", q),
+        };
+        if self.format == from {
+            format!("re-encoded {}{}", from.label(), quality)
+        } else {
+            format!("converted {} to {}{}", from.label(), self.format.label(), quality)
+        }
+    }
+}
+
+#[derive(Clone)]
+enum Action {
+    /// None undoes an earlier conversion rule.
+    Convert(Option<Conversion>),
+    Optimize,
+    AutoRotate,
+}
+
+#[derive(Clone)]
+struct Rule {
+    when: Vec<Condition>,
+    action: Action,
+}
+
+/// What the rules say to do with one file.
+#[derive(Default)]
+struct Plan {
+    convert: Option<Conversion>,
+    optimize: bool,
+    auto_rotate: bool,
+}
+
+/// A destination's image rules, one per line:
+///
+/// ```text
+/// [png, jpg] convert: webp 80
+/// [webp] convert: webp lossless
+/// [png] optimize
+/// auto-rotate
+/// ```
+///
+/// `convert` takes `webp`, `avif`, `jpeg` or `png`, an optional quality
+/// (1-100) or `lossless` (WebP), or `none`. `optimize` losslessly
+/// recompresses PNG output; `auto-rotate` turns images upright according to
+/// their EXIF orientation. Conditions are extensions and MIME types as in
+/// `HeaderRules`; later lines override earlier ones. Blank lines and `#`
+/// comments are skipped.
+#[derive(Clone, Default)]
+pub struct ImageRules {
+    rules: Vec<Rule>,
+}
+
+impl ImageRules {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut rules = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let rule = parse_rule(line).map_err(|e| format!("Image rule line {}: {}", i + 1, e))?;
+            rules.push(rule);
+        }
+        Ok(Self { rules })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    fn plan(&self, name: &str) -> Plan {
+        let ext = Path::new(name)
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("")
+            .to_ascii_lowercase();
+        let content_type = mime_guess::from_path(name).first_or_octet_stream().to_string();
+        let mut plan = Plan::default();
+        for rule in &self.rules {
+            if !rule.when.is_empty() && !rule.when.iter().any(|c| c.matches(&ext, &content_type, false)) {
+                continue;
+            }
+            match rule.action {
+                Action::Convert(conversion) => plan.convert = conversion,
+                Action::Optimize => plan.optimize = true,
+                Action::AutoRotate => plan.auto_rotate = true,
+            }
+        }
+        plan
+    }
+}
+
+fn parse_rule(line: &str) -> Result<Rule, String> {
+    let (when, rest) = headers::split_conditions(line)?;
+    if when.iter().any(|c| matches!(c, Condition::Hashed)) {
+        return Err("[hashed] doesn't apply to image rules".to_string());
+    }
+    let rest = rest.to_ascii_lowercase();
+    let action = match rest.split_once(':') {
+        Some((name, args)) if name.trim() == "convert" => Action::Convert(parse_conversion(args.trim())?),
+        Some((name, _)) => return Err(format!("unknown action: {}", name.trim())),
+        None => match rest.trim() {
+            "optimize" => Action::Optimize,
+            "auto-rotate" => Action::AutoRotate,
+            "convert" => return Err("expected 'convert: FORMAT [QUALITY]'".to_string()),
+            other => return Err(format!("unknown action: {}", other)),
+        },
+    };
+    // Caught here rather than at upload time, where the rule would silently
+    // do nothing
+    if !matches!(action, Action::Convert(None)) {
+        if let Some(format) = when.iter().find_map(undecodable_format) {
+            return Err(format!(
+                "{} images can't be decoded in this build, so this rule would never apply; export them as JPEG first",
+                format
+            ));
+        }
+    }
+    Ok(Rule { when, action })
+}
+
+/// The format a condition names, if it's one that can't be read (HEIC,
+/// HEIF or AVIF).
+fn undecodable_format(condition: &Condition) -> Option<&'static str> {
+    let name = match condition {
+        Condition::Extension(e) => e.as_str(),
+        Condition::Mime(m) => m.strip_prefix("image/")?,
+        Condition::Hashed => return None,
+    };
+    match name {
+        "heic" => Some("HEIC"),
+        "heif" => Some("HEIF"),
+        "avif" => Some("AVIF"),
+        _ => None,
+    }
+}
+
+fn parse_conversion(args: &str) -> Result<Option<Conversion>, String> {
+    let mut parts = args.split_whitespace();
+    let name = parts.next().ok_or("expected 'convert: FORMAT [QUALITY]'")?;
+    if name == "none" {
+        return Ok(None);
+    }
+    if name == "heic" || name == "heif" {
+        return Err("HEIC output isn't supported; use webp or avif".to_string());
+    }
+    let format = OutputFormat::parse(name).ok_or_else(|| format!("unknown format: {}", name))?;
+    let quality = match parts.next() {
+        None => match format {
+            OutputFormat::WebP => Quality::Level(DEFAULT_WEBP_QUALITY),
+            OutputFormat::Avif => Quality::Level(DEFAULT_AVIF_QUALITY),
+            OutputFormat::Jpeg => Quality::Level(DEFAULT_JPEG_QUALITY),
+            OutputFormat::Png => Quality::Lossless,
+        },
+        Some("lossless") if matches!(format, OutputFormat::WebP | OutputFormat::Png) => Quality::Lossless,
+        Some("lossless") => return Err(format!("{} has no lossless mode here", format.label())),
+        Some(_) if format == OutputFormat::Png => return Err("PNG is always lossless; drop the quality".to_string()),
+        Some(q) => Quality::Level(
+            q.parse::<u8>()
+                .ok()
+                .filter(|q| (1..=100).contains(q))
+                .ok_or_else(|| format!("quality must be 1-100 or 'lossless', got {}", q))?,
+        ),
+    };
+    if let Some(extra) = parts.next() {
+        return Err(format!("unexpected '{}'", extra));
+    }
+    Ok(Some(Conversion { format, quality }))
+}
+
+/// What the rules did to a file, for the upload result.
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TransformReport {
+    pub original_bytes: u64,
+    pub bytes: u64,
+    /// e.g. "converted PNG to WebP (quality 80)", "rotated upright",
+    /// "optimized PNG".
+    pub steps: Vec<String>,
+    /// Why a file the rules matched went up unchanged.
+    pub skipped: Option<String>,
+}
+
+/// The file to upload after applying image rules: a converted copy named
+/// `<stem>.<new ext>` in its own temp directory (removed when this is
+/// dropped), or the original when nothing could be done.
+pub struct Transformed {
+    _dir: Option<tempfile::TempDir>,
+    pub path: PathBuf,
+    pub report: TransformReport,
+}
+
+/// Apply the rules that match `name` to `src`. Returns None when no rule
+/// applies or the file isn't an image. Runs on the blocking pool.
+///
+/// Only JPEG, PNG and WebP can be read: HEIC/AVIF sources and animated
+/// images are uploaded unchanged, with the reason in the report. A
+/// conversion that comes out larger than the original is dropped.
+pub async fn apply(
+    rules: &ImageRules,
+    src: &Path,
+    name: &str,
+    cancel: &CancellationToken,
+) -> Result<Option<Transformed>, String> {
+    let plan = rules.plan(name);
+    if plan.convert.is_none() && !plan.optimize && !plan.auto_rotate {
+        return Ok(None);
+    }
+    let src = src.to_path_buf();
+    let cancel = cancel.clone();
+    tokio::task::spawn_blocking(move || apply_blocking(&plan, &src, &cancel))
+        .await
+        .map_err(|e| format!("Image conversion task failed: {}", e))?
+}
+
+fn apply_blocking(plan: &Plan, src: &Path, cancel: &CancellationToken) -> Result<Option<Transformed>, String> {
+    let data = fs::read(src).map_err(|e| format!("Failed to read file: {}", e))?;
+    let original_bytes = data.len() as u64;
+    let unchanged = |reason: String| {
+        Ok(Some(Transformed {
+            _dir: None,
+            path: src.to_path_buf(),
+            report: TransformReport {
+                original_bytes,
+                bytes: original_bytes,
+                steps: Vec::new(),
+                skipped: Some(reason),
+            },
+        }))
+    };
+
+    if sanitize::is_heif(&data) {
+        return unchanged("HEIC and AVIF images can't be decoded here; uploaded unchanged".to_string());
+    }
+    let source = match image::guess_format(&data) {
+        Ok(format) => format,
+        Err(_) => return Ok(None),
+    };
+    let Some(from) = OutputFormat::of(source) else {
+        return unchanged(format!("{:?} images aren't converted; uploaded unchanged", source));
+    };
+    if is_animated(from, &data) {
+        return unchanged("Animated images are uploaded unchanged".to_string());
+    }
+
+    let mut steps = Vec::new();
+    let mut skipped = None;
+    let mut output: Option<(OutputFormat, Vec<u8>)> = None;
+
+    let mut decoder = ImageReader::with_format(Cursor::new(&data), source)
+        .into_decoder()
+        .map_err(|e| format!("Failed to read image: {}", e))?;
+    let orientation = decoder
+        .orientation()
+        .map_err(|e| format!("Failed to read image: {}", e))?;
+    let rotate = orientation != Orientation::NoTransforms;
+    if plan.convert.is_some() || (plan.auto_rotate && rotate) {
+        let mut image = DynamicImage::from_decoder(decoder).map_err(|e| format!("Failed to decode image: {}", e))?;
+        // Converted copies carry no EXIF, so they're always turned upright
+        image.apply_orientation(orientation);
+        if cancel.is_cancelled() {
+            return Err(CANCELLED.to_string());
+        }
+        if let Some(conversion) = plan.convert.filter(|c| c.format != OutputFormat::WebP || fits_webp(&image)) {
+            let bytes = encode(&image, conversion)?;
+            if bytes.len() < data.len() {
+                steps.push(conversion.describe(from));
+                output = Some((conversion.format, bytes));
+            } else {
+                skipped = Some(format!("{} came out larger than the original; kept {}", conversion.format.label(), from.label()));
+            }
+        } else if plan.convert.is_some() {
+            skipped = Some(format!(
+                "{}×{} is larger than WebP allows ({} px a side); kept {}",
+                image.width(),
+                image.height(),
+                WEBP_MAX_DIMENSION,
+                from.label()
+            ));
+        }
+        if rotate && (output.is_some() || plan.auto_rotate) {
+            if output.is_none() {
+                output = Some((from, encode(&image, Conversion::upright(from, &data))?));
+            }
+            steps.push("rotated upright".to_string());
+        }
+    }
+
+    if cancel.is_cancelled() {
+        return Err(CANCELLED.to_string());
+    }
+    let format = output.as_ref().map_or(from, |(f, _)| *f);
+    if plan.optimize && format == OutputFormat::Png {
+        let input = output.as_ref().map_or(data.as_slice(), |(_, b)| b.as_slice());
+        let optimized = oxipng::optimize_from_memory(input, &oxipng::Options::from_preset(OPTIMIZE_PRESET))
+            .map_err(|e| format!("PNG optimization failed: {}", e))?;
+        if optimized.len() < input.len() {
+            output = Some((OutputFormat::Png, optimized));
+        }
+        steps.push("optimized PNG".to_string());
+    }
+
+    let Some((format, bytes)) = output else {
+        return match skipped {
+            Some(reason) => unchanged(reason),
+            None => Ok(None),
+        };
+    };
+
+    let dir = tempfile::Builder::new()
+        .prefix("b2upload_")
+        .tempdir()
+        .map_err(|e| format!("Failed to create temp dir: {}", e))?;
+    let stem = src.file_stem().and_then(|s| s.to_str()).unwrap_or("image");
+    // Same format keeps the original extension (.jpeg stays .jpeg)
+    let ext = match src.extension().and_then(|e| e.to_str()) {
+        Some(ext) if format == from => ext,
+        _ => format.extension(),
+    };
+    let path = dir.path().join(format!("{}.{}", stem, ext));
+    fs::write(&path, &bytes).map_err(|e| format!("Failed to write temp file: {}", e))?;
+
+    Ok(Some(Transformed {
+        _dir: Some(dir),
+        path,
+        report: TransformReport {
+            original_bytes,
+            bytes: bytes.len() as u64,
+            steps,
+            skipped,
+        },
+    }))
+}
+
+fn encode(image: &DynamicImage, conversion: Conversion) -> Result<Vec<u8>, String> {
+    // Encoders take 8-bit RGB(A); JPEG has no alpha channel
+    let image = if image.color().has_alpha() && conversion.format != OutputFormat::Jpeg {
+        DynamicImage::ImageRgba8(image.to_rgba8())
+    } else {
+        DynamicImage::ImageRgb8(image.to_rgb8())
+    };
+    if conversion.format == OutputFormat::WebP {
+        return match conversion.quality {
+            Quality::Lossless => encode_webp(&image, None),
+            Quality::Level(q) => encode_webp(&image, Some(q)),
+        };
+    }
+    let mut out = Vec::new();
+    let result = match (conversion.format, conversion.quality) {
+        (OutputFormat::Jpeg, Quality::Level(q)) => JpegEncoder::new_with_quality(&mut out, q).encode_image(&image),
+        (OutputFormat::Avif, Quality::Level(q)) => {
+            image.write_with_encoder(AvifEncoder::new_with_speed_quality(&mut out, AVIF_SPEED, q))
+        }
+        _ => image.write_with_encoder(PngEncoder::new(&mut out)),
+    };
+    result.map_err(|e| format!("Failed to encode {}: {}", conversion.format.label(), e))?;
+    Ok(out)
+}
+
+/// Whether `image` is within WebP's size limit.
+pub fn fits_webp(image: &DynamicImage) -> bool {
+    image.width() <= WEBP_MAX_DIMENSION && image.height() <= WEBP_MAX_DIMENSION
+}
+
+/// Encode as WebP at `quality`, or losslessly if None. libwebp can't take
+/// images over WEBP_MAX_DIMENSION (the `webp` crate's simple `encode`
+/// panics on them), so those are an error; check `fits_webp` first.
+pub fn encode_webp(image: &DynamicImage, quality: Option<u8>) -> Result<Vec<u8>, String> {
+    if !fits_webp(image) {
+        return Err(format!(
+            "Failed to encode WebP: {}×{} is over {} px a side",
+            image.width(),
+            image.height(),
+            WEBP_MAX_DIMENSION
+        ));
+    }
+    let (width, height) = (image.width(), image.height());
+    let rgba;
+    let encoder = match image {
+        DynamicImage::ImageRgb8(rgb) => webp::Encoder::from_rgb(rgb, width, height),
+        DynamicImage::ImageRgba8(rgba) => webp::Encoder::from_rgba(rgba, width, height),
+        _ => {
+            rgba = image.to_rgba8();
+            webp::Encoder::from_rgba(&rgba, width, height)
+        }
+    };
+    let memory = match quality {
+        Some(q) => encoder.encode_simple(false, q as f32),
+        // For lossless, "quality" is compression effort; 75 is libwebp's default
+        None => encoder.encode_simple(true, 75.0),
+    }
+    .map_err(|e| format!("Failed to encode WebP: {:?}", e))?;
+    Ok(memory.to_vec())
+}
+
+/// APNG (an `acTL` chunk before the image data) or animated WebP (the VP8X
+/// animation flag). Decoding either gives only the first frame.
+fn is_animated(format: OutputFormat, data: &[u8]) -> bool {
+    match format {
+        OutputFormat::Png => {
+            let mut pos = 8;
+            while let Some(header) = data.get(pos..pos + 8) {
+                let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+                match &header[4..8] {
+                    b"acTL" => return true,
+                    b"IDAT" => return false,
+                    _ => pos += 12 + len,
+                }
+            }
+            false
+        }
+        OutputFormat::WebP => data.get(12..16) == Some(b"VP8X") && data.get(20).is_some_and(|flags| flags & 0x02 != 0),
+        _ => false,
+    }
+}
+
+/// A WebP whose image chunk is VP8L, either on its own or after a VP8X header.
+fn is_lossless_webp(data: &[u8]) -> bool {
+    data[..data.len().min(64)].windows(4).any(|w| w == b"VP8L")
+}
@@ -1,36 +1,195 @@
 use aws_config::Region;
 use aws_credential_types::Credentials;
+use aws_sdk_s3::config::retry::RetryConfig;
 use aws_sdk_s3::config::Builder as S3ConfigBuilder;
-use aws_sdk_s3::primitives::{ByteStream, Length};
-use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart};
+use aws_sdk_s3::error::{ProvideErrorMetadata, SdkError};
+use aws_sdk_s3::presigning::PresigningConfig;
+use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart, MetadataDirective};
 use aws_sdk_s3::Client as S3Client;
-use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
 use base64::Engine;
+use bytes::Bytes;
 use hmac::{Hmac, Mac};
+use md5::Md5;
 use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
-use sha2::Sha256;
-use std::collections::HashMap;
-use std::path::Path;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::Semaphore;
+use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
 use zeroize::Zeroizing;
 
-use tokio::io::AsyncWriteExt;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
 /// Progress callback: (bytes_done, bytes_total).
 /// Called from any tokio task so must be Send + Sync.
 pub type ProgressFn = Arc<dyn Fn(u64, u64) + Send + Sync>;
 
-use crate::storage::B2Credentials;
+/// Per-upload runtime settings that come from the caller rather than config.json.
+#[derive(Clone)]
+pub struct UploadOptions {
+    pub parallelism: usize,
+    /// Multipart part size from settings; None picks one from the file size.
+    pub part_size: Option<u64>,
+    pub progress: Option<ProgressFn>,
+    /// When set, multipart uploads are journaled here and can be resumed
+    /// after a crash or network drop.
+    pub journal: Option<Journal>,
+    /// Tripped by `cancel_upload`; stops in-flight requests and aborts any
+    /// multipart upload.
+    pub cancel: CancellationToken,
+    pub retry: RetryPolicy,
+    /// App-wide upload rate limit, shared by every request body.
+    pub throttle: Arc<Throttle>,
+    /// Backs the `{counter}` key template token.
+    pub counter: Counter,
+    /// App-wide cache of B2 download authorizations for the "b2" token mode.
+    pub download_auth: Arc<DownloadAuthCache>,
+    /// Per-upload header overrides (header name -> value), applied after
+    /// the destination's header rules.
+    pub headers: Option<HashMap<String, String>>,
+    /// Where a URL upload was downloaded from; names the file for header
+    /// rules in place of the temp file.
+    pub source_url: Option<String>,
+    /// HASH_BEFORE_UPLOAD: read multipart files once more up front so the
+    /// SHA-256 goes on at creation, even for objects too big to copy.
+    pub hash_first: bool,
+}
+
+/// Error returned when an upload is stopped through its cancellation token.
+pub const CANCELLED: &str = "Upload cancelled";
+
+/// Buffer size for copying files on the blocking pool.
+pub const COPY_BUFFER: usize = 64 * 1024;
+
+/// Copy `reader` into `writer` in COPY_BUFFER chunks, stopping with
+/// CANCELLED between chunks. Errors are prefixed with `read_context` or
+/// `write_context`. The buffer is wiped afterwards, since it may have held
+/// decrypted data.
+pub fn copy_until_cancelled(
+    reader: &mut impl std::io::Read,
+    writer: &mut impl std::io::Write,
+    cancel: &CancellationToken,
+    read_context: &str,
+    write_context: &str,
+) -> Result<(), String> {
+    let mut buf = Zeroizing::new(vec![0u8; COPY_BUFFER]);
+    loop {
+        if cancel.is_cancelled() {
+            return Err(CANCELLED.to_string());
+        }
+        let n = reader
+            .read(&mut buf)
+            .map_err(|e| format!("{}: {}", read_context, e))?;
+        if n == 0 {
+            return Ok(());
+        }
+        writer
+            .write_all(&buf[..n])
+            .map_err(|e| format!("{}: {}", write_context, e))?;
+    }
+}
+
+/// Run a fallible future unless the upload is cancelled first.
+async fn or_cancel<T>(
+    cancel: &CancellationToken,
+    fut: impl std::future::Future<Output = Result<T, String>>,
+) -> Result<T, String> {
+    tokio::select! {
+        _ = cancel.cancelled() => Err(CANCELLED.to_string()),
+        result = fut => result,
+    }
+}
+
+/// What a finished upload produced.
+pub struct UploadOutcome {
+    pub url: String,
+    pub key: String,
+    /// Hex SHA-256 of the uploaded bytes, computed while reading them.
+    pub sha256: String,
+    /// False when the object was stored without the SHA-256 metadata (see
+    /// `attach_sha256_metadata`).
+    pub sha256_stored: bool,
+    /// True when a content-addressed object already existed and no bytes were sent.
+    pub reused: bool,
+    /// Metadata removed before upload (see `sanitize`); empty if none was.
+    pub stripped: Vec<&'static str>,
+    /// Resized copies uploaded next to an image (see `variants`).
+    pub variants: Vec<UploadedVariant>,
+    /// `srcset` value covering the widths and the original, when there are
+    /// width variants.
+    pub srcset: Option<String>,
+    /// What the destination's image rules did (see `transform`).
+    pub transform: Option<TransformReport>,
+    /// Sizes before and after compression, when the file was stored compressed.
+    pub compression: Option<CompressionReport>,
+}
+
+/// One resized copy of an uploaded image.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadedVariant {
+    /// `800w` or `thumb`.
+    pub label: String,
+    pub width: u32,
+    pub height: u32,
+    pub key: String,
+    pub url: String,
+}
+
+use crate::b2auth::DownloadAuthCache;
+use crate::body;
+use crate::bundle::{self, Archive, BundleFormat, ManifestEntry};
+use crate::compress::{self, CompressionReport, Encoding};
+use crate::e2e::{self, E2eMode};
+use crate::headers::{FileInfo, HeaderRules, ObjectHeaders};
+use crate::journal::{FileFingerprint, Journal, JournalEntry};
+use crate::keytemplate::{self, Counter, KeyContext, KeyTemplate};
+use crate::provider::Provider;
+use crate::retry::RetryPolicy;
+use crate::sanitize;
+use crate::sse::{Encryption, EncryptionMode};
+use crate::throttle::Throttle;
+use crate::transform::{self, ImageRules, TransformReport};
+use crate::variants::{self, Renditions, VariantSettings};
+use crate::storage::{B2Credentials, Destination};
 
 type HmacSha256 = Hmac<Sha256>;
 
-// Files larger than this use multipart upload. B2 single-PUT max is 5 GiB;
-// multipart also unlocks incremental progress reporting.
+// Files larger than this (or than the part size, if bigger) use multipart
+// upload. B2 single-PUT max is 5 GiB.
 const MULTIPART_THRESHOLD: u64 = 16 * 1024 * 1024; // 16 MiB
-const PART_SIZE: u64 = 16 * 1024 * 1024; // 16 MiB per part (min 5 MiB for S3)
+const DEFAULT_PART_SIZE: u64 = 16 * 1024 * 1024; // 16 MiB
+// S3 limits: every part but the last is 5 MiB-5 GiB, at most 10,000 parts.
+// Each part is read into memory to hash it before it's sent, so parts stop
+// at 512 MiB; 10,000 of them still cover S3's 5 TiB object limit, near enough.
+const MIN_PART_SIZE: u64 = 5 * 1024 * 1024; // 5 MiB
+const MAX_PART_SIZE: u64 = 512 * 1024 * 1024; // 512 MiB
+const MAX_PARTS: u64 = 10_000;
+// Upper bound on part buffers held in memory at once; parallelism is
+// lowered for large part sizes to stay under it.
+const MAX_BUFFERED_BYTES: u64 = 1024 * 1024 * 1024; // 1 GiB
+// Largest object CopyObject accepts; bigger multipart uploads can only get
+// their SHA-256 metadata when it's hashed before the upload starts.
+const MAX_COPY_SIZE: u64 = 5 * 1024 * 1024 * 1024; // 5 GiB
+// Files uploaded at once during a directory upload (each may itself be multipart).
+const DIRECTORY_CONCURRENCY: usize = 4;
+// Per-directory exclusion file for directory uploads, in .gitignore syntax.
+const IGNORE_FILE_NAME: &str = ".b2ignore";
+// Object metadata key holding the whole-file SHA-256 (x-amz-meta-sha256).
+pub const SHA256_METADATA_KEY: &str = "sha256";
+// Minimum bytes between progress callbacks for streamed bodies and
+// downloads; they move in small chunks and emitting an event for each one
+// would flood the frontend.
+const PROGRESS_STEP: u64 = 1024 * 1024; // 1 MiB
+// Longest lifetime of a presigned URL (the SigV4 limit) or a B2 download
+// authorization.
+const MAX_SIGNED_TTL: u64 = 7 * 24 * 60 * 60; // 7 days
 
 /// Walk the std::error::Error source chain and join messages.
 /// AWS SDK errors wrap the useful details several layers deep, so the top-level
@@ -73,6 +232,28 @@ fn format_sdk_error<E: std::error::Error + 'static>(
     parts.join(" | ")
 }
 
+/// S3 services answer a Content-MD5 mismatch with BadDigest. Call it out explicitly:
+/// it means the bytes were corrupted in transit or changed while being read.
+fn format_upload_error<E: std::error::Error + ProvideErrorMetadata + 'static>(
+    e: &SdkError<E, aws_smithy_runtime_api::http::Response>,
+) -> String {
+    let bad_digest = e
+        .as_service_error()
+        .and_then(|se| se.code())
+        .map(|c| c == "BadDigest")
+        .unwrap_or(false);
+    if bad_digest {
+        format!("checksum mismatch reported by the server: {}", format_sdk_error(e))
+    } else {
+        format_sdk_error(e)
+    }
+}
+
+/// Base64 Content-MD5 header value for a buffer.
+fn content_md5(data: &[u8]) -> String {
+    STANDARD.encode(Md5::digest(data))
+}
+
 // Encode everything except unreserved chars and forward slash
 const PATH_SEGMENT_SET: &AsciiSet = &NON_ALPHANUMERIC
     .remove(b'-')
@@ -83,9 +264,7 @@ const PATH_SEGMENT_SET: &AsciiSet = &NON_ALPHANUMERIC
 /// Build a fresh S3 client with secure credential handoff.
 /// The Zeroizing wrappers wipe the credential copies immediately after
 /// the AWS SDK copies them into its internal Arc buffer.
-fn build_client(endpoint: &str, creds: &B2Credentials) -> S3Client {
-    let region = parse_region(endpoint);
-
+fn build_client(provider: &Provider, creds: &B2Credentials) -> S3Client {
     // Wrap in Zeroizing so originals are wiped after handoff to Credentials::new()
     let key_id = Zeroizing::new(creds.key_id.clone());
     let secret_key = Zeroizing::new(creds.app_key.clone());
@@ -99,11 +278,14 @@ fn build_client(endpoint: &str, creds: &B2Credentials) -> S3Client {
     );
     // key_id and secret_key Zeroizing wrappers drop here, wiping the cloned strings
 
+    // Retries are handled by RetryPolicy, which also reports them to the UI.
     let config = S3ConfigBuilder::new()
-        .endpoint_url(format!("https://{}", endpoint))
-        .region(Region::new(region))
+        .endpoint_url(&provider.endpoint_url)
+        .region(Region::new(provider.region.clone()))
         .credentials_provider(aws_creds)
-        .force_path_style(true)
+        .force_path_style(provider.path_style)
+        .retry_config(RetryConfig::disabled())
+        .request_checksum_calculation(provider.checksums)
         .build();
 
     S3Client::from_conf(config)
@@ -118,16 +300,6 @@ fn generate_hmac_token(path: &str, expires: u64, secret: &str) -> String {
     URL_SAFE_NO_PAD.encode(result.into_bytes())
 }
 
-fn parse_region(endpoint: &str) -> String {
-    // Extract region from "s3.REGION.backblazeb2.com"
-    endpoint
-        .split('.')
-        .nth(1)
-        .filter(|_| endpoint.starts_with("s3."))
-        .unwrap_or("us-east-005")
-        .to_string()
-}
-
 /// Percent-encode each segment of an object key, preserving `/` separators.
 fn encode_object_key(object_key: &str) -> String {
     object_key
@@ -137,298 +309,609 @@ fn encode_object_key(object_key: &str) -> String {
         .join("/")
 }
 
-/// Upload a file using S3 multipart upload with bounded parallelism.
-/// Parts are uploaded concurrently up to `parallelism` at a time; on any
-/// part failure the multipart upload is aborted so B2 doesn't bill for
-/// orphaned parts.
+/// Start (or continue) an S3 multipart upload with bounded parallelism.
+///
+/// The file is read once, sequentially: each part is hashed into the
+/// whole-file SHA-256, sent with its own Content-MD5, and handed to an upload
+/// task. At most `parallelism` parts are in flight (and in memory) at a time.
+/// Parts already recorded in `entry.parts` are read for the hash but not
+/// sent again, which is how a resumed upload only uploads what's missing.
+///
+/// With a journal, progress is persisted after every part and a failed part
+/// leaves the upload in place so it can be resumed later. Without one, any
+/// part failure aborts the upload so B2 doesn't bill for orphaned parts.
+///
+/// Returns the hex SHA-256 of the whole file and whether it's in the
+/// object's metadata.
 async fn multipart_upload(
     client: &S3Client,
-    bucket: &str,
-    key: &str,
     path: &Path,
-    content_type: &str,
-    file_size: u64,
-    parallelism: usize,
-    progress: Option<ProgressFn>,
-) -> Result<(), String> {
-    let create = client
-        .create_multipart_upload()
-        .bucket(bucket)
-        .key(key)
-        .content_type(content_type)
-        .send()
-        .await
-        .map_err(|e| format!("Failed to start multipart upload: {}", format_sdk_error(&e)))?;
+    mut entry: JournalEntry,
+    sse: &Encryption,
+    opts: &UploadOptions,
+) -> Result<(String, bool), String> {
+    if opts.cancel.is_cancelled() {
+        return Err(CANCELLED.to_string());
+    }
 
-    let upload_id = create
-        .upload_id()
-        .ok_or("Multipart upload created without an upload ID")?
-        .to_string();
+    if entry.upload_id.is_empty() {
+        entry.upload_id = create_multipart_upload(client, &entry, sse, opts).await?;
+    }
+
+    if let Some(journal) = &opts.journal {
+        journal.save(&entry)?;
+    }
 
-    let part_count = file_size.div_ceil(PART_SIZE);
-    let bytes_uploaded = Arc::new(AtomicU64::new(0));
-    let sem = Arc::new(Semaphore::new(parallelism.max(1)));
+    let file_size = entry.fingerprint.size;
+    let part_count = entry.part_count();
+    let bytes_uploaded = Arc::new(AtomicU64::new(entry.bytes_done()));
+    let parallelism = opts
+        .parallelism
+        .min((MAX_BUFFERED_BYTES / entry.part_size) as usize)
+        .max(1);
+    let sem = Arc::new(Semaphore::new(parallelism));
     let mut joins: tokio::task::JoinSet<Result<(i32, Option<String>), String>> =
         tokio::task::JoinSet::new();
+    let mut failure: Option<String> = None;
+
+    if let Some(cb) = &opts.progress {
+        cb(bytes_uploaded.load(Ordering::SeqCst), file_size);
+    }
+
+    let mut file = tokio::fs::File::open(path)
+        .await
+        .map_err(|e| format!("Failed to open file: {}", e))?;
+    let mut hasher = Sha256::new();
 
     for part_num in 1..=part_count {
-        let offset = (part_num - 1) * PART_SIZE;
-        let length = std::cmp::min(PART_SIZE, file_size - offset);
+        // Record parts that already finished so the journal stays current
+        // and a failure stops the read loop early.
+        while let Some(joined) = joins.try_join_next() {
+            handle_part_result(joined, &mut entry, &mut failure, &mut joins, opts);
+        }
+        if failure.is_some() {
+            break;
+        }
 
-        let sem_c = sem.clone();
-        let client_c = client.clone();
-        let bucket_c = bucket.to_string();
-        let key_c = key.to_string();
-        let upload_id_c = upload_id.clone();
-        let path_c = path.to_path_buf();
+        let length = entry.part_length(part_num);
+
+        // Wait for an upload slot before reading, bounding memory use.
+        let permit = tokio::select! {
+            _ = opts.cancel.cancelled() => {
+                failure = Some(CANCELLED.to_string());
+                joins.abort_all();
+                break;
+            }
+            permit = sem.clone().acquire_owned() => {
+                permit.map_err(|e| format!("semaphore closed: {}", e))?
+            }
+        };
+
+        let mut buf = vec![0u8; length as usize];
+        if let Err(e) = file.read_exact(&mut buf).await {
+            failure = Some(if e.kind() == std::io::ErrorKind::UnexpectedEof {
+                format!("part {}: file was truncated while uploading", part_num)
+            } else {
+                format!("part {}: read failed: {}", part_num, e)
+            });
+            break;
+        }
+        hasher.update(&buf);
+
+        if entry.parts.contains_key(&(part_num as i32)) {
+            continue;
+        }
+
+        let upload = upload_part(client, &entry, part_num, buf, sse, opts);
         let bytes_c = bytes_uploaded.clone();
-        let progress_c = progress.clone();
+        let progress_c = opts.progress.clone();
 
         joins.spawn(async move {
-            let _permit = sem_c
-                .acquire_owned()
-                .await
-                .map_err(|e| format!("semaphore closed: {}", e))?;
-
-            let body = ByteStream::read_from()
-                .path(&path_c)
-                .offset(offset)
-                .length(Length::Exact(length))
-                .build()
-                .await
-                .map_err(|e| format!("part {}: read failed: {}", part_num, e))?;
-
-            let resp = client_c
-                .upload_part()
-                .bucket(&bucket_c)
-                .key(&key_c)
-                .upload_id(&upload_id_c)
-                .part_number(part_num as i32)
-                .body(body)
-                .send()
-                .await
-                .map_err(|e| {
-                    format!("part {}: upload failed: {}", part_num, format_sdk_error(&e))
-                })?;
+            let _permit = permit;
+            let part = upload.await?;
 
             let done = bytes_c.fetch_add(length, Ordering::SeqCst) + length;
             if let Some(cb) = &progress_c {
                 cb(done, file_size);
             }
 
-            Ok((part_num as i32, resp.e_tag().map(String::from)))
+            Ok(part)
         });
     }
 
-    let mut completed_parts: Vec<CompletedPart> = Vec::with_capacity(part_count as usize);
-    let mut failure: Option<String> = None;
+    // A file that grew since it was fingerprinted would upload stale bytes.
+    if failure.is_none() {
+        let mut probe = [0u8; 1];
+        if file.read(&mut probe).await.map(|n| n > 0).unwrap_or(false) {
+            failure = Some("File grew while uploading".to_string());
+            joins.abort_all();
+        }
+    }
 
-    while let Some(joined) = joins.join_next().await {
-        match joined {
-            Ok(Ok((pn, et))) => {
-                completed_parts.push(
-                    CompletedPart::builder()
-                        .part_number(pn)
-                        .set_e_tag(et)
-                        .build(),
-                );
-            }
-            Ok(Err(e)) => {
-                if failure.is_none() {
-                    failure = Some(e);
-                }
-                joins.abort_all();
-            }
-            Err(join_err) => {
-                if failure.is_none() {
-                    failure = Some(format!("part task panicked: {}", join_err));
-                }
+    loop {
+        let joined = tokio::select! {
+            _ = opts.cancel.cancelled(), if failure.as_deref() != Some(CANCELLED) => {
+                // Stop every part task right away rather than letting them finish
+                failure = Some(CANCELLED.to_string());
                 joins.abort_all();
+                continue;
             }
+            joined = joins.join_next() => joined,
+        };
+        match joined {
+            Some(joined) => handle_part_result(joined, &mut entry, &mut failure, &mut joins, opts),
+            None => break,
         }
     }
 
     if let Some(e) = failure {
-        let _ = client
-            .abort_multipart_upload()
-            .bucket(bucket)
-            .key(key)
-            .upload_id(&upload_id)
-            .send()
-            .await;
+        // A cancelled upload is never resumed; drop it on B2 and locally
+        if e == CANCELLED {
+            abort_multipart(client, &entry).await;
+            if let Some(journal) = &opts.journal {
+                journal.remove(&entry.id);
+            }
+            return Err(e);
+        }
+        if opts.journal.is_some() {
+            return Err(format!("{} (the upload can be resumed)", e));
+        }
+        abort_multipart(client, &entry).await;
         return Err(e);
     }
 
-    completed_parts.sort_by_key(|p| p.part_number());
+    let sha256 = format!("{:x}", hasher.finalize());
+    if entry.sha256.as_ref().is_some_and(|h| *h != sha256) {
+        // Resuming would read the same changed file and fail again
+        abort_multipart(client, &entry).await;
+        if let Some(journal) = &opts.journal {
+            journal.remove(&entry.id);
+        }
+        return Err("File changed while uploading (SHA-256 mismatch)".to_string());
+    }
 
-    let completed_mpu = CompletedMultipartUpload::builder()
-        .set_parts(Some(completed_parts))
-        .build();
+    if let Err(e) = complete_multipart_upload(client, &entry, file_size, sse, opts).await {
+        // The parts are all there, but a completion that keeps failing isn't
+        // worth resuming; drop the upload so B2 doesn't keep billing for them.
+        abort_multipart(client, &entry).await;
+        if let Some(journal) = &opts.journal {
+            journal.remove(&entry.id);
+        }
+        return Err(e);
+    }
 
-    client
-        .complete_multipart_upload()
-        .bucket(bucket)
-        .key(key)
-        .upload_id(&upload_id)
-        .multipart_upload(completed_mpu)
-        .send()
-        .await
-        .map_err(|e| format!("Failed to complete multipart upload: {}", format_sdk_error(&e)))?;
+    if let Some(journal) = &opts.journal {
+        journal.remove(&entry.id);
+    }
 
-    Ok(())
+    let stored = match entry.sha256 {
+        Some(_) => true,
+        None => attach_sha256_metadata(client, &entry, &sha256, file_size, sse, opts).await,
+    };
+    Ok((sha256, stored))
 }
 
-pub async fn upload_file(
-    file_path: &str,
-    mode: &str,
-    config: &HashMap<String, String>,
-    creds: &B2Credentials,
-    ttl: Option<u64>,
-    parallelism: usize,
-    progress: Option<ProgressFn>,
+/// Start a multipart upload with the entry's headers and metadata,
+/// returning its upload ID.
+async fn create_multipart_upload(
+    client: &S3Client,
+    entry: &JournalEntry,
+    sse: &Encryption,
+    opts: &UploadOptions,
 ) -> Result<String, String> {
-    let input_path = Path::new(file_path);
-    if !input_path.exists() {
-        return Err(format!("File not found: {}", file_path));
-    }
-    if input_path.is_dir() {
-        return Err("Directories are not supported. Drop individual files instead.".to_string());
-    }
+    let create = opts
+        .retry
+        .run("create multipart upload", || {
+            client
+                .create_multipart_upload()
+                .bucket(&entry.bucket)
+                .key(&entry.key)
+                .content_type(&entry.headers.content_type)
+                .set_cache_control(entry.headers.cache_control.clone())
+                .set_content_disposition(entry.headers.content_disposition.clone())
+                .set_content_language(entry.headers.content_language.clone())
+                .set_content_encoding(entry.headers.content_encoding.clone())
+                .set_metadata(entry.headers.metadata_with(entry.sha256.as_deref()))
+                .set_server_side_encryption(sse.server_side_encryption())
+                .set_sse_customer_algorithm(sse.customer_algorithm())
+                .set_sse_customer_key(sse.customer_key())
+                .set_sse_customer_key_md5(sse.customer_key_md5())
+                .send()
+        })
+        .await
+        .map_err(|e| format!("Failed to start multipart upload: {}", format_sdk_error(&e)))?;
 
-    let endpoint = config.get("S3_ENDPOINT").ok_or("Missing S3_ENDPOINT")?;
-    let bucket = config.get("BUCKET_NAME").ok_or("Missing BUCKET_NAME")?;
-    let domain = config.get("DOMAIN").ok_or("Missing DOMAIN")?;
+    Ok(create
+        .upload_id()
+        .ok_or("Multipart upload created without an upload ID")?
+        .to_string())
+}
 
-    // Map mode to folder/token
-    let (folder, token) = if mode == "folder2" {
-        (
-            config.get("FOLDER_2").map(|s| s.as_str()).unwrap_or(""),
-            &creds.folder_2_token,
-        )
-    } else {
-        (
-            config.get("FOLDER_1").map(|s| s.as_str()).unwrap_or(""),
-            &creds.folder_1_token,
-        )
-    };
+/// Send one part of a multipart upload, with retries. The returned future
+/// owns everything it needs, so it can be spawned; it yields the part
+/// number and ETag.
+fn upload_part(
+    client: &S3Client,
+    entry: &JournalEntry,
+    part_num: u64,
+    buf: Vec<u8>,
+    sse: &Encryption,
+    opts: &UploadOptions,
+) -> impl std::future::Future<Output = Result<(i32, Option<String>), String>> + Send + 'static {
+    let length = buf.len();
+    let md5 = content_md5(&buf);
+    let data = Bytes::from(buf);
+    let client = client.clone();
+    let bucket = entry.bucket.clone();
+    let key = entry.key.clone();
+    let upload_id = entry.upload_id.clone();
+    let retry = opts.retry.clone();
+    let throttle = opts.throttle.clone();
+    let sse = sse.clone();
+
+    async move {
+        let resp = retry
+            .run(&format!("part {}", part_num), || {
+                client
+                    .upload_part()
+                    .bucket(&bucket)
+                    .key(&key)
+                    .upload_id(&upload_id)
+                    .part_number(part_num as i32)
+                    .content_length(length as i64)
+                    .content_md5(&md5)
+                    .set_sse_customer_algorithm(sse.customer_algorithm())
+                    .set_sse_customer_key(sse.customer_key())
+                    .set_sse_customer_key_md5(sse.customer_key_md5())
+                    .body(body::byte_stream(data.clone(), &throttle, None))
+                    .send()
+            })
+            .await
+            .map_err(|e| format!("part {}: upload failed: {}", part_num, format_upload_error(&e)))?;
+        Ok((part_num as i32, resp.e_tag().map(String::from)))
+    }
+}
 
-    // Read upload options
-    let use_date = config.get("DATE_FOLDERS").map(|s| s.as_str()).unwrap_or("on") != "off";
-    let use_uuid = config.get("UUID_FILENAMES").map(|s| s.as_str()).unwrap_or("on") != "off";
-    let allow_overwrite = config.get("OVERWRITE_UPLOADS").map(|s| s.as_str()).unwrap_or("no") == "yes";
+/// Complete a multipart upload from the entry's recorded parts. `size` is
+/// the object's total length, used to recognize a completion that went
+/// through on an earlier attempt.
+async fn complete_multipart_upload(
+    client: &S3Client,
+    entry: &JournalEntry,
+    size: u64,
+    sse: &Encryption,
+    opts: &UploadOptions,
+) -> Result<(), String> {
+    let completed_parts: Vec<CompletedPart> = entry
+        .parts
+        .iter()
+        .map(|(pn, et)| {
+            CompletedPart::builder()
+                .part_number(*pn)
+                .e_tag(et)
+                .build()
+        })
+        .collect();
 
-    // Build a fresh client for this operation; drops when function returns
-    let client = build_client(endpoint, creds);
+    let completed_mpu = CompletedMultipartUpload::builder()
+        .set_parts(Some(completed_parts))
+        .build();
 
-    let path = Path::new(file_path);
-    let ext = path
-        .extension()
-        .and_then(|e| e.to_str())
-        .unwrap_or("bin");
+    or_cancel(&opts.cancel, async {
+        let result = opts
+            .retry
+            .run("complete upload", || {
+                client
+                    .complete_multipart_upload()
+                    .bucket(&entry.bucket)
+                    .key(&entry.key)
+                    .upload_id(&entry.upload_id)
+                    .multipart_upload(completed_mpu.clone())
+                    .send()
+            })
+            .await;
+        match result {
+            Ok(_) => Ok(()),
+            // A retried completion whose first attempt went through but whose
+            // response was lost comes back as NoSuchUpload.
+            Err(e) if e.code() == Some("NoSuchUpload")
+                && object_has_size(client, &entry.bucket, &entry.key, size, sse).await =>
+            {
+                Ok(())
+            }
+            Err(e) => Err(format!("Failed to complete multipart upload: {}", format_sdk_error(&e))),
+        }
+    })
+    .await
+}
 
-    // Build filename
-    let filename = if use_uuid {
-        format!("{}.{}", Uuid::new_v4(), ext)
-    } else {
-        path.file_name()
-            .and_then(|n| n.to_str())
-            .unwrap_or(&format!("file.{}", ext))
-            .to_string()
-    };
+fn handle_part_result(
+    joined: Result<Result<(i32, Option<String>), String>, tokio::task::JoinError>,
+    entry: &mut JournalEntry,
+    failure: &mut Option<String>,
+    joins: &mut tokio::task::JoinSet<Result<(i32, Option<String>), String>>,
+    opts: &UploadOptions,
+) {
+    match joined {
+        Ok(Ok((pn, et))) => {
+            entry.parts.insert(pn, et.unwrap_or_default());
+            if let Some(journal) = &opts.journal {
+                if let Err(e) = journal.save(entry) {
+                    eprintln!("[journal] {}", e);
+                }
+            }
+        }
+        Ok(Err(e)) => {
+            if failure.is_none() {
+                *failure = Some(e);
+            }
+            joins.abort_all();
+        }
+        Err(join_err) => {
+            if join_err.is_cancelled() {
+                return;
+            }
+            if failure.is_none() {
+                *failure = Some(format!("part task panicked: {}", join_err));
+            }
+            joins.abort_all();
+        }
+    }
+}
 
-    // Build object key: [folder/][date/]filename
-    let mut parts: Vec<String> = Vec::new();
-    if !folder.is_empty() {
-        parts.push(folder.to_string());
+/// Add the SHA-256 to a finished multipart object's metadata, which is only
+/// known once every part has been read, with a metadata-only CopyObject onto
+/// its own key; no bytes are sent again. Returns whether it was stored:
+/// CopyObject only takes objects up to MAX_COPY_SIZE, and a failed copy
+/// leaves the object as it was.
+async fn attach_sha256_metadata(
+    client: &S3Client,
+    entry: &JournalEntry,
+    sha256: &str,
+    size: u64,
+    sse: &Encryption,
+    opts: &UploadOptions,
+) -> bool {
+    if size > MAX_COPY_SIZE {
+        return false;
     }
-    if use_date {
-        let now = chrono::Local::now();
-        parts.push(now.format("%Y/%m/%d").to_string());
+    let result = opts
+        .retry
+        .run("store checksum", || {
+            client
+                .copy_object()
+                .bucket(&entry.bucket)
+                .key(&entry.key)
+                .copy_source(format!("{}/{}", entry.bucket, encode_object_key(&entry.key)))
+                .metadata_directive(MetadataDirective::Replace)
+                .content_type(&entry.headers.content_type)
+                .set_cache_control(entry.headers.cache_control.clone())
+                .set_content_disposition(entry.headers.content_disposition.clone())
+                .set_content_language(entry.headers.content_language.clone())
+                .set_content_encoding(entry.headers.content_encoding.clone())
+                .set_metadata(entry.headers.metadata_with(Some(sha256)))
+                .set_server_side_encryption(sse.server_side_encryption())
+                .set_sse_customer_algorithm(sse.customer_algorithm())
+                .set_sse_customer_key(sse.customer_key())
+                .set_sse_customer_key_md5(sse.customer_key_md5())
+                .set_copy_source_sse_customer_algorithm(sse.customer_algorithm())
+                .set_copy_source_sse_customer_key(sse.customer_key())
+                .set_copy_source_sse_customer_key_md5(sse.customer_key_md5())
+                .send()
+        })
+        .await;
+    match result {
+        Ok(_) => true,
+        Err(e) => {
+            eprintln!("[upload] Failed to store SHA-256 metadata: {}", format_sdk_error(&e));
+            false
+        }
     }
-    parts.push(filename);
-    let object_key = parts.join("/");
+}
 
-    // Overwrite guard: only check when overwrite is off AND uuid is off (original filenames)
-    if !allow_overwrite && !use_uuid {
-        match client.head_object().bucket(bucket).key(&object_key).send().await {
-            Ok(_) => return Err("File already exists (overwrite is disabled)".to_string()),
-            Err(e) => {
-                let is_not_found = e.as_service_error()
-                    .map(|se| se.is_not_found())
+/// Whether `key` exists with exactly `size` bytes.
+async fn object_has_size(
+    client: &S3Client,
+    bucket: &str,
+    key: &str,
+    size: u64,
+    sse: &Encryption,
+) -> bool {
+    let head = client
+        .head_object()
+        .bucket(bucket)
+        .key(key)
+        .set_sse_customer_algorithm(sse.customer_algorithm())
+        .set_sse_customer_key(sse.customer_key())
+        .set_sse_customer_key_md5(sse.customer_key_md5())
+        .send()
+        .await;
+    match head {
+        Ok(head) => head.content_length() == Some(size as i64),
+        Err(_) => false,
+    }
+}
+
+async fn abort_multipart(client: &S3Client, entry: &JournalEntry) {
+    let _ = client
+        .abort_multipart_upload()
+        .bucket(&entry.bucket)
+        .key(&entry.key)
+        .upload_id(&entry.upload_id)
+        .send()
+        .await;
+}
+
+/// Ask B2 which parts it already holds for an interrupted upload and make
+/// the journal agree. The server is authoritative: parts it doesn't list (or
+/// lists with the wrong size) are uploaded again.
+async fn reconcile_parts(client: &S3Client, entry: &mut JournalEntry) -> Result<(), String> {
+    let mut server_parts: BTreeMap<i32, String> = BTreeMap::new();
+    let mut marker: Option<String> = None;
+
+    loop {
+        let resp = client
+            .list_parts()
+            .bucket(&entry.bucket)
+            .key(&entry.key)
+            .upload_id(&entry.upload_id)
+            .set_part_number_marker(marker.take())
+            .send()
+            .await
+            .map_err(|e| {
+                let gone = e
+                    .as_service_error()
+                    .and_then(|se| se.code())
+                    .map(|c| c == "NoSuchUpload")
                     .unwrap_or(false);
-                if !is_not_found {
-                    return Err(format!("Failed to check existing file: {}", format_sdk_error(&e)));
+                if gone {
+                    "The interrupted upload no longer exists on the server".to_string()
+                } else {
+                    format!("Failed to list uploaded parts: {}", format_sdk_error(&e))
                 }
+            })?;
+
+        for part in resp.parts() {
+            let (Some(pn), Some(etag)) = (part.part_number(), part.e_tag()) else {
+                continue;
+            };
+            if pn < 1 || pn as u64 > entry.part_count() {
+                continue;
+            }
+            let size_ok = part
+                .size()
+                .map(|s| s as u64 == entry.part_length(pn as u64))
+                .unwrap_or(true);
+            if size_ok {
+                server_parts.insert(pn, etag.to_string());
             }
         }
+
+        if resp.is_truncated().unwrap_or(false) {
+            marker = resp.next_part_number_marker().map(String::from);
+            if marker.is_some() {
+                continue;
+            }
+        }
+        break;
     }
 
-    let content_type = mime_guess::from_path(path)
-        .first_or_octet_stream()
-        .to_string();
+    entry.parts = server_parts;
+    Ok(())
+}
 
-    let file_size = tokio::fs::metadata(path)
+/// SHA-256 of a whole file, read in 1 MiB chunks.
+async fn hash_file(path: &Path, cancel: &CancellationToken) -> Result<String, String> {
+    let mut file = tokio::fs::File::open(path)
         .await
-        .map_err(|e| format!("Failed to stat file: {}", e))?
-        .len();
-
-    // Prime the progress bar at 0 so the UI shows something immediately.
-    if let Some(cb) = &progress {
-        cb(0, file_size);
-    }
-
-    if file_size > MULTIPART_THRESHOLD {
-        multipart_upload(
-            &client,
-            bucket,
-            &object_key,
-            path,
-            &content_type,
-            file_size,
-            parallelism,
-            progress.clone(),
-        )
-        .await?;
-    } else {
-        let body = ByteStream::from_path(path)
+        .map_err(|e| format!("Failed to open file: {}", e))?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 1024 * 1024];
+    loop {
+        if cancel.is_cancelled() {
+            return Err(CANCELLED.to_string());
+        }
+        let n = file
+            .read(&mut buf)
             .await
             .map_err(|e| format!("Failed to read file: {}", e))?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
 
-        client
-            .put_object()
-            .bucket(bucket)
-            .key(&object_key)
-            .content_type(content_type)
-            .body(body)
-            .send()
-            .await
-            .map_err(|e| format!("Upload failed: {}", format_sdk_error(&e)))?;
-
-        // Single-PUT has no byte-level progress hook; jump to complete.
-        if let Some(cb) = &progress {
-            cb(file_size, file_size);
+/// Whether a content-addressed key already holds these bytes: Some(true)
+/// when its SHA-256 metadata says so, Some(false) for an object without
+/// that metadata, which is trusted by name. Objects whose metadata disagrees
+/// are treated as missing (None) so they get replaced.
+async fn object_has_sha256(
+    client: &S3Client,
+    bucket: &str,
+    key: &str,
+    sha256: &str,
+    sse: &Encryption,
+    retry: &RetryPolicy,
+) -> Result<Option<bool>, String> {
+    let head = retry
+        .run("check existing file", || {
+            client
+                .head_object()
+                .bucket(bucket)
+                .key(key)
+                .set_sse_customer_algorithm(sse.customer_algorithm())
+                .set_sse_customer_key(sse.customer_key())
+                .set_sse_customer_key_md5(sse.customer_key_md5())
+                .send()
+        })
+        .await;
+    match head {
+        Ok(head) => Ok(match head.metadata().and_then(|m| m.get(SHA256_METADATA_KEY)) {
+            Some(h) if h.eq_ignore_ascii_case(sha256) => Some(true),
+            Some(_) => None,
+            None => Some(false),
+        }),
+        Err(e) => {
+            let is_not_found = e.as_service_error()
+                .map(|se| se.is_not_found())
+                .unwrap_or(false);
+            if is_not_found {
+                Ok(None)
+            } else {
+                Err(format!("Failed to check existing file: {}", format_sdk_error(&e)))
+            }
         }
     }
+}
+
+/// Build the shareable URL for an uploaded object, applying the configured
+/// token mode. Presigned links are signed with `client`'s credentials.
+async fn build_url(
+    client: &S3Client,
+    object_key: &str,
+    destination_id: &str,
+    config: &HashMap<String, String>,
+    creds: &B2Credentials,
+    ttl: Option<u64>,
+    download_auth: &DownloadAuthCache,
+) -> Result<String, String> {
+    let token_mode = config.get("TOKEN_MODE").map(|s| s.as_str()).unwrap_or("static");
+    if token_mode == "presigned" {
+        return presigned_url(client, object_key, config, ttl).await;
+    }
+    if token_mode == "b2" {
+        return b2_download_url(download_auth, object_key, config, creds, ttl).await;
+    }
+
+    let domain = config.get("DOMAIN").ok_or("Missing DOMAIN")?;
+    let token = creds.destination_token(destination_id);
 
     // Percent-encode the object key for the URL
-    let encoded_key = encode_object_key(&object_key);
+    let encoded_key = encode_object_key(object_key);
 
     // Build URL with optional token
-    let token_mode = config.get("TOKEN_MODE").map(|s| s.as_str()).unwrap_or("static");
-
     let url = if token_mode == "dynamic" {
         if let Some(ttl_secs) = ttl {
-            if creds.token_secret.is_empty() {
-                return Err("TOKEN_SECRET is required for dynamic token mode".to_string());
-            }
+            let (kid, secret) = creds
+                .link_signing_key()
+                .ok_or("A signing key or TOKEN_SECRET is required for dynamic token mode")?;
             let now = std::time::SystemTime::now()
                 .duration_since(std::time::UNIX_EPOCH)
                 .map_err(|e| e.to_string())?
                 .as_secs();
             let expires = now + ttl_secs;
             let hmac_path = format!("/{}", object_key);
-            let sig = generate_hmac_token(&hmac_path, expires, &creds.token_secret);
-            format!("https://{}/{}?token={}&expires={}", domain, encoded_key, sig, expires)
+            let sig = generate_hmac_token(&hmac_path, expires, secret);
+            match kid {
+                // Key IDs are plain ASCII, safe in a query string as is
+                Some(kid) => format!(
+                    "https://{}/{}?token={}&expires={}&kid={}",
+                    domain, encoded_key, sig, expires, kid
+                ),
+                None => format!("https://{}/{}?token={}&expires={}", domain, encoded_key, sig, expires),
+            }
         } else {
             format!("https://{}/{}", domain, encoded_key)
         }
@@ -438,51 +921,1379 @@ pub async fn upload_file(
         format!("https://{}/{}?token={}", domain, encoded_key, token)
     };
 
-    // client drops here -- AWS SDK zeroizes its internal credential buffers
     Ok(url)
 }
 
-/// Download a URL to a temporary file, preserving the original extension.
-/// Returns the path to the temp file.
-pub async fn download_url(url: &str) -> Result<String, String> {
-    let response = reqwest::get(url)
+/// A SigV4 query-signed GET URL on the S3 endpoint, which opens without a
+/// Worker. It lasts `ttl` seconds (DEFAULT_TTL if unset), at most the 7 days
+/// SigV4 allows. PRESIGNED_DISPOSITION makes it open inline or download.
+async fn presigned_url(
+    client: &S3Client,
+    object_key: &str,
+    config: &HashMap<String, String>,
+    ttl: Option<u64>,
+) -> Result<String, String> {
+    let bucket = config.get("BUCKET_NAME").ok_or("Missing BUCKET_NAME")?;
+    let presigning = PresigningConfig::expires_in(Duration::from_secs(signed_ttl(config, ttl)))
+        .map_err(|e| format!("Invalid link expiry: {}", e))?;
+    let disposition = match config.get("PRESIGNED_DISPOSITION").map(|s| s.as_str()) {
+        Some(d @ ("inline" | "attachment")) => Some(d.to_string()),
+        _ => None,
+    };
+    let request = client
+        .get_object()
+        .bucket(bucket)
+        .key(object_key)
+        .set_response_content_disposition(disposition)
+        .presigned(presigning)
         .await
-        .map_err(|e| format!("Download failed: {}", e))?;
+        .map_err(|e| format!("Failed to sign link: {}", format_sdk_error(&e)))?;
+    Ok(request.uri().to_string())
+}
 
-    if !response.status().is_success() {
-        return Err(format!("Download failed: HTTP {}", response.status()));
+/// A `/file/<bucket>/<key>?Authorization=` URL on the account's B2 download
+/// host, which opens files in a private bucket without a Worker. The token
+/// is scoped to `object_key` itself, so sharing one file never opens its
+/// neighbours; only a folder upload's prefix link (a key ending in `/`)
+/// covers a whole folder.
+async fn b2_download_url(
+    download_auth: &DownloadAuthCache,
+    object_key: &str,
+    config: &HashMap<String, String>,
+    creds: &B2Credentials,
+    ttl: Option<u64>,
+) -> Result<String, String> {
+    let bucket = config.get("BUCKET_NAME").ok_or("Missing BUCKET_NAME")?;
+    let auth = download_auth
+        .authorize(creds, bucket, object_key, signed_ttl(config, ttl))
+        .await?;
+    Ok(format!(
+        "{}/file/{}/{}?Authorization={}",
+        auth.download_url,
+        utf8_percent_encode(bucket, PATH_SEGMENT_SET),
+        encode_object_key(object_key),
+        utf8_percent_encode(&auth.token, PATH_SEGMENT_SET)
+    ))
+}
+
+/// Lifetime of a presigned or B2-authorized link: `ttl`, else DEFAULT_TTL,
+/// capped at 7 days.
+fn signed_ttl(config: &HashMap<String, String>, ttl: Option<u64>) -> u64 {
+    ttl.or_else(|| config.get("DEFAULT_TTL").and_then(|s| s.parse().ok()))
+        .unwrap_or(MAX_SIGNED_TTL)
+        .clamp(1, MAX_SIGNED_TTL)
+}
+
+/// The plain S3 URL of `key`, for folder prefixes in presigned mode: a
+/// prefix isn't an object, so there is nothing to sign.
+fn endpoint_url(provider: &Provider, bucket: &str, key: &str) -> String {
+    let encoded_key = encode_object_key(key);
+    let endpoint = provider.endpoint_url.trim_end_matches('/');
+    match endpoint.split_once("://") {
+        Some((scheme, host)) if !provider.path_style => format!("{}://{}.{}/{}", scheme, bucket, host, encoded_key),
+        _ => format!("{}/{}/{}", endpoint, bucket, encoded_key),
     }
+}
 
-    // Extract filename from URL path (strip query params)
-    let parsed = url.split('?').next().unwrap_or(url);
-    let url_filename = parsed.split('/').last().unwrap_or("download");
+/// Fail before any bytes are sent if no working link can be built for
+/// `dest`: Worker links need DOMAIN and B2 download authorizations need B2.
+/// No link opens an SSE-C object, whose key has to be sent as request
+/// headers: presigned and B2 links can't carry it, and the Worker doesn't
+/// send it.
+fn check_link_settings(config: &HashMap<String, String>, dest: &Destination) -> Result<(), String> {
+    if dest.encryption == EncryptionMode::SseC {
+        return Err(
+            "Share links can't open SSE-C objects (neither signed links nor the Worker can send the key); use SSE-B2 or end-to-end encryption"
+                .to_string(),
+        );
+    }
+    let token_mode = config.get("TOKEN_MODE").map(|s| s.as_str()).unwrap_or("static");
+    if token_mode != "presigned" && token_mode != "b2" {
+        config.get("DOMAIN").ok_or("Missing DOMAIN")?;
+        return Ok(());
+    }
+    if token_mode == "b2" && !matches!(config.get("S3_PROVIDER").map(|s| s.trim()), None | Some("" | "b2")) {
+        return Err("B2 download authorization links only work with Backblaze B2".to_string());
+    }
+    Ok(())
+}
 
-    // Get extension, default to "bin"
-    let ext = Path::new(url_filename)
-        .extension()
-        .and_then(|e| e.to_str())
-        .unwrap_or("bin");
+/// Where a single object upload is going, and what URL it should produce.
+struct PutTarget<'a> {
+    bucket: &'a str,
+    key: &'a str,
+    destination_id: &'a str,
+    ttl: Option<u64>,
+    headers: &'a ObjectHeaders,
+    sse: &'a Encryption,
+}
+
+fn guess_content_type(path: &Path) -> String {
+    mime_guess::from_path(path).first_or_octet_stream().to_string()
+}
+
+/// Last path segment of a URL, ignoring any query string.
+fn url_file_name(url: &str) -> &str {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    path.rsplit('/').next().filter(|n| !n.is_empty()).unwrap_or("download")
+}
+
+/// The destination's key template, falling back to the layout the upload
+/// toggles describe.
+fn key_template_for(
+    dest: &Destination,
+    config: &HashMap<String, String>,
+    is_dir: bool,
+) -> Result<KeyTemplate, String> {
+    KeyTemplate::parse(&keytemplate::template_for(
+        Some(&dest.key_template),
+        config,
+        is_dir,
+    ))
+}
+
+/// Render the object key an upload to `dest` would get, without uploading.
+/// The destination may hold unsaved values from the settings form. Hash and
+/// counter tokens show placeholder values (the counter shows its next value).
+pub fn preview_object_key(
+    dest: &Destination,
+    config: &HashMap<String, String>,
+    file_name: &str,
+    counter: &Counter,
+) -> Result<String, String> {
+    let template = key_template_for(dest, config, false)?;
+    let sample_sha256 = format!("{:x}", Sha256::digest(file_name.as_bytes()));
+    template.render(&KeyContext {
+        folder: &dest.prefix,
+        name: file_name,
+        is_dir: false,
+        sha256: Some(&sample_sha256),
+        counter: Some(counter.peek()),
+    })
+}
+
+/// Fail if the key is already taken (used when overwrites are disabled).
+async fn ensure_key_free(
+    client: &S3Client,
+    bucket: &str,
+    key: &str,
+    sse: &Encryption,
+    retry: &RetryPolicy,
+) -> Result<(), String> {
+    let head = retry
+        .run("check existing file", || {
+            client
+                .head_object()
+                .bucket(bucket)
+                .key(key)
+                .set_sse_customer_algorithm(sse.customer_algorithm())
+                .set_sse_customer_key(sse.customer_key())
+                .set_sse_customer_key_md5(sse.customer_key_md5())
+                .send()
+        })
+        .await;
+    match head {
+        Ok(_) => Err("File already exists (overwrite is disabled)".to_string()),
+        Err(e) => {
+            let is_not_found = e.as_service_error()
+                .map(|se| se.is_not_found())
+                .unwrap_or(false);
+            if is_not_found {
+                Ok(())
+            } else {
+                Err(format!("Failed to check existing file: {}", format_sdk_error(&e)))
+            }
+        }
+    }
+}
+
+/// Part size for a file: the configured one, or the default grown (in whole
+/// MiB) until the file fits in 10,000 parts. Fails up front rather than after
+/// thousands of parts have been sent.
+fn choose_part_size(file_size: u64, configured: Option<u64>) -> Result<u64, String> {
+    const MIB: u64 = 1024 * 1024;
+    let part_size = match configured {
+        Some(size) => size.clamp(MIN_PART_SIZE, MAX_PART_SIZE),
+        None => {
+            let needed = file_size.div_ceil(MAX_PARTS).div_ceil(MIB) * MIB;
+            needed.clamp(DEFAULT_PART_SIZE, MAX_PART_SIZE)
+        }
+    };
+    let parts = file_size.div_ceil(part_size);
+    if parts > MAX_PARTS {
+        return Err(match configured {
+            Some(_) => format!(
+                "File needs {} parts of {} MiB, over the 10,000-part limit; raise the part size or set it to auto",
+                parts,
+                part_size / MIB
+            ),
+            None => "File is too large for a multipart upload (over 10,000 parts of 512 MiB)".to_string(),
+        });
+    }
+    Ok(part_size)
+}
+
+/// Upload one local file to an exact key, as a single PUT or multipart
+/// depending on size. Returns the hex SHA-256 of the uploaded bytes and
+/// whether it's in the object's metadata.
+async fn put_file(
+    client: &S3Client,
+    target: &PutTarget<'_>,
+    path: &Path,
+    precomputed_sha256: Option<String>,
+    opts: &UploadOptions,
+) -> Result<(String, bool), String> {
+    let file_size = tokio::fs::metadata(path)
+        .await
+        .map_err(|e| format!("Failed to stat file: {}", e))?
+        .len();
+
+    // Prime the progress bar at 0 so the UI shows something immediately.
+    if let Some(cb) = &opts.progress {
+        cb(0, file_size);
+    }
+
+    let part_size = choose_part_size(file_size, opts.part_size)?;
+    if file_size > MULTIPART_THRESHOLD.max(part_size) {
+        let fingerprint = FileFingerprint::compute(path)?;
+        // Hashed as the parts are read, unless asked for up front so it can
+        // go on CreateMultipartUpload; the upload then checks it still matches.
+        let sha256 = match precomputed_sha256 {
+            None if opts.hash_first => Some(hash_file(path, &opts.cancel).await?),
+            sha256 => sha256,
+        };
+        let entry = JournalEntry {
+            id: Uuid::new_v4().to_string(),
+            upload_id: String::new(),
+            bucket: target.bucket.to_string(),
+            key: target.key.to_string(),
+            headers: target.headers.clone(),
+            part_size,
+            file_path: path.to_string_lossy().to_string(),
+            file_name: path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or("unknown")
+                .to_string(),
+            fingerprint,
+            mode: target.destination_id.to_string(),
+            ttl: target.ttl,
+            started: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+            sha256,
+            encryption: target.sse.mode(),
+            parts: BTreeMap::new(),
+        };
+        return multipart_upload(client, path, entry, target.sse, opts).await;
+    }
+
+    // Small enough to hold in memory: one read feeds the hashes and the body.
+    let data = tokio::fs::read(path)
+        .await
+        .map_err(|e| format!("Failed to read file: {}", e))?;
+    if data.len() as u64 != file_size {
+        return Err("File changed while reading".to_string());
+    }
+    let sha256 = format!("{:x}", Sha256::digest(&data));
+    if precomputed_sha256.is_some_and(|h| h != sha256) {
+        return Err("File changed while reading".to_string());
+    }
+    put_bytes(client, target, data, &sha256, opts).await?;
+    Ok((sha256, true))
+}
+
+/// Upload an in-memory body to an exact key in a single PUT. `sha256` is
+/// the body's hex SHA-256, stored as metadata.
+async fn put_bytes(
+    client: &S3Client,
+    target: &PutTarget<'_>,
+    data: Vec<u8>,
+    sha256: &str,
+    opts: &UploadOptions,
+) -> Result<(), String> {
+    let size = data.len() as u64;
+    let md5 = content_md5(&data);
+    let data = Bytes::from(data);
+    or_cancel(&opts.cancel, async {
+        opts.retry
+            .run("upload", || {
+                client
+                    .put_object()
+                    .bucket(target.bucket)
+                    .key(target.key)
+                    .content_type(&target.headers.content_type)
+                    .set_cache_control(target.headers.cache_control.clone())
+                    .set_content_disposition(target.headers.content_disposition.clone())
+                    .set_content_language(target.headers.content_language.clone())
+                    .set_content_encoding(target.headers.content_encoding.clone())
+                    .content_length(data.len() as i64)
+                    .content_md5(&md5)
+                    .set_metadata(target.headers.metadata_with(Some(sha256)))
+                    .set_server_side_encryption(target.sse.server_side_encryption())
+                    .set_sse_customer_algorithm(target.sse.customer_algorithm())
+                    .set_sse_customer_key(target.sse.customer_key())
+                    .set_sse_customer_key_md5(target.sse.customer_key_md5())
+                    .body(body::byte_stream(
+                        data.clone(),
+                        &opts.throttle,
+                        sent_progress(opts.progress.as_ref(), size),
+                    ))
+                    .send()
+            })
+            .await
+            .map_err(|e| format!("Upload failed: {}", format_upload_error(&e)))
+    })
+    .await?;
+
+    if let Some(cb) = &opts.progress {
+        cb(size, size);
+    }
+    Ok(())
+}
+
+/// Report a single PUT's progress as its body is sent, at most once per
+/// PROGRESS_STEP. Built per attempt, so a retry starts back at zero.
+fn sent_progress(progress: Option<&ProgressFn>, total: u64) -> Option<body::SentFn> {
+    let cb = progress?.clone();
+    let done = AtomicU64::new(0);
+    let last_reported = AtomicU64::new(0);
+    Some(Arc::new(move |n| {
+        let now = done.fetch_add(n, Ordering::SeqCst) + n;
+        if now == total || now - last_reported.load(Ordering::SeqCst) >= PROGRESS_STEP {
+            last_reported.store(now, Ordering::SeqCst);
+            cb(now, total);
+        }
+    }))
+}
+
+pub async fn upload_file(
+    file_path: &str,
+    dest: &Destination,
+    config: &HashMap<String, String>,
+    creds: &B2Credentials,
+    ttl: Option<u64>,
+    opts: &UploadOptions,
+) -> Result<UploadOutcome, String> {
+    let strip = config.get("STRIP_METADATA").map(|s| s.as_str()).unwrap_or("off") == "on";
+    let image_rules = ImageRules::parse(&dest.image_rules)?;
+    // Ciphertext doesn't compress, and a browser would try to decompress it
+    let encoding = Encoding::from_config(config).filter(|_| dest.e2e == E2eMode::Off);
+    if !strip && dest.e2e == E2eMode::Off && image_rules.is_empty() && encoding.is_none() {
+        return upload_object(file_path, dest, config, creds, ttl, None, opts).await;
+    }
+
+    let input_path = Path::new(file_path);
+    if !input_path.is_file() {
+        return Err(format!("File not found: {}", file_path));
+    }
+    // Converted, cleaned and sealed copies are uploaded in place of the
+    // file and removed when they drop.
+    let file_name = input_path.file_name().and_then(|n| n.to_str()).unwrap_or("file");
+    let rule_name = opts.source_url.as_deref().map(url_file_name).unwrap_or(file_name);
+    let transformed = transform::apply(&image_rules, input_path, rule_name, &opts.cancel).await?;
+    let path = transformed.as_ref().map_or(input_path, |t| t.path.as_path());
+    let sanitized = if strip {
+        sanitize::strip_metadata(path, &opts.cancel).await?
+    } else {
+        None
+    };
+    let path = sanitized.as_ref().map_or(path, |s| s.path.as_path());
+    let compressed = match encoding {
+        Some(encoding) => compress::compress_file(path, &guess_content_type(path), encoding, &opts.cancel).await?,
+        None => None,
+    };
+    let path = compressed.as_ref().map_or(path, |c| c.path.as_path());
+    let sealed = match dest.e2e {
+        E2eMode::Off => None,
+        mode => Some(e2e::seal_file(path, mode, &dest.recipients, &opts.cancel).await?),
+    };
+    let path = sealed.as_ref().map_or(path, |s| s.path.as_path());
+
+    // Copies aren't journaled: they're gone after a crash, and a link key
+    // only lives in memory, so a resumed upload couldn't produce a working link.
+    let copy_opts;
+    let opts = if path == input_path {
+        opts
+    } else {
+        copy_opts = UploadOptions {
+            journal: None,
+            ..opts.clone()
+        };
+        &copy_opts
+    };
+    let content_encoding = compressed.as_ref().map(|c| c.report.encoding);
+    let mut outcome = upload_object(&path.to_string_lossy(), dest, config, creds, ttl, content_encoding, opts).await?;
+    if let Some(sealed) = &sealed {
+        outcome.url = sealed.share_url(&outcome.url);
+    }
+    if let Some(sanitized) = sanitized {
+        outcome.stripped = sanitized.removed.clone();
+    }
+    outcome.transform = transformed.map(|t| t.report);
+    outcome.compression = compressed.map(|c| c.report);
+    Ok(outcome)
+}
+
+/// Upload one file as-is to the destination's next key. `content_encoding`
+/// is set when the file is a compressed copy.
+async fn upload_object(
+    file_path: &str,
+    dest: &Destination,
+    config: &HashMap<String, String>,
+    creds: &B2Credentials,
+    ttl: Option<u64>,
+    content_encoding: Option<Encoding>,
+    opts: &UploadOptions,
+) -> Result<UploadOutcome, String> {
+    let input_path = Path::new(file_path);
+    if !input_path.exists() {
+        return Err(format!("File not found: {}", file_path));
+    }
+    if input_path.is_dir() {
+        return Err("Use upload_directory for directories".to_string());
+    }
+
+    let provider = Provider::from_config(config)?;
+    let bucket = config.get("BUCKET_NAME").ok_or("Missing BUCKET_NAME")?;
+    // The URL is built after the upload; fail before sending any bytes.
+    check_link_settings(config, dest)?;
+
+    let template = key_template_for(dest, config, false)?;
+    let header_rules = HeaderRules::parse(&dest.headers)?;
+    let sse = Encryption::resolve(dest.encryption, creds, &dest.id)?;
+
+    // Read upload options
+    let allow_overwrite = config.get("OVERWRITE_UPLOADS").map(|s| s.as_str()).unwrap_or("no") == "yes";
+    let content_addressed = config.get("CONTENT_ADDRESSED").map(|s| s.as_str()).unwrap_or("off") == "on";
+
+    // Build a fresh client for this operation; drops when function returns
+    let client = build_client(&provider, creds);
+
+    let path = Path::new(file_path);
+    let file_name = path.file_name().and_then(|n| n.to_str()).unwrap_or("file");
+
+    // Hash-based names need the hash before anything else happens
+    let precomputed_sha256 = if content_addressed || template.needs_sha256() {
+        Some(hash_file(path, &opts.cancel).await?)
+    } else {
+        None
+    };
+    let counter = if template.uses_counter() {
+        Some(opts.counter.next()?)
+    } else {
+        None
+    };
+
+    let object_key = template.render(&KeyContext {
+        folder: &dest.prefix,
+        name: file_name,
+        is_dir: false,
+        sha256: precomputed_sha256.as_deref(),
+        counter,
+    })?;
+
+    let hashed = content_addressed || template.needs_sha256() || template.is_unique();
+    let mut headers = header_rules.resolve(
+        &FileInfo {
+            name: opts.source_url.as_deref().map(url_file_name).unwrap_or(file_name),
+            content_type: &guess_content_type(path),
+            hashed,
+            source: opts.source_url.as_deref(),
+        },
+        opts.headers.as_ref(),
+    )?;
+    headers.content_encoding = content_encoding.map(|e| e.as_str().to_string());
+    let target = PutTarget {
+        bucket,
+        key: &object_key,
+        destination_id: &dest.id,
+        ttl,
+        headers: &headers,
+        sse: &sse,
+    };
+
+    // Identical bytes already uploaded: skip the PUT and just hand out a URL;
+    // its variants went up with it the first time
+    let existing = match precomputed_sha256.as_ref().filter(|_| content_addressed) {
+        Some(hash) => object_has_sha256(&client, bucket, &object_key, hash, &sse, &opts.retry)
+            .await?
+            .map(|stored| (hash.clone(), stored)),
+        None => None,
+    };
+    let reused = existing.is_some();
+
+    // Resized copies are rendered up front so a bad image fails before any
+    // bytes are sent. Sealed uploads get none: they'd be plaintext.
+    let renditions = match VariantSettings::from_config(config)? {
+        Some(settings) if dest.e2e == E2eMode::Off && !reused => variants::render(path, &settings, &opts.cancel).await?,
+        _ => None,
+    };
+
+    // Overwrite guard: only check when overwrite is off and the template can
+    // repeat a key (no {uuid} or {randN}). Content-addressed keys were checked
+    // above; an existing key there with different bytes is stale and gets replaced.
+    if !allow_overwrite && !template.is_unique() && !content_addressed {
+        ensure_key_free(&client, bucket, &object_key, &sse, &opts.retry).await?;
+        for item in renditions.iter().flat_map(|r| &r.items) {
+            let key = variants::sibling_key(&object_key, &item.label);
+            ensure_key_free(&client, bucket, &key, &sse, &opts.retry).await?;
+        }
+    }
+
+    let (sha256, sha256_stored) = match existing {
+        Some((hash, stored)) => {
+            let file_size = tokio::fs::metadata(path)
+                .await
+                .map_err(|e| format!("Failed to stat file: {}", e))?
+                .len();
+            if let Some(cb) = &opts.progress {
+                cb(file_size, file_size);
+            }
+            (hash, stored)
+        }
+        None => put_file(&client, &target, path, precomputed_sha256, opts).await?,
+    };
+
+    let url = build_url(&client, &object_key, &dest.id, config, creds, ttl, &opts.download_auth).await?;
+    let (variants, srcset) = match &renditions {
+        Some(renditions) => {
+            let uploaded = upload_variants(&client, renditions, &target, &header_rules, hashed, config, creds, opts).await?;
+            let srcset = variants::srcset(&url, renditions.source_width, &uploaded);
+            (uploaded, srcset)
+        }
+        None => (Vec::new(), None),
+    };
+
+    // client drops here -- AWS SDK zeroizes its internal credential buffers
+    Ok(UploadOutcome {
+        url,
+        key: object_key,
+        sha256,
+        sha256_stored,
+        reused,
+        stripped: Vec::new(),
+        variants,
+        srcset,
+        transform: None,
+        compression: None,
+    })
+}
+
+/// Upload an image's resized copies next to its object, with the same
+/// header rules, encryption and URL token or signature.
+#[allow(clippy::too_many_arguments)]
+async fn upload_variants(
+    client: &S3Client,
+    renditions: &Renditions,
+    target: &PutTarget<'_>,
+    header_rules: &HeaderRules,
+    hashed: bool,
+    config: &HashMap<String, String>,
+    creds: &B2Credentials,
+    opts: &UploadOptions,
+) -> Result<Vec<UploadedVariant>, String> {
+    // Variants are small and share the main file's progress bar, which is
+    // already full
+    let variant_opts = UploadOptions {
+        progress: None,
+        journal: None,
+        ..opts.clone()
+    };
+    let mut uploaded = Vec::with_capacity(renditions.items.len());
+    for item in &renditions.items {
+        let key = variants::sibling_key(target.key, &item.label);
+        let name = item.path.file_name().and_then(|n| n.to_str()).unwrap_or("image");
+        let headers = header_rules.resolve(
+            &FileInfo {
+                name,
+                content_type: &guess_content_type(&item.path),
+                hashed,
+                source: opts.source_url.as_deref(),
+            },
+            opts.headers.as_ref(),
+        )?;
+        let variant_target = PutTarget {
+            key: &key,
+            headers: &headers,
+            ..*target
+        };
+        put_file(client, &variant_target, &item.path, None, &variant_opts).await?;
+        uploaded.push(UploadedVariant {
+            url: build_url(client, &key, target.destination_id, config, creds, target.ttl, &opts.download_auth).await?,
+            label: item.label.clone(),
+            width: item.width,
+            height: item.height,
+            key,
+        });
+    }
+    Ok(uploaded)
+}
+
+/// Collect the files under `root` to upload, with their `/`-separated paths
+/// relative to it. Honors `.b2ignore` files (gitignore syntax) at any level,
+/// optionally skips hidden entries, and skips symlink loops when following
+/// links.
+pub fn collect_directory_files(
+    root: &Path,
+    skip_hidden: bool,
+    follow_symlinks: bool,
+) -> Result<Vec<(PathBuf, String)>, String> {
+    let walker = ignore::WalkBuilder::new(root)
+        .standard_filters(false)
+        .hidden(skip_hidden)
+        .follow_links(follow_symlinks)
+        .add_custom_ignore_filename(IGNORE_FILE_NAME)
+        .build();
+
+    let mut files = Vec::new();
+    for result in walker {
+        let entry = match result {
+            Ok(entry) => entry,
+            Err(e) => {
+                // Loops and unreadable entries are skipped, not fatal
+                eprintln!("[upload] Skipping directory entry: {}", e);
+                continue;
+            }
+        };
+        if !entry.file_type().map(|t| t.is_file()).unwrap_or(false) {
+            continue;
+        }
+        if entry.file_name() == IGNORE_FILE_NAME {
+            continue;
+        }
+        let rel = entry
+            .path()
+            .strip_prefix(root)
+            .map_err(|e| e.to_string())?
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().to_string())
+            .collect::<Vec<_>>()
+            .join("/");
+        files.push((entry.path().to_path_buf(), rel));
+    }
+    files.sort_by(|a, b| a.1.cmp(&b.1));
+    Ok(files)
+}
+
+/// Result for one file of a directory upload.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DirectoryFileResult {
+    pub path: String,
+    pub key: String,
+    pub url: Option<String>,
+    pub sha256: Option<String>,
+    /// False when the SHA-256 couldn't be stored as object metadata.
+    pub sha256_stored: bool,
+    /// Metadata removed before upload.
+    pub stripped: Vec<&'static str>,
+    /// What the destination's image rules did.
+    pub transform: Option<TransformReport>,
+    /// Sizes before and after compression, when the file was stored compressed.
+    pub compression: Option<CompressionReport>,
+    pub error: Option<String>,
+}
+
+/// What one file of a directory upload stored. The key differs from its
+/// relative path when an image rule converted it.
+struct FileUpload {
+    key: String,
+    sha256: String,
+    sha256_stored: bool,
+    stripped: Vec<&'static str>,
+    transform: Option<TransformReport>,
+    compression: Option<CompressionReport>,
+}
+
+type FileResult = Result<FileUpload, String>;
+
+/// What a directory upload produced: one URL for the common prefix plus a
+/// result per file. Individual file failures don't fail the whole upload.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DirectoryOutcome {
+    pub prefix: String,
+    pub prefix_url: String,
+    pub files: Vec<DirectoryFileResult>,
+}
+
+/// Upload every file under a directory to a common key prefix, keeping
+/// relative paths: `[folder/][YYYY/MM/DD/]<dir name or UUID>/<relative path>`.
+/// Files are uploaded a few at a time; progress is reported for the
+/// directory as a whole.
+pub async fn upload_directory(
+    dir_path: &str,
+    dest: &Destination,
+    config: &HashMap<String, String>,
+    creds: &B2Credentials,
+    ttl: Option<u64>,
+    opts: &UploadOptions,
+) -> Result<DirectoryOutcome, String> {
+    let root = Path::new(dir_path);
+    if !root.is_dir() {
+        return Err(format!("Not a directory: {}", dir_path));
+    }
+    // One prefix link can't carry a key per file
+    if dest.e2e != E2eMode::Off {
+        return Err("Folders can't be uploaded to an end-to-end encrypted destination".to_string());
+    }
+
+    let provider = Provider::from_config(config)?;
+    let bucket = config.get("BUCKET_NAME").ok_or("Missing BUCKET_NAME")?;
+    check_link_settings(config, dest)?;
+
+    let template = key_template_for(dest, config, true)?;
+    let unique_prefix = template.is_unique();
+    let header_rules = HeaderRules::parse(&dest.headers)?;
+    let image_rules = ImageRules::parse(&dest.image_rules)?;
+    let sse = Encryption::resolve(dest.encryption, creds, &dest.id)?;
+    let allow_overwrite = config.get("OVERWRITE_UPLOADS").map(|s| s.as_str()).unwrap_or("no") == "yes";
+    let skip_hidden = config.get("SKIP_HIDDEN_FILES").map(|s| s.as_str()).unwrap_or("on") != "off";
+    let follow_symlinks = config.get("FOLLOW_SYMLINKS").map(|s| s.as_str()).unwrap_or("off") == "on";
+    let strip = config.get("STRIP_METADATA").map(|s| s.as_str()).unwrap_or("off") == "on";
+    let encoding = Encoding::from_config(config);
+
+    let files = {
+        let root = root.to_path_buf();
+        tokio::task::spawn_blocking(move || {
+            collect_directory_files(&root, skip_hidden, follow_symlinks)
+        })
+        .await
+        .map_err(|e| format!("Directory walk failed: {}", e))??
+    };
+    if files.is_empty() {
+        return Err("Directory has no files to upload".to_string());
+    }
+
+    // The key template names the directory itself (with UUID filenames it
+    // gets a UUID, so two drops of a folder with the same name never
+    // collide); the files inside keep their names so relative links between
+    // them still work.
+    let dir_name = root.file_name().and_then(|n| n.to_str()).unwrap_or("upload");
+    let counter = if template.uses_counter() {
+        Some(opts.counter.next()?)
+    } else {
+        None
+    };
+    let prefix = template.render(&KeyContext {
+        folder: &dest.prefix,
+        name: dir_name,
+        is_dir: true,
+        sha256: None,
+        counter,
+    })?;
+
+    let client = build_client(&provider, creds);
+
+    let mut sizes = Vec::with_capacity(files.len());
+    for (path, _) in &files {
+        let len = tokio::fs::metadata(path).await.map(|m| m.len()).unwrap_or(0);
+        sizes.push(len);
+    }
+    let total: u64 = sizes.iter().sum();
+    let done_total = Arc::new(AtomicU64::new(0));
+    if let Some(cb) = &opts.progress {
+        cb(0, total);
+    }
+
+    let sem = Arc::new(Semaphore::new(DIRECTORY_CONCURRENCY));
+    let mut joins: tokio::task::JoinSet<(usize, FileResult)> = tokio::task::JoinSet::new();
+
+    for (idx, (path, rel)) in files.iter().enumerate() {
+        let permit = tokio::select! {
+            _ = opts.cancel.cancelled() => break,
+            permit = sem.clone().acquire_owned() => {
+                permit.map_err(|e| format!("semaphore closed: {}", e))?
+            }
+        };
+
+        // Fold this file's progress into the directory-wide total
+        let last = Arc::new(AtomicU64::new(0));
+        let done_c = done_total.clone();
+        let outer = opts.progress.clone();
+        let file_progress: ProgressFn = Arc::new(move |done, _| {
+            let prev = last.swap(done, Ordering::SeqCst);
+            let now = done_c.fetch_add(done.saturating_sub(prev), Ordering::SeqCst)
+                + done.saturating_sub(prev);
+            if let Some(cb) = &outer {
+                cb(now, total);
+            }
+        });
+        let file_opts = UploadOptions {
+            parallelism: opts.parallelism,
+            part_size: opts.part_size,
+            progress: Some(file_progress),
+            journal: opts.journal.clone(),
+            cancel: opts.cancel.clone(),
+            retry: opts.retry.clone(),
+            throttle: opts.throttle.clone(),
+            counter: opts.counter.clone(),
+            download_auth: opts.download_auth.clone(),
+            headers: opts.headers.clone(),
+            source_url: None,
+            hash_first: opts.hash_first,
+        };
+
+        let client_c = client.clone();
+        let bucket_c = bucket.clone();
+        let dest_id = dest.id.clone();
+        let sse_c = sse.clone();
+        let header_rules_c = header_rules.clone();
+        let image_rules_c = image_rules.clone();
+        let path_c = path.clone();
+        let rel_c = rel.clone();
+        let prefix_c = prefix.clone();
+        joins.spawn(async move {
+            let _permit = permit;
+            let result = async {
+                let file_name = path_c.file_name().and_then(|n| n.to_str()).unwrap_or("file");
+                let transformed = transform::apply(&image_rules_c, &path_c, file_name, &file_opts.cancel).await?;
+                let path = transformed.as_ref().map_or(path_c.as_path(), |t| t.path.as_path());
+                let sanitized = if strip {
+                    sanitize::strip_metadata(path, &file_opts.cancel).await?
+                } else {
+                    None
+                };
+                let path = sanitized.as_ref().map_or(path, |s| s.path.as_path());
+                let compressed = match encoding {
+                    Some(encoding) => {
+                        compress::compress_file(path, &guess_content_type(path), encoding, &file_opts.cancel).await?
+                    }
+                    None => None,
+                };
+                let path = compressed.as_ref().map_or(path, |c| c.path.as_path());
+
+                // A converted image keeps its place but takes the new extension
+                let name = path.file_name().and_then(|n| n.to_str()).unwrap_or(file_name);
+                let key = match rel_c.rsplit_once('/') {
+                    Some((dir, _)) => format!("{}/{}/{}", prefix_c, dir, name),
+                    None => format!("{}/{}", prefix_c, name),
+                };
+                if !allow_overwrite && !unique_prefix {
+                    ensure_key_free(&client_c, &bucket_c, &key, &sse_c, &file_opts.retry).await?;
+                }
+                let mut headers = header_rules_c.resolve(
+                    &FileInfo {
+                        name,
+                        content_type: &guess_content_type(path),
+                        hashed: unique_prefix,
+                        source: None,
+                    },
+                    file_opts.headers.as_ref(),
+                )?;
+                headers.content_encoding = compressed.as_ref().map(|c| c.report.encoding.as_str().to_string());
+                let target = PutTarget {
+                    bucket: &bucket_c,
+                    key: &key,
+                    destination_id: &dest_id,
+                    ttl,
+                    headers: &headers,
+                    sse: &sse_c,
+                };
+                // Converted, cleaned and compressed copies are temporary, so there'd be
+                // nothing to resume from
+                let copy_opts;
+                let upload_opts = if path == path_c.as_path() {
+                    &file_opts
+                } else {
+                    copy_opts = UploadOptions {
+                        journal: None,
+                        ..file_opts.clone()
+                    };
+                    &copy_opts
+                };
+                let (sha256, sha256_stored) = put_file(&client_c, &target, path, None, upload_opts).await?;
+                Ok(FileUpload {
+                    key,
+                    sha256,
+                    sha256_stored,
+                    stripped: sanitized.map(|s| s.removed.clone()).unwrap_or_default(),
+                    transform: transformed.map(|t| t.report),
+                    compression: compressed.map(|c| c.report),
+                })
+            }
+            .await;
+            (idx, result)
+        });
+    }
+
+    let mut results: Vec<Option<FileResult>> = (0..files.len()).map(|_| None).collect();
+    while let Some(joined) = joins.join_next().await {
+        match joined {
+            Ok((idx, result)) => results[idx] = Some(result),
+            Err(e) => eprintln!("[upload] directory file task failed: {}", e),
+        }
+    }
+
+    if opts.cancel.is_cancelled() {
+        return Err(CANCELLED.to_string());
+    }
+
+    let mut file_results = Vec::with_capacity(files.len());
+    for ((_, rel), result) in files.iter().zip(results) {
+        let result = result.unwrap_or_else(|| Err("Upload task failed".to_string()));
+        file_results.push(match result {
+            Ok(upload) => DirectoryFileResult {
+                path: rel.clone(),
+                url: Some(build_url(&client, &upload.key, &dest.id, config, creds, ttl, &opts.download_auth).await?),
+                key: upload.key,
+                sha256: Some(upload.sha256),
+                sha256_stored: upload.sha256_stored,
+                stripped: upload.stripped,
+                transform: upload.transform,
+                compression: upload.compression,
+                error: None,
+            },
+            Err(e) => DirectoryFileResult {
+                path: rel.clone(),
+                key: format!("{}/{}", prefix, rel),
+                url: None,
+                sha256: None,
+                sha256_stored: false,
+                stripped: Vec::new(),
+                transform: None,
+                compression: None,
+                error: Some(e),
+            },
+        });
+    }
+
+    let prefix_key = format!("{}/", prefix);
+    let prefix_url = if config.get("TOKEN_MODE").map(|s| s.as_str()) == Some("presigned") {
+        endpoint_url(&provider, bucket, &prefix_key)
+    } else {
+        build_url(&client, &prefix_key, &dest.id, config, creds, ttl, &opts.download_auth).await?
+    };
+
+    // client drops here -- AWS SDK zeroizes its internal credential buffers
+    Ok(DirectoryOutcome {
+        prefix,
+        prefix_url,
+        files: file_results,
+    })
+}
+
+/// What a bundle upload produced: one archive object and its contents.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BundleOutcome {
+    /// File name the key was rendered from (`bundle-20260220-101500.zip`).
+    pub name: String,
+    pub url: String,
+    pub key: String,
+    /// Hex SHA-256 of the archive.
+    pub sha256: String,
+    /// False when the SHA-256 couldn't be stored as object metadata (see
+    /// `attach_sha256_metadata`).
+    pub sha256_stored: bool,
+    /// Size of the archive as stored.
+    pub size: u64,
+    pub manifest: Vec<ManifestEntry>,
+}
+
+/// Upload dropped files and folders as one ZIP or tar.zst archive. The
+/// archive is written while it's uploaded, straight into multipart parts,
+/// so it's never staged on disk.
+pub async fn upload_bundle(
+    paths: &[String],
+    dest: &Destination,
+    config: &HashMap<String, String>,
+    creds: &B2Credentials,
+    ttl: Option<u64>,
+    format: BundleFormat,
+    opts: &UploadOptions,
+) -> Result<BundleOutcome, String> {
+    if dest.e2e != E2eMode::Off {
+        return Err("Bundles can't be uploaded to an end-to-end encrypted destination".to_string());
+    }
+
+    let provider = Provider::from_config(config)?;
+    let bucket = config.get("BUCKET_NAME").ok_or("Missing BUCKET_NAME")?;
+    check_link_settings(config, dest)?;
+
+    let template = key_template_for(dest, config, false)?;
+    // The hash is only known once the last byte has been sent
+    if template.needs_sha256() {
+        return Err(
+            "Bundles are hashed while they upload, so their key can't use {sha256}; turn off content-addressed filenames or change the key template"
+                .to_string(),
+        );
+    }
+    let header_rules = HeaderRules::parse(&dest.headers)?;
+    let sse = Encryption::resolve(dest.encryption, creds, &dest.id)?;
+    let allow_overwrite = config.get("OVERWRITE_UPLOADS").map(|s| s.as_str()).unwrap_or("no") == "yes";
+    let skip_hidden = config.get("SKIP_HIDDEN_FILES").map(|s| s.as_str()).unwrap_or("on") != "off";
+    let follow_symlinks = config.get("FOLLOW_SYMLINKS").map(|s| s.as_str()).unwrap_or("off") == "on";
+
+    let files = {
+        let paths: Vec<PathBuf> = paths.iter().map(PathBuf::from).collect();
+        tokio::task::spawn_blocking(move || bundle::collect_files(&paths, skip_hidden, follow_symlinks))
+            .await
+            .map_err(|e| format!("Directory walk failed: {}", e))??
+    };
+
+    // A single folder is named after itself
+    let stem = match paths {
+        [only] if Path::new(only).is_dir() => Path::new(only)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("bundle")
+            .to_string(),
+        _ => format!("bundle-{}", chrono::Local::now().format("%Y%m%d-%H%M%S")),
+    };
+    let file_name = format!("{}.{}", stem, format.extension());
+    let counter = if template.uses_counter() {
+        Some(opts.counter.next()?)
+    } else {
+        None
+    };
+    let object_key = template.render(&KeyContext {
+        folder: &dest.prefix,
+        name: &file_name,
+        is_dir: false,
+        sha256: None,
+        counter,
+    })?;
+    let headers = header_rules.resolve(
+        &FileInfo {
+            name: &file_name,
+            content_type: format.content_type(),
+            hashed: template.is_unique(),
+            source: None,
+        },
+        opts.headers.as_ref(),
+    )?;
+    let target = PutTarget {
+        bucket,
+        key: &object_key,
+        destination_id: &dest.id,
+        ttl,
+        headers: &headers,
+        sse: &sse,
+    };
+
+    let client = build_client(&provider, creds);
+    if !allow_overwrite && !template.is_unique() {
+        ensure_key_free(&client, bucket, &object_key, &sse, &opts.retry).await?;
+    }
+
+    let archive = Archive::start(format, files, opts.progress.clone(), &opts.cancel);
+    let (sha256, sha256_stored, size, manifest) = put_archive(&client, &target, archive, opts).await?;
+    let url = build_url(&client, &object_key, &dest.id, config, creds, ttl, &opts.download_auth).await?;
+
+    // client drops here -- AWS SDK zeroizes its internal credential buffers
+    Ok(BundleOutcome {
+        name: file_name,
+        url,
+        key: object_key,
+        sha256,
+        sha256_stored,
+        size,
+        manifest,
+    })
+}
+
+/// Upload an archive as it's written: a single PUT if it fits in one part,
+/// otherwise multipart with parts sent as soon as they fill. Its size isn't
+/// known up front, so the part size is the configured one or the default.
+/// Returns the SHA-256, whether it was stored as metadata, size and
+/// manifest. Nothing is journaled: the archive
+/// can't be re-read to resume.
+async fn put_archive(
+    client: &S3Client,
+    target: &PutTarget<'_>,
+    mut archive: Archive,
+    opts: &UploadOptions,
+) -> Result<(String, bool, u64, Vec<ManifestEntry>), String> {
+    let part_size = opts
+        .part_size
+        .unwrap_or(DEFAULT_PART_SIZE)
+        .clamp(MIN_PART_SIZE, MAX_PART_SIZE);
+    // The archive reports progress as it reads the files
+    let opts = &UploadOptions {
+        progress: None,
+        journal: None,
+        ..opts.clone()
+    };
+
+    let first = or_cancel(&opts.cancel, async { Ok(archive.read_part(part_size as usize).await) }).await?;
+    let mut next = or_cancel(&opts.cancel, async { Ok(archive.read_part(part_size as usize).await) }).await?;
+    if next.is_empty() {
+        let manifest = archive.finish().await?;
+        let size = first.len() as u64;
+        let sha256 = format!("{:x}", Sha256::digest(&first));
+        put_bytes(client, target, first, &sha256, opts).await?;
+        return Ok((sha256, true, size, manifest));
+    }
+
+    let mut entry = JournalEntry {
+        id: Uuid::new_v4().to_string(),
+        upload_id: String::new(),
+        bucket: target.bucket.to_string(),
+        key: target.key.to_string(),
+        headers: target.headers.clone(),
+        part_size,
+        file_path: String::new(),
+        file_name: target.key.rsplit('/').next().unwrap_or(target.key).to_string(),
+        fingerprint: FileFingerprint::default(),
+        mode: target.destination_id.to_string(),
+        ttl: target.ttl,
+        started: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+        sha256: None,
+        encryption: target.sse.mode(),
+        parts: BTreeMap::new(),
+    };
+    entry.upload_id = create_multipart_upload(client, &entry, target.sse, opts).await?;
+
+    let parallelism = opts
+        .parallelism
+        .min((MAX_BUFFERED_BYTES / part_size) as usize)
+        .max(1);
+    let sem = Arc::new(Semaphore::new(parallelism));
+    let mut joins: tokio::task::JoinSet<Result<(i32, Option<String>), String>> =
+        tokio::task::JoinSet::new();
+    let mut failure: Option<String> = None;
+    let mut hasher = Sha256::new();
+    let mut size = 0u64;
+    let mut buf = first;
+
+    for part_num in 1.. {
+        while let Some(joined) = joins.try_join_next() {
+            handle_part_result(joined, &mut entry, &mut failure, &mut joins, opts);
+        }
+        if failure.is_some() {
+            break;
+        }
+        if part_num > MAX_PARTS {
+            failure = Some(format!(
+                "Bundle needs more than 10,000 parts of {} MiB; raise the part size",
+                part_size / (1024 * 1024)
+            ));
+            break;
+        }
+
+        let permit = tokio::select! {
+            _ = opts.cancel.cancelled() => {
+                failure = Some(CANCELLED.to_string());
+                break;
+            }
+            permit = sem.clone().acquire_owned() => {
+                permit.map_err(|e| format!("semaphore closed: {}", e))?
+            }
+        };
+        hasher.update(&buf);
+        size += buf.len() as u64;
+        let upload = upload_part(client, &entry, part_num, buf, target.sse, opts);
+        joins.spawn(async move {
+            let _permit = permit;
+            upload.await
+        });
+
+        let read = tokio::select! {
+            _ = opts.cancel.cancelled() => None,
+            part = archive.read_part(part_size as usize) => Some(part),
+        };
+        buf = match read {
+            Some(part) => std::mem::replace(&mut next, part),
+            None => {
+                failure = Some(CANCELLED.to_string());
+                break;
+            }
+        };
+        if buf.is_empty() {
+            break;
+        }
+    }
+
+    loop {
+        let joined = tokio::select! {
+            _ = opts.cancel.cancelled(), if failure.as_deref() != Some(CANCELLED) => {
+                failure = Some(CANCELLED.to_string());
+                joins.abort_all();
+                continue;
+            }
+            joined = joins.join_next() => joined,
+        };
+        match joined {
+            Some(joined) => handle_part_result(joined, &mut entry, &mut failure, &mut joins, opts),
+            None => break,
+        }
+    }
+
+    // A file that couldn't be read leaves a truncated archive; never complete it
+    let finished = match failure {
+        Some(e) => Err(e),
+        None => archive.finish().await,
+    };
+    let manifest = match finished {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            abort_multipart(client, &entry).await;
+            return Err(e);
+        }
+    };
+
+    if let Err(e) = complete_multipart_upload(client, &entry, size, target.sse, opts).await {
+        abort_multipart(client, &entry).await;
+        return Err(e);
+    }
+    let sha256 = format!("{:x}", hasher.finalize());
+    let stored = attach_sha256_metadata(client, &entry, &sha256, size, target.sse, opts).await;
+    Ok((sha256, stored, size, manifest))
+}
+
+/// Finish an interrupted multipart upload recorded in the journal. The
+/// source file must be unchanged; B2 is asked which parts it already has
+/// and only the missing ones are sent.
+pub async fn resume_upload(
+    journal_id: &str,
+    config: &HashMap<String, String>,
+    creds: &B2Credentials,
+    opts: &UploadOptions,
+) -> Result<(JournalEntry, UploadOutcome), String> {
+    let journal = opts
+        .journal
+        .as_ref()
+        .ok_or("Resuming requires the upload journal")?;
+    let mut entry = journal.load(journal_id)?;
+
+    let path = PathBuf::from(&entry.file_path);
+    if !path.exists() {
+        return Err(format!("File not found: {}", entry.file_path));
+    }
+    if FileFingerprint::compute(&path)? != entry.fingerprint {
+        return Err("File has changed since the upload started; discard and upload it again".to_string());
+    }
+
+    let sse = Encryption::resolve(entry.encryption, creds, &entry.mode)?;
+    let provider = Provider::from_config(config)?;
+    let client = build_client(&provider, creds);
+
+    if let Err(e) = reconcile_parts(&client, &mut entry).await {
+        if e.starts_with("The interrupted upload no longer exists") {
+            journal.remove(&entry.id);
+        }
+        return Err(e);
+    }
+
+    let (sha256, sha256_stored) = multipart_upload(&client, &path, entry.clone(), &sse, opts).await?;
+
+    let url = build_url(&client, &entry.key, &entry.mode, config, creds, entry.ttl, &opts.download_auth).await?;
+    let outcome = UploadOutcome {
+        url,
+        key: entry.key.clone(),
+        sha256,
+        sha256_stored,
+        reused: false,
+        stripped: Vec::new(),
+        variants: Vec::new(),
+        srcset: None,
+        transform: None,
+        compression: None,
+    };
+    Ok((entry, outcome))
+}
+
+/// Abort an interrupted multipart upload and forget it.
+pub async fn discard_upload(
+    journal: &Journal,
+    journal_id: &str,
+    config: &HashMap<String, String>,
+    creds: &B2Credentials,
+) -> Result<(), String> {
+    let entry = journal.load(journal_id)?;
+    let provider = Provider::from_config(config)?;
+    let client = build_client(&provider, creds);
+    abort_multipart(&client, &entry).await;
+    journal.remove(&entry.id);
+    Ok(())
+}
+
+/// Download a URL to a temporary file, preserving the original extension.
+/// The body is streamed to disk chunk by chunk so multi-GB files never sit
+/// in memory. `progress` receives (bytes_done, content_length), where the
+/// total is 0 when the server doesn't send a Content-Length.
+/// Returns the path to the temp file.
+pub async fn download_url(
+    url: &str,
+    progress: Option<ProgressFn>,
+    cancel: &CancellationToken,
+) -> Result<String, String> {
+    let mut response = or_cancel(cancel, async {
+        reqwest::get(url)
+            .await
+            .map_err(|e| format!("Download failed: {}", e))
+    })
+    .await?;
+
+    if !response.status().is_success() {
+        return Err(format!("Download failed: HTTP {}", response.status()));
+    }
+
+    // Extract filename from URL path (strip query params)
+    let parsed = url.split('?').next().unwrap_or(url);
+    let url_filename = parsed.rsplit('/').next().unwrap_or("download");
+
+    // Get extension, default to "bin"
+    let ext = Path::new(url_filename)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("bin");
 
     // Create temp file with the right extension
     let tmp_dir = std::env::temp_dir();
     let tmp_path = tmp_dir.join(format!("b2upload_{}.{}", Uuid::new_v4(), ext));
 
-    let bytes = response
-        .bytes()
-        .await
-        .map_err(|e| format!("Failed to read response body: {}", e))?;
+    let total = response.content_length().unwrap_or(0);
+    if let Some(cb) = &progress {
+        cb(0, total);
+    }
 
-    let mut file = tokio::fs::File::create(&tmp_path)
+    let file = tokio::fs::File::create(&tmp_path)
         .await
         .map_err(|e| format!("Failed to create temp file: {}", e))?;
+    let mut writer = tokio::io::BufWriter::new(file);
+
+    let result: Result<(), String> = async {
+        let mut done: u64 = 0;
+        let mut last_reported: u64 = 0;
+        while let Some(chunk) = or_cancel(cancel, async {
+            response
+                .chunk()
+                .await
+                .map_err(|e| format!("Failed to read response body: {}", e))
+        })
+        .await?
+        {
+            writer
+                .write_all(&chunk)
+                .await
+                .map_err(|e| format!("Failed to write temp file: {}", e))?;
+            done += chunk.len() as u64;
+            if done - last_reported >= PROGRESS_STEP {
+                last_reported = done;
+                if let Some(cb) = &progress {
+                    cb(done, total);
+                }
+            }
+        }
 
-    file.write_all(&bytes)
-        .await
-        .map_err(|e| format!("Failed to write temp file: {}", e))?;
+        writer
+            .flush()
+            .await
+            .map_err(|e| format!("Failed to flush temp file: {}", e))?;
 
-    file.flush()
-        .await
-        .map_err(|e| format!("Failed to flush temp file: {}", e))?;
+        if let Some(cb) = &progress {
+            cb(done, if total > 0 { total } else { done });
+        }
+        Ok(())
+    }
+    .await;
+
+    if let Err(e) = result {
+        drop(writer);
+        let _ = tokio::fs::remove_file(&tmp_path).await;
+        return Err(e);
+    }
 
     Ok(tmp_path.to_string_lossy().to_string())
 }
@@ -491,11 +2302,11 @@ pub async fn test_connection(
     config: &HashMap<String, String>,
     creds: &B2Credentials,
 ) -> Result<String, String> {
-    let endpoint = config.get("S3_ENDPOINT").ok_or("Missing S3_ENDPOINT")?;
+    let provider = Provider::from_config(config)?;
     let bucket = config.get("BUCKET_NAME").ok_or("Missing BUCKET_NAME")?;
 
     // Build a fresh client for this operation; drops when function returns
-    let client = build_client(endpoint, creds);
+    let client = build_client(&provider, creds);
 
     client
         .head_bucket()
@@ -504,6 +2315,29 @@ pub async fn test_connection(
         .await
         .map_err(|e| format!("Connection failed: {}", format_sdk_error(&e)))?;
 
+    let encryption = match client.get_bucket_encryption().bucket(bucket).send().await {
+        Ok(resp) => {
+            let algorithms: Vec<&str> = resp
+                .server_side_encryption_configuration()
+                .map(|c| c.rules())
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|r| r.apply_server_side_encryption_by_default())
+                .map(|d| d.sse_algorithm().as_str())
+                .collect();
+            if algorithms.is_empty() {
+                "bucket has no default encryption".to_string()
+            } else {
+                format!("bucket encrypts by default ({})", algorithms.join(", "))
+            }
+        }
+        Err(e) if e.code() == Some("ServerSideEncryptionConfigurationNotFoundError") => {
+            "bucket has no default encryption".to_string()
+        }
+        // Keys without readBucketEncryption, or services without the API
+        Err(_) => "default encryption unknown".to_string(),
+    };
+
     // client drops here -- AWS SDK zeroizes its internal credential buffers
-    Ok("Connection successful".to_string())
+    Ok(format!("Connection successful; {}", encryption))
 }
@@ -0,0 +1,212 @@
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use tokio_util::sync::CancellationToken;
+
+use crate::transform;
+use crate::uploader::{UploadedVariant, CANCELLED};
+
+const DEFAULT_WIDTHS: &[u32] = &[320, 800, 1600];
+const DEFAULT_THUMBNAIL_SIZE: u32 = 200;
+const MIN_SIZE: u32 = 16;
+const MAX_SIZE: u32 = 8192;
+/// JPEG and WebP quality of the variants.
+const QUALITY: u8 = 82;
+/// Label of the thumbnail, used in its key (`photo-thumb.jpg`).
+pub const THUMBNAIL_LABEL: &str = "thumb";
+
+/// Which resized copies to make of uploaded images, from IMAGE_VARIANTS,
+/// IMAGE_VARIANT_WIDTHS and IMAGE_THUMBNAIL_SIZE.
+pub struct VariantSettings {
+    widths: Vec<u32>,
+    /// Longest side of the thumbnail; 0 for none.
+    thumbnail: u32,
+}
+
+impl VariantSettings {
+    /// None when variants are off.
+    pub fn from_config(config: &HashMap<String, String>) -> Result<Option<Self>, String> {
+        if config.get("IMAGE_VARIANTS").map(|s| s.as_str()).unwrap_or("off") != "on" {
+            return Ok(None);
+        }
+        let widths = match config.get("IMAGE_VARIANT_WIDTHS").map(|s| s.trim()).filter(|s| !s.is_empty()) {
+            Some(list) => {
+                let mut widths = Vec::new();
+                for part in list.split(',') {
+                    let width = part
+                        .trim()
+                        .parse::<u32>()
+                        .ok()
+                        .filter(|w| (MIN_SIZE..=MAX_SIZE).contains(w))
+                        .ok_or_else(|| {
+                            format!("Image variant widths must be {}-{} px, got \"{}\"", MIN_SIZE, MAX_SIZE, part.trim())
+                        })?;
+                    widths.push(width);
+                }
+                widths.sort_unstable();
+                widths.dedup();
+                widths
+            }
+            None => DEFAULT_WIDTHS.to_vec(),
+        };
+        let thumbnail = config
+            .get("IMAGE_THUMBNAIL_SIZE")
+            .and_then(|s| s.trim().parse::<u32>().ok())
+            .unwrap_or(DEFAULT_THUMBNAIL_SIZE)
+            .min(MAX_SIZE);
+        Ok(Some(Self { widths, thumbnail }))
+    }
+}
+
+/// One resized copy, written to the temp directory of its `Renditions`.
+pub struct Rendition {
+    /// `320w` style for widths, `thumb` for the thumbnail.
+    pub label: String,
+    pub width: u32,
+    pub height: u32,
+    pub path: PathBuf,
+}
+
+/// The resized copies of one image. The directory is removed when this is
+/// dropped.
+pub struct Renditions {
+    _dir: tempfile::TempDir,
+    /// Width of the original after applying its EXIF orientation; it's the
+    /// largest entry in the srcset.
+    pub source_width: u32,
+    pub items: Vec<Rendition>,
+}
+
+/// Decode `src` and write its variants: each configured width narrower than
+/// the image, and the thumbnail if the image is bigger than it. Images are
+/// never enlarged. Returns None for anything but JPEG, PNG and WebP (GIFs
+/// may be animated, and a single frame isn't a fair stand-in).
+///
+/// Variants keep the original's format, are turned upright according to its
+/// EXIF orientation, and carry no metadata.
+pub async fn render(
+    src: &Path,
+    settings: &VariantSettings,
+    cancel: &CancellationToken,
+) -> Result<Option<Renditions>, String> {
+    let src = src.to_path_buf();
+    let widths = settings.widths.clone();
+    let thumbnail = settings.thumbnail;
+    let cancel = cancel.clone();
+    tokio::task::spawn_blocking(move || render_blocking(&src, &widths, thumbnail, &cancel))
+        .await
+        .map_err(|e| format!("Image variant task failed: {}", e))?
+}
+
+fn render_blocking(
+    src: &Path,
+    widths: &[u32],
+    thumbnail: u32,
+    cancel: &CancellationToken,
+) -> Result<Option<Renditions>, String> {
+    let reader = ImageReader::open(src)
+        .and_then(|r| r.with_guessed_format())
+        .map_err(|e| format!("Failed to open image: {}", e))?;
+    let format = match reader.format() {
+        Some(f @ (ImageFormat::Jpeg | ImageFormat::Png | ImageFormat::WebP)) => f,
+        _ => return Ok(None),
+    };
+    let mut decoder = reader
+        .into_decoder()
+        .map_err(|e| format!("Failed to read image: {}", e))?;
+    let orientation = decoder
+        .orientation()
+        .map_err(|e| format!("Failed to read image: {}", e))?;
+    let mut image = DynamicImage::from_decoder(decoder).map_err(|e| format!("Failed to decode image: {}", e))?;
+    image.apply_orientation(orientation);
+
+    let dir = tempfile::Builder::new()
+        .prefix("b2upload_")
+        .tempdir()
+        .map_err(|e| format!("Failed to create temp dir: {}", e))?;
+    let stem = src.file_stem().and_then(|s| s.to_str()).unwrap_or("image");
+    let ext = src.extension().and_then(|e| e.to_str()).unwrap_or(format.extensions_str()[0]);
+
+    let mut sizes: Vec<(String, DynamicImage)> = Vec::new();
+    for &width in widths.iter().filter(|&&w| w < image.width()) {
+        if cancel.is_cancelled() {
+            return Err(CANCELLED.to_string());
+        }
+        sizes.push((format!("{}w", width), image.resize(width, u32::MAX, FilterType::Lanczos3)));
+    }
+    if thumbnail > 0 && (image.width() > thumbnail || image.height() > thumbnail) {
+        sizes.push((THUMBNAIL_LABEL.to_string(), image.thumbnail(thumbnail, thumbnail)));
+    }
+
+    let mut items = Vec::new();
+    for (label, resized) in sizes {
+        if cancel.is_cancelled() {
+            return Err(CANCELLED.to_string());
+        }
+        let path = dir.path().join(format!("{}-{}.{}", stem, label, ext));
+        encode(&resized, format, &path)?;
+        items.push(Rendition {
+            label,
+            width: resized.width(),
+            height: resized.height(),
+            path,
+        });
+    }
+
+    Ok(Some(Renditions {
+        _dir: dir,
+        source_width: image.width(),
+        items,
+    }))
+}
+
+fn encode(image: &DynamicImage, format: ImageFormat, path: &Path) -> Result<(), String> {
+    let file = fs::File::create(path).map_err(|e| format!("Failed to create temp file: {}", e))?;
+    let mut writer = BufWriter::new(file);
+    let result = match format {
+        // JPEG has no alpha channel; the source didn't either
+        ImageFormat::Jpeg => {
+            JpegEncoder::new_with_quality(&mut writer, QUALITY).encode_image(&DynamicImage::ImageRgb8(image.to_rgb8()))
+        }
+        ImageFormat::Png => image.write_with_encoder(PngEncoder::new(&mut writer)),
+        _ => {
+            let memory = transform::encode_webp(image, Some(QUALITY))?;
+            writer.write_all(&memory).map_err(image::ImageError::IoError)
+        }
+    };
+    result.map_err(|e| format!("Failed to encode image variant: {}", e))?;
+    writer.flush().map_err(|e| format!("Failed to write temp file: {}", e))
+}
+
+/// Key for a variant next to `key`: the label goes before the extension,
+/// so `2026/photo.jpg` becomes `2026/photo-800w.jpg`.
+pub fn sibling_key(key: &str, label: &str) -> String {
+    let (dir, name) = match key.rfind('/') {
+        Some(i) => key.split_at(i + 1),
+        None => ("", key),
+    };
+    match name.rfind('.').filter(|&i| i > 0) {
+        Some(i) => format!("{}{}-{}{}", dir, &name[..i], label, &name[i..]),
+        None => format!("{}{}-{}", dir, name, label),
+    }
+}
+
+/// A `srcset` value: each width variant and the original at its own width.
+/// None if no width variants were made (the image was already small).
+pub fn srcset(url: &str, source_width: u32, uploaded: &[UploadedVariant]) -> Option<String> {
+    let mut entries: Vec<String> = uploaded
+        .iter()
+        .filter(|v| v.label != THUMBNAIL_LABEL)
+        .map(|v| format!("{} {}w", v.url, v.width))
+        .collect();
+    if entries.is_empty() {
+        return None;
+    }
+    entries.push(format!("{} {}w", url, source_width));
+    Some(entries.join(", "))
+}
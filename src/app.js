@@ -11,16 +11,51 @@ function newUploadId() {
     return "u-" + Date.now().toString(36) + "-" + Math.random().toString(36).slice(2, 10);
 }
 
+// Error the backend returns once a cancelled upload has stopped
+const CANCELLED_ERROR = "Upload cancelled";
+
+function isCancelled(err) {
+    return String(err) === CANCELLED_ERROR;
+}
+
+function formatBytes(n) {
+    if (n < 1024) return n + " B";
+    const units = ["KB", "MB", "GB", "TB"];
+    let v = n / 1024;
+    let i = 0;
+    while (v >= 1024 && i < units.length - 1) {
+        v /= 1024;
+        i++;
+    }
+    return v.toFixed(v < 10 ? 1 : 0) + " " + units[i];
+}
+
 listen("upload-progress", (event) => {
     const p = event.payload || {};
     const row = uploadRows.get(p.uploadId);
     if (!row) return;
+    if (p.phase === "cancelled") {
+        setRowCancelled(row);
+        return;
+    }
+    const statusEl = row.querySelector(".r-status.pending");
+    // A retry carries no byte counts; leave the bar where it is
+    if (p.phase === "retrying") {
+        if (statusEl) statusEl.textContent = `retrying ${p.operation} (${p.reason})`;
+        return;
+    }
     const fill = row.querySelector(".r-progress-fill");
     if (!fill) return;
     const total = Number(p.bytesTotal) || 0;
     const done = Number(p.bytesDone) || 0;
     const pct = total > 0 ? Math.min(100, Math.round((done / total) * 100)) : 0;
     fill.style.width = pct + "%";
+    // Label the phase; without a Content-Length only the byte count is known
+    if (statusEl && p.phase) {
+        let label = total > 0 ? p.phase : `${p.phase} ${formatBytes(done)}`;
+        if (p.retries > 0) label += ` · ${p.retries} ${p.retries === 1 ? "retry" : "retries"}`;
+        statusEl.textContent = label;
+    }
 });
 
 const mainView = document.getElementById("main-view");
@@ -39,17 +74,20 @@ const resultsBox = document.getElementById("results");
 const resultsBody = document.getElementById("results-body");
 const copyAllBtn = document.getElementById("copy-all-btn");
 const settingsForm = document.getElementById("settings-form");
-const modeToggle = document.getElementById("mode-toggle");
+const destinationSelect = document.getElementById("destination-select");
+const destinationList = document.getElementById("destination-list");
 const clipToggle = document.getElementById("clip-toggle");
 
 // State
-let mode = "folder2";
+let mode = localStorage.getItem("b2u_mode") || "folder2"; // destination ID
+let destinations = []; // [{id, name, prefix, keyTemplate, encryption, e2e, recipients, hasToken, hasSseKey}]
 let autoClip = true;
 let lastResults = []; // [{file, url}]
 let tokenMode = "static";
 let isUploading = false;
 let uploadCancelled = false;
 let notificationsEnabled = true;
+let bundleFormat = "off"; // archive format for multi-file drops, or "off"
 
 const cancelBtn = document.getElementById("cancel-upload-btn");
 
@@ -57,24 +95,16 @@ const cancelBtn = document.getElementById("cancel-upload-btn");
 const ttlBar = document.getElementById("ttl-bar");
 const ttlSelect = document.getElementById("ttl-select");
 const ttlCustom = document.getElementById("ttl-custom");
-const toggleTokenMode = document.getElementById("toggle-token-mode");
+const tokenModeSelect = document.getElementById("token-mode-select");
 const dynamicTokenSettings = document.getElementById("dynamic-token-settings");
+const tokenSecretField = document.getElementById("token-secret-field");
+const signingKeySettings = document.getElementById("signing-key-settings");
+const signingKeyList = document.getElementById("signing-key-list");
+const presignedDispositionField = document.getElementById("presigned-disposition-field");
 const defaultTtlSelect = document.getElementById("default-ttl-select");
 const defaultTtlCustom = document.getElementById("default-ttl-custom");
 
-const modeLeftLabel = document.querySelector(".toggle-group .toggle-label:first-child");
-const modeRightLabel = document.querySelector(".toggle-group .toggle-label:last-child");
-
 // Restore persisted toggle states
-const savedMode = localStorage.getItem("b2u_mode");
-if (savedMode === "folder1") {
-    mode = "folder1";
-    modeToggle.classList.remove("on");
-    modeToggle.setAttribute("aria-checked", "false");
-} else {
-    modeToggle.classList.add("on");
-}
-
 const savedClip = localStorage.getItem("b2u_autoClip");
 if (savedClip === "off") {
     autoClip = false;
@@ -84,10 +114,8 @@ if (savedClip === "off") {
     clipToggle.classList.add("on");
 }
 
-modeToggle.addEventListener("click", () => {
-    const isOn = modeToggle.classList.toggle("on");
-    modeToggle.setAttribute("aria-checked", isOn);
-    mode = isOn ? "folder2" : "folder1";
+destinationSelect.addEventListener("change", () => {
+    mode = destinationSelect.value;
     localStorage.setItem("b2u_mode", mode);
 });
 
@@ -113,8 +141,16 @@ const urlSubmitBtn = document.getElementById("url-submit-btn");
 urlInput.addEventListener("input", () => {
     const hasValue = urlInput.value.trim().length > 0;
     urlSubmitBtn.classList.toggle("hidden", !hasValue);
+    urlSubmitBtn.textContent = isEncryptedLink(urlInput.value.trim()) ? "Decrypt" : "Upload";
 });
 
+// End-to-end encrypted links carry the key in the #fragment, or were
+// sealed to team keys and end in .age
+function isEncryptedLink(url) {
+    const [base, fragment = ""] = url.split("#");
+    return fragment.startsWith("AGE-SECRET-KEY-") || base.split("?")[0].endsWith(".age");
+}
+
 urlSubmitBtn.addEventListener("click", () => handleUrlUpload());
 urlInput.addEventListener("keydown", (e) => {
     if (e.key === "Enter" && urlInput.value.trim()) handleUrlUpload();
@@ -128,8 +164,13 @@ async function handleUrlUpload() {
         showStatus("Invalid URL", "error");
         return;
     }
+    if (isEncryptedLink(url)) {
+        await handleDecrypt(url);
+        return;
+    }
 
     isUploading = true;
+    uploadCancelled = false;
     urlInput.disabled = true;
     urlSubmitBtn.disabled = true;
     urlSubmitBtn.textContent = "Downloading...";
@@ -156,16 +197,18 @@ async function handleUrlUpload() {
     const card = addResultRow(fileName);
     const uploadId = newUploadId();
     uploadRows.set(uploadId, card);
+    cancelBtn.classList.remove("hidden");
 
     try {
-        const resultUrl = await invoke("download_and_upload_url", {
+        const result = await invoke("download_and_upload_url", {
             uploadId,
             url,
             mode,
             autoClip: false,
             ttl: getCurrentTtl(),
         });
-        setRowSuccess(card, resultUrl);
+        const resultUrl = result.url;
+        setRowFileResult(card, result);
         lastResults.push({ file: fileName, url: resultUrl });
 
         if (autoClip) {
@@ -179,10 +222,16 @@ async function handleUrlUpload() {
         urlInput.value = "";
         urlSubmitBtn.classList.add("hidden");
     } catch (err) {
-        setRowError(card, err.toString());
-        showStatus("Upload failed", "error");
+        if (isCancelled(err)) {
+            setRowCancelled(card);
+            showStatus("Upload cancelled", "");
+        } else {
+            setRowError(card, err.toString());
+            showStatus("Upload failed", "error");
+        }
     }
     uploadRows.delete(uploadId);
+    cancelBtn.classList.add("hidden");
 
     dropZone.classList.remove("uploading");
     dropZone.querySelector("p").textContent = "Drop or click to upload";
@@ -192,8 +241,58 @@ async function handleUrlUpload() {
     isUploading = false;
 }
 
+async function handleDecrypt(url) {
+    let fileName = url.split("#")[0].split("?")[0].split("/").pop() || "download.age";
+    try {
+        fileName = decodeURIComponent(fileName);
+    } catch (_) {}
+    fileName = fileName.replace(/\.age$/, "");
+
+    const savePath = await window.__TAURI__.dialog.save({ defaultPath: fileName });
+    if (!savePath) return;
+
+    isUploading = true;
+    uploadCancelled = false;
+    urlInput.disabled = true;
+    urlSubmitBtn.disabled = true;
+    urlSubmitBtn.textContent = "Decrypting...";
+    hideResults();
+    resultsBox.classList.remove("hidden");
+    showStatus("", "");
+
+    const card = addResultRow(fileName);
+    card.querySelector(".r-status").textContent = "downloading";
+    const uploadId = newUploadId();
+    uploadRows.set(uploadId, card);
+    cancelBtn.classList.remove("hidden");
+
+    try {
+        await invoke("decrypt_download", { uploadId, url, savePath });
+        setRowSuccess(card, savePath);
+        card.querySelector(".r-status").textContent = "decrypted";
+        showStatus("Decrypted and saved", "success");
+        urlInput.value = "";
+        urlSubmitBtn.classList.add("hidden");
+    } catch (err) {
+        if (isCancelled(err)) {
+            setRowCancelled(card);
+            showStatus("Download cancelled", "");
+        } else {
+            setRowError(card, err.toString());
+            showStatus("Decryption failed", "error");
+        }
+    }
+    uploadRows.delete(uploadId);
+    cancelBtn.classList.add("hidden");
+
+    urlInput.disabled = false;
+    urlSubmitBtn.disabled = false;
+    urlSubmitBtn.textContent = "Decrypt";
+    isUploading = false;
+}
+
 function getCurrentTtl() {
-    if (tokenMode !== "dynamic") return null;
+    if (tokenMode === "static") return null;
     if (ttlSelect.value === "custom") {
         const v = parseInt(ttlCustom.value, 10);
         return v > 0 ? v : null;
@@ -204,10 +303,19 @@ function getCurrentTtl() {
 function applyTokenMode(mode) {
     tokenMode = mode;
     // Main view: show/hide TTL bar
-    ttlBar.classList.toggle("hidden", mode !== "dynamic");
-    // Settings view: show/hide static token fields and dynamic settings
+    ttlBar.classList.toggle("hidden", mode === "static");
+    showTokenModeFields(mode);
+}
+
+// Settings view: static tokens are set per destination, dynamic ones need
+// the shared secret, presigned links can override Content-Disposition
+function showTokenModeFields(mode) {
+    dynamicTokenSettings.classList.toggle("hidden", mode === "static");
+    tokenSecretField.classList.toggle("hidden", mode !== "dynamic");
+    signingKeySettings.classList.toggle("hidden", mode !== "dynamic");
+    presignedDispositionField.classList.toggle("hidden", mode !== "presigned");
     document.querySelectorAll(".static-token-field").forEach(el => {
-        el.classList.toggle("hidden", mode === "dynamic");
+        el.classList.toggle("hidden", mode !== "static");
     });
 }
 
@@ -261,6 +369,118 @@ function setRowSuccess(card, url) {
     });
 }
 
+// Single files: image rules report what they did and the bytes saved;
+// uploads with variants also get a button that copies their srcset, and
+// list the variant URLs under the link.
+// "1.2 MB → 300 KB (−75%)"
+function formatSizeChange(before, after) {
+    const pct = Math.round((after / before - 1) * 100);
+    const change = pct > 0 ? `+${pct}%` : `−${-pct}%`;
+    return `${formatBytes(before)} → ${formatBytes(after)} (${change})`;
+}
+
+function setRowFileResult(card, result) {
+    setRowSuccess(card, result.url);
+    addChecksumNote(card, result);
+    if (result.transform) {
+        const t = result.transform;
+        const notes = [...t.steps];
+        if (t.bytes !== t.originalBytes) notes.push(formatSizeChange(t.originalBytes, t.bytes));
+        if (t.skipped) notes.push(t.skipped);
+        const line = document.createElement("div");
+        line.className = "r-transform";
+        line.textContent = notes.join(" · ");
+        card.insertBefore(line, card.querySelector(".r-progress"));
+    }
+    if (result.compression) {
+        const c = result.compression;
+        const line = document.createElement("div");
+        line.className = "r-transform";
+        line.textContent = `${c.encoding} · ${formatSizeChange(c.originalBytes, c.bytes)}`;
+        card.insertBefore(line, card.querySelector(".r-progress"));
+    }
+    if (!result.variants || result.variants.length === 0) return;
+    const list = document.createElement("div");
+    list.className = "r-variants";
+    list.textContent = result.variants.map((v) => `${v.label} ${v.width}×${v.height}: ${v.url}`).join("\n");
+    card.insertBefore(list, card.querySelector(".r-progress"));
+    if (!result.srcset) return;
+    const btn = document.createElement("button");
+    btn.className = "r-copy-btn r-srcset-btn";
+    btn.textContent = "Copy srcset";
+    btn.addEventListener("click", async () => {
+        await invoke("copy_to_clipboard", { text: result.srcset });
+        btn.textContent = "Copied!";
+        setTimeout(() => {
+            btn.textContent = "Copy srcset";
+        }, 1500);
+    });
+    card.appendChild(btn);
+}
+
+// Directory uploads: the row links the common prefix and lists any files
+// that failed underneath it.
+function setRowDirectoryResult(card, result) {
+    setRowSuccess(card, result.prefixUrl);
+    const failures = result.files.filter((f) => f.error);
+    const statusEl = card.querySelector(".r-status");
+    statusEl.textContent = `${result.files.length - failures.length} files`;
+    if (failures.length > 0) {
+        statusEl.textContent += ` · ${failures.length} failed`;
+        statusEl.className = "r-status error";
+        const list = document.createElement("div");
+        list.className = "r-file-errors";
+        list.textContent = failures.map((f) => `${f.path}: ${f.error}`).join("\n");
+        card.insertBefore(list, card.querySelector(".r-progress"));
+    }
+    const renamed = result.files.filter((f) => !f.error && f.key.split("/").pop() !== f.path.split("/").pop());
+    if (renamed.length > 0) {
+        const line = document.createElement("div");
+        line.className = "r-transform";
+        line.textContent = `converted ${renamed.length} ${renamed.length === 1 ? "image" : "images"}`;
+        line.title = renamed.map((f) => `${f.path} → ${f.key.split("/").pop()}`).join("\n");
+        card.insertBefore(line, card.querySelector(".r-progress"));
+    }
+    const unhashed = result.files.filter((f) => !f.error && f.sha256Stored === false);
+    if (unhashed.length > 0) {
+        const line = document.createElement("div");
+        line.className = "r-checksum-missing";
+        line.textContent = `SHA-256 not stored as object metadata for ${unhashed.length} ${unhashed.length === 1 ? "file" : "files"}`;
+        line.title = unhashed.map((f) => f.path).join("\n");
+        card.insertBefore(line, card.querySelector(".r-progress"));
+    }
+    const compressed = result.files.filter((f) => f.compression);
+    if (compressed.length > 0) {
+        const original = compressed.reduce((sum, f) => sum + f.compression.originalBytes, 0);
+        const stored = compressed.reduce((sum, f) => sum + f.compression.bytes, 0);
+        const line = document.createElement("div");
+        line.className = "r-transform";
+        line.textContent = `compressed ${compressed.length} ${compressed.length === 1 ? "file" : "files"} · ${formatSizeChange(original, stored)}`;
+        card.insertBefore(line, card.querySelector(".r-progress"));
+    }
+}
+
+// Bundles: one link for the archive, with its contents listed underneath.
+function setRowBundleResult(card, result) {
+    setRowSuccess(card, result.url);
+    card.querySelector(".r-status").textContent = `${result.manifest.length} files · ${formatBytes(result.size)}`;
+    addChecksumNote(card, result);
+    const list = document.createElement("div");
+    list.className = "r-manifest";
+    list.textContent = result.manifest.map((f) => `${f.path} (${formatBytes(f.size)})`).join("\n");
+    card.insertBefore(list, card.querySelector(".r-progress"));
+}
+
+// Multipart objects whose hash wasn't known when they were started carry
+// no sha256 metadata; say so rather than let later checks find it missing.
+function addChecksumNote(card, result) {
+    if (result.sha256Stored !== false) return;
+    const line = document.createElement("div");
+    line.className = "r-checksum-missing";
+    line.textContent = "SHA-256 not stored as object metadata";
+    card.insertBefore(line, card.querySelector(".r-progress"));
+}
+
 function setRowError(card, msg) {
     card.classList.add("done");
     card.querySelector(".r-url").textContent = msg;
@@ -295,7 +515,7 @@ historySearch.addEventListener("input", () => {
 function renderHistoryList(filter) {
     historyList.innerHTML = "";
     const filtered = filter
-        ? fullHistory.filter(e => e.file.toLowerCase().includes(filter) || e.url.toLowerCase().includes(filter))
+        ? fullHistory.filter(e => e.file.toLowerCase().includes(filter) || e.url.toLowerCase().includes(filter) || (e.sha256 || "").startsWith(filter))
         : fullHistory;
     if (filtered.length === 0) {
         historyEmpty.classList.remove("hidden");
@@ -309,6 +529,13 @@ function renderHistoryList(filter) {
     historyList.appendChild(frag);
 }
 
+// Tooltip for a bundle's history entry: the first paths in the archive.
+function manifestSummary(manifest) {
+    const shown = manifest.slice(0, 20).map((f) => f.path);
+    if (manifest.length > shown.length) shown.push(`…and ${manifest.length - shown.length} more`);
+    return shown.join("\n");
+}
+
 function createHistoryItem(entry) {
     const item = document.createElement("div");
     item.className = "history-item";
@@ -321,8 +548,14 @@ function createHistoryItem(entry) {
       </div>
       <div class="h-url">${escapeHtml(entry.url)}</div>
       <div class="h-meta">
-        <span class="h-mode ${entry.mode === "shared" ? "shared" : ""}">${entry.mode}</span>
+        <span class="h-mode ${entry.mode === "shared" ? "shared" : ""}">${escapeHtml(entry.destination || entry.mode)}</span>
         <span>${entry.datetime}</span>
+        ${entry.srcset ? `<button class="h-srcset-btn" title="Copy srcset (${entry.variants.length} variants)">srcset</button>` : ""}
+        ${entry.stripped && entry.stripped.length ? `<span class="h-stripped" title="Removed: ${escapeAttr(entry.stripped.join(", "))}">stripped</span>` : ""}
+        ${entry.manifest ? `<span class="h-files" title="${escapeAttr(manifestSummary(entry.manifest))}">${entry.manifest.length} files</span>` : ""}
+        ${entry.compression ? `<span class="h-compressed" title="${escapeAttr(formatSizeChange(entry.compression.originalBytes, entry.compression.bytes))}">${escapeHtml(entry.compression.encoding)}</span>` : ""}
+        ${entry.sha256Stored === false ? `<span class="h-no-checksum" title="The object has no sha256 metadata">no checksum metadata</span>` : ""}
+        ${entry.sha256 ? `<span class="h-hash" title="SHA-256 ${escapeAttr(entry.sha256)}">${escapeHtml(entry.sha256.slice(0, 12))}</span>` : ""}
       </div>
     `;
     // Delete button handler
@@ -337,14 +570,15 @@ function createHistoryItem(entry) {
     });
     const copyHandler = async (e) => {
         if (e.target.closest(".h-delete-btn")) return;
-        await invoke("copy_to_clipboard", { text: entry.url });
+        const srcset = e.target.closest(".h-srcset-btn") !== null;
+        await invoke("copy_to_clipboard", { text: srcset ? entry.srcset : entry.url });
         let copied = item.querySelector(".h-copied");
         if (!copied) {
             copied = document.createElement("div");
             copied.className = "h-copied";
             item.appendChild(copied);
         }
-        copied.textContent = "Copied!";
+        copied.textContent = srcset ? "Copied srcset!" : "Copied!";
         setTimeout(() => { copied.textContent = ""; }, 1500);
     };
     item.addEventListener("click", copyHandler);
@@ -405,6 +639,12 @@ clearHistoryBtn.addEventListener("click", async () => {
 const toggleDateFolders = document.getElementById("toggle-date-folders");
 const toggleUuidFilenames = document.getElementById("toggle-uuid-filenames");
 const toggleOverwriteUploads = document.getElementById("toggle-overwrite-uploads");
+const toggleContentAddressed = document.getElementById("toggle-content-addressed");
+const toggleHashFirst = document.getElementById("toggle-hash-first");
+const toggleStripMetadata = document.getElementById("toggle-strip-metadata");
+const toggleImageVariants = document.getElementById("toggle-image-variants");
+const toggleSkipHidden = document.getElementById("toggle-skip-hidden");
+const toggleFollowSymlinks = document.getElementById("toggle-follow-symlinks");
 const toggleNotifications = document.getElementById("toggle-notifications");
 
 function setSettingsToggle(btn, on) {
@@ -421,38 +661,406 @@ toggleUuidFilenames.addEventListener("click", () => {
 toggleOverwriteUploads.addEventListener("click", () => {
     setSettingsToggle(toggleOverwriteUploads, !toggleOverwriteUploads.classList.contains("on"));
 });
+toggleContentAddressed.addEventListener("click", () => {
+    setSettingsToggle(toggleContentAddressed, !toggleContentAddressed.classList.contains("on"));
+});
+toggleHashFirst.addEventListener("click", () => {
+    setSettingsToggle(toggleHashFirst, !toggleHashFirst.classList.contains("on"));
+});
+toggleStripMetadata.addEventListener("click", () => {
+    setSettingsToggle(toggleStripMetadata, !toggleStripMetadata.classList.contains("on"));
+});
+toggleImageVariants.addEventListener("click", () => {
+    setSettingsToggle(toggleImageVariants, !toggleImageVariants.classList.contains("on"));
+});
+toggleSkipHidden.addEventListener("click", () => {
+    setSettingsToggle(toggleSkipHidden, !toggleSkipHidden.classList.contains("on"));
+});
+toggleFollowSymlinks.addEventListener("click", () => {
+    setSettingsToggle(toggleFollowSymlinks, !toggleFollowSymlinks.classList.contains("on"));
+});
 toggleNotifications.addEventListener("click", () => {
     setSettingsToggle(toggleNotifications, !toggleNotifications.classList.contains("on"));
 });
 
-toggleTokenMode.addEventListener("click", () => {
-    const on = !toggleTokenMode.classList.contains("on");
-    setSettingsToggle(toggleTokenMode, on);
-    dynamicTokenSettings.classList.toggle("hidden", !on);
-    document.querySelectorAll(".static-token-field").forEach(el => {
-        el.classList.toggle("hidden", on);
+tokenModeSelect.addEventListener("change", () => {
+    showTokenModeFields(tokenModeSelect.value);
+});
+
+// Destinations: the main view picks one, settings edit the list
+async function loadDestinations() {
+    destinations = await invoke("get_destinations");
+    destinationSelect.innerHTML = "";
+    for (const dest of destinations) {
+        const option = document.createElement("option");
+        option.value = dest.id;
+        option.textContent = dest.name;
+        destinationSelect.appendChild(option);
+    }
+    // The saved destination may have been removed
+    if (destinations.length && !destinations.some(d => d.id === mode)) {
+        mode = destinations[0].id;
+        localStorage.setItem("b2u_mode", mode);
+    }
+    destinationSelect.value = mode;
+}
+
+// Key templates: show the key a destination's template would produce as it's typed
+async function updateKeyPreview(row) {
+    const preview = row.querySelector(".key-preview");
+    try {
+        const key = await invoke("preview_object_key", {
+            prefix: row.querySelector(".dest-prefix").value,
+            template: row.querySelector(".dest-template").value,
+        });
+        preview.textContent = key;
+        preview.classList.remove("error");
+        return true;
+    } catch (err) {
+        preview.textContent = err.toString();
+        preview.classList.add("error");
+        return false;
+    }
+}
+
+function createDestinationRow(dest) {
+    const row = document.createElement("div");
+    row.className = "destination-row";
+    row.dataset.id = dest.id;
+    row.innerHTML = `
+      <div class="folder-row">
+        <label class="folder-field">Name<input type="text" class="dest-name" /></label>
+        <label class="folder-field">Prefix<input type="text" class="dest-prefix" placeholder="(bucket root)" /></label>
+        <button type="button" class="h-delete-btn" title="Remove destination">x</button>
+      </div>
+      <label class="static-token-field">Token<input type="text" class="dest-token" /></label>
+      <label>Key template<input type="text" class="dest-template" placeholder="(from upload options)" /></label>
+      <div class="key-preview"></div>
+      <label>Headers<textarea class="dest-headers" rows="3" spellcheck="false"
+        placeholder="Cache-Control: public, max-age=3600&#10;[hashed] Cache-Control: public, max-age=31536000, immutable"></textarea></label>
+      <label>Image rules<textarea class="dest-image-rules" rows="2" spellcheck="false"
+        placeholder="[png, jpg] convert: webp 80&#10;[png] optimize&#10;auto-rotate"></textarea></label>
+      <label>Encryption<select class="dest-encryption settings-select">
+        <option value="none">Bucket default</option>
+        <option value="sse-b2">SSE-B2 (server-managed key)</option>
+        <option value="sse-c">SSE-C (your own key; links can't open it)</option>
+      </select></label>
+      <div class="folder-row sse-key-field">
+        <label class="folder-field">SSE-C key<input type="password" class="dest-sse-key" spellcheck="false" /></label>
+        <button type="button" class="sse-generate-btn">Generate</button>
+      </div>
+      <div class="key-preview sse-key-field">Back up this key: objects can't be read without it, and share links need it to download.</div>
+      <label>End-to-end encryption<select class="dest-e2e settings-select">
+        <option value="off">Off</option>
+        <option value="link">Key in link</option>
+        <option value="team">Team keys only</option>
+      </select></label>
+      <label class="e2e-field">Team keys<textarea class="dest-recipients" rows="2" spellcheck="false"
+        placeholder="age1... (one per line)"></textarea></label>
+    `;
+    row.querySelector(".dest-name").value = dest.name;
+    row.querySelector(".dest-prefix").value = dest.prefix;
+    row.querySelector(".dest-template").value = dest.keyTemplate;
+    row.querySelector(".dest-headers").value = dest.headers || "";
+    row.querySelector(".dest-image-rules").value = dest.imageRules || "";
+    row.querySelector(".dest-token").placeholder = dest.hasToken ? "(saved)" : "(optional)";
+    const encryptionSelect = row.querySelector(".dest-encryption");
+    const sseKeyInput = row.querySelector(".dest-sse-key");
+    encryptionSelect.value = dest.encryption || "none";
+    sseKeyInput.placeholder = dest.hasSseKey ? "(saved)" : "base64, 32 bytes";
+    const updateSseFields = () => {
+        row.querySelectorAll(".sse-key-field").forEach(el => {
+            el.classList.toggle("hidden", encryptionSelect.value !== "sse-c");
+        });
+    };
+    encryptionSelect.addEventListener("change", updateSseFields);
+    updateSseFields();
+    const e2eSelect = row.querySelector(".dest-e2e");
+    e2eSelect.value = dest.e2e || "off";
+    row.querySelector(".dest-recipients").value = dest.recipients || "";
+    const updateE2eFields = () => {
+        row.querySelector(".e2e-field").classList.toggle("hidden", e2eSelect.value === "off");
+    };
+    e2eSelect.addEventListener("change", updateE2eFields);
+    updateE2eFields();
+    row.querySelector(".sse-generate-btn").addEventListener("click", () => {
+        if (dest.hasSseKey && !confirm("Replace the saved key? Objects uploaded with it will need the old key to download.")) {
+            return;
+        }
+        const bytes = crypto.getRandomValues(new Uint8Array(32));
+        sseKeyInput.value = btoa(String.fromCharCode(...bytes));
+        // Shown so it can be copied somewhere safe
+        sseKeyInput.type = "text";
     });
+    row.querySelector(".static-token-field").classList.toggle("hidden", tokenModeSelect.value !== "static");
+    row.querySelector(".dest-prefix").addEventListener("input", () => updateKeyPreview(row));
+    row.querySelector(".dest-template").addEventListener("input", () => updateKeyPreview(row));
+    row.querySelector(".h-delete-btn").addEventListener("click", () => {
+        if (destinationList.children.length > 1) row.remove();
+    });
+    updateKeyPreview(row);
+    return row;
+}
+
+document.getElementById("add-destination-btn").addEventListener("click", () => {
+    const row = createDestinationRow({
+        id: "d-" + crypto.randomUUID().slice(0, 8),
+        name: "",
+        prefix: "",
+        keyTemplate: "",
+        headers: "",
+        imageRules: "",
+        encryption: "none",
+        e2e: "off",
+        recipients: "",
+        hasToken: false,
+        hasSseKey: false,
+    });
+    destinationList.appendChild(row);
+    row.querySelector(".dest-name").focus();
 });
 
-function capitalize(str) {
-    if (!str) return "";
-    return str.charAt(0).toUpperCase() + str.slice(1);
+// Signing keys: named HMAC secrets for dynamic links, one of them active.
+// Removing a row retires the key once settings are saved.
+function createSigningKeyRow(key) {
+    const row = document.createElement("div");
+    row.className = "signing-key-row";
+    row.innerHTML = `
+      <div class="folder-row">
+        <label class="folder-field">Key ID<input type="text" class="sk-id" spellcheck="false" placeholder="2026a" /></label>
+        <label class="signing-key-active" title="Sign new links with this key"><input type="radio" class="sk-active" />Active</label>
+        <button type="button" class="h-delete-btn" title="Retire key">x</button>
+      </div>
+      <div class="folder-row">
+        <label class="folder-field">Secret<input type="password" class="sk-secret" spellcheck="false" /></label>
+        <button type="button" class="sse-generate-btn">Generate</button>
+      </div>
+    `;
+    const idInput = row.querySelector(".sk-id");
+    const secretInput = row.querySelector(".sk-secret");
+    const activeInput = row.querySelector(".sk-active");
+    idInput.value = key.id;
+    // A saved key's ID names its secret; renaming it would need a new secret
+    idInput.readOnly = key.saved;
+    secretInput.placeholder = key.saved ? "(saved)" : "Shared HMAC-SHA256 secret";
+    activeInput.checked = key.active;
+    activeInput.addEventListener("change", () => {
+        signingKeyList.querySelectorAll(".sk-active").forEach(el => {
+            el.checked = el === activeInput;
+        });
+    });
+    row.querySelector(".sse-generate-btn").addEventListener("click", () => {
+        if (key.saved && !confirm("Replace the saved secret? Links signed with it stop working once the Worker has the new one.")) {
+            return;
+        }
+        const bytes = crypto.getRandomValues(new Uint8Array(32));
+        secretInput.value = btoa(String.fromCharCode(...bytes))
+            .replace(/\+/g, "-").replace(/\//g, "_").replace(/=+$/, "");
+        // Shown so it can be copied somewhere safe
+        secretInput.type = "text";
+    });
+    row.querySelector(".h-delete-btn").addEventListener("click", () => {
+        if (key.saved && !confirm(`Retire key ${key.id}? Links signed with it stop working once the Worker drops it.`)) {
+            return;
+        }
+        row.remove();
+    });
+    return row;
+}
+
+async function loadSigningKeys() {
+    signingKeyList.innerHTML = "";
+    for (const key of await invoke("get_signing_keys")) {
+        signingKeyList.appendChild(createSigningKeyRow({ ...key, saved: true }));
+    }
 }
 
-function updateModeLabels(folder1, folder2) {
-    const label1 = capitalize(folder1) || "Folder 1";
-    const label2 = capitalize(folder2) || "Folder 2";
-    // Toggle: left=folder1, right=folder2
-    modeLeftLabel.textContent = label1;
-    modeRightLabel.textContent = label2;
+document.getElementById("add-signing-key-btn").addEventListener("click", () => {
+    // Suggest this year's next free ID: 2026a, 2026b, ...
+    const taken = [...signingKeyList.querySelectorAll(".sk-id")].map(el => el.value);
+    const year = new Date().getFullYear();
+    let id = "";
+    for (const letter of "abcdefghijklmnopqrstuvwxyz") {
+        if (!taken.includes(`${year}${letter}`)) {
+            id = `${year}${letter}`;
+            break;
+        }
+    }
+    const row = createSigningKeyRow({ id, active: false, saved: false });
+    signingKeyList.appendChild(row);
+    row.querySelector(".sse-generate-btn").click();
+    // The first key is active; later ones are switched to once the Worker has them
+    if (signingKeyList.children.length === 1) row.querySelector(".sk-active").checked = true;
+    row.querySelector(".sk-id").focus();
+});
+
+document.getElementById("copy-signing-keys-btn").addEventListener("click", async (e) => {
+    const btn = e.currentTarget;
+    try {
+        await invoke("copy_worker_signing_keys");
+        btn.textContent = "Copied!";
+        setTimeout(() => {
+            btn.textContent = "Copy for Worker";
+        }, 1500);
+    } catch (err) {
+        settingsError.textContent = err.toString();
+        settingsError.classList.remove("hidden");
+    }
+});
+
+// This machine's age public key, created on first request
+const ageRecipientInput = document.getElementById("age-recipient");
+document.getElementById("age-recipient-btn").addEventListener("click", async (e) => {
+    const btn = e.currentTarget;
+    try {
+        if (!ageRecipientInput.value) {
+            ageRecipientInput.value = await invoke("get_age_recipient");
+            btn.textContent = "Copy";
+            return;
+        }
+        await invoke("copy_to_clipboard", { text: ageRecipientInput.value });
+        btn.textContent = "Copied!";
+        setTimeout(() => {
+            btn.textContent = "Copy";
+        }, 1500);
+    } catch (err) {
+        settingsError.textContent = err.toString();
+        settingsError.classList.remove("hidden");
+    }
+});
+
+// Endpoint placeholder follows the chosen provider
+const providerSelect = document.getElementById("provider-select");
+const ENDPOINT_EXAMPLES = {
+    b2: "s3.us-east-005.backblazeb2.com",
+    r2: "<account-id>.r2.cloudflarestorage.com",
+    aws: "s3.us-east-1.amazonaws.com",
+    wasabi: "s3.us-east-1.wasabisys.com",
+    minio: "localhost:9000",
+    custom: "s3.example.com",
+};
+
+function updateEndpointPlaceholder() {
+    settingsForm.elements["S3_ENDPOINT"].placeholder = ENDPOINT_EXAMPLES[providerSelect.value] || "";
 }
 
+providerSelect.addEventListener("change", updateEndpointPlaceholder);
+
 // Secret field names -- values are never sent back from the backend
-const SECRET_FIELDS = ["B2_APPLICATION_KEY_ID", "B2_APPLICATION_KEY", "FOLDER_1_TOKEN", "FOLDER_2_TOKEN", "TOKEN_SECRET"];
+const SECRET_FIELDS = ["B2_APPLICATION_KEY_ID", "B2_APPLICATION_KEY", "TOKEN_SECRET"];
 let currentSavedSecretKeys = [];
 
+// Profiles: each has its own connection, destinations, history and
+// keychain entry. The picker in the toolbar only shows once there are two.
+const profileSelect = document.getElementById("profile-select");
+const profileNameInput = document.getElementById("profile-name");
+const deleteProfileBtn = document.getElementById("delete-profile-btn");
+let profiles = { active: "default", profiles: [] };
+
+function activeProfile() {
+    return profiles.profiles.find(p => p.id === profiles.active) || { id: profiles.active, name: "" };
+}
+
+async function loadProfiles() {
+    profiles = await invoke("list_profiles");
+    profileSelect.innerHTML = "";
+    for (const profile of profiles.profiles) {
+        const option = document.createElement("option");
+        option.value = profile.id;
+        option.textContent = profile.name;
+        profileSelect.appendChild(option);
+    }
+    profileSelect.value = profiles.active;
+    profileSelect.classList.toggle("hidden", profiles.profiles.length < 2);
+}
+
+// Reload everything that belongs to the active profile
+async function loadProfileState() {
+    const has = await invoke("has_settings");
+    showStatus(has ? "" : "Open settings to configure", "");
+    // Load destinations and token mode to update UI
+    try {
+        await loadDestinations();
+        const settings = await invoke("get_settings");
+        notificationsEnabled = (settings.NOTIFICATIONS || "on") !== "off";
+        bundleFormat = settings.BUNDLE_UPLOADS || "off";
+        applyTokenMode(settings.TOKEN_MODE || "static");
+        if (settings.DEFAULT_TTL) {
+            const presetValues = [...ttlSelect.options].map(o => o.value).filter(v => v !== "custom");
+            if (presetValues.includes(settings.DEFAULT_TTL)) {
+                ttlSelect.value = settings.DEFAULT_TTL;
+            } else {
+                ttlSelect.value = "custom";
+                ttlCustom.value = settings.DEFAULT_TTL;
+                ttlCustom.classList.remove("hidden");
+            }
+        }
+    } catch (_) {}
+    if (has) {
+        try {
+            await offerResume();
+        } catch (_) {}
+    }
+}
+
+async function switchProfile(id) {
+    await invoke("switch_profile", { id });
+    await loadProfiles();
+    hideResults();
+    await loadProfileState();
+}
+
+profileSelect.addEventListener("change", async () => {
+    try {
+        await switchProfile(profileSelect.value);
+    } catch (err) {
+        profileSelect.value = profiles.active;
+        showStatus(err.toString(), "error");
+    }
+});
+
+document.getElementById("new-profile-btn").addEventListener("click", async () => {
+    try {
+        await invoke("create_profile", { name: `Profile ${profiles.profiles.length + 1}` });
+        await loadProfiles();
+        hideResults();
+        await loadProfileState();
+        await openSettings();
+        profileNameInput.focus();
+        profileNameInput.select();
+    } catch (err) {
+        settingsError.textContent = err.toString();
+        settingsError.classList.remove("hidden");
+    }
+});
+
+deleteProfileBtn.addEventListener("click", async () => {
+    const doomed = activeProfile();
+    const confirmed = await window.__TAURI__.dialog.ask(
+        `Delete the profile "${doomed.name}"? Its settings, destinations, history and saved keys are removed.`,
+        { title: "B2Upload", kind: "warning", okLabel: "Delete", cancelLabel: "Cancel" }
+    );
+    if (!confirmed) return;
+    try {
+        // The active profile can't be deleted; fall back to the default one
+        await switchProfile("default");
+        await invoke("delete_profile", { id: doomed.id });
+        await loadProfiles();
+        await openSettings();
+    } catch (err) {
+        settingsError.textContent = err.toString();
+        settingsError.classList.remove("hidden");
+    }
+});
+
 // Settings
-settingsBtn.addEventListener("click", async () => {
+async function openSettings() {
+    // Start from a clean form; a new profile has none of the previous one's values
+    settingsForm.reset();
+    settingsError.classList.add("hidden");
+    document.getElementById("age-recipient-btn").textContent = "Show";
+    profileNameInput.value = activeProfile().name;
+    deleteProfileBtn.classList.toggle("hidden", profiles.active === "default");
     const settings = await invoke("get_settings");
     const savedKeys = await invoke("get_saved_secret_keys");
     currentSavedSecretKeys = savedKeys;
@@ -467,23 +1075,42 @@ settingsBtn.addEventListener("click", async () => {
         input.value = "";
         input.placeholder = savedKeys.includes(key) ? "(saved)" : "";
     }
+    updateEndpointPlaceholder();
     // Populate toggles
     setSettingsToggle(toggleDateFolders, (settings.DATE_FOLDERS || "on") !== "off");
     setSettingsToggle(toggleUuidFilenames, (settings.UUID_FILENAMES || "on") !== "off");
     setSettingsToggle(toggleOverwriteUploads, (settings.OVERWRITE_UPLOADS || "no") === "yes");
+    setSettingsToggle(toggleContentAddressed, (settings.CONTENT_ADDRESSED || "off") === "on");
+    setSettingsToggle(toggleHashFirst, (settings.HASH_BEFORE_UPLOAD || "off") === "on");
+    setSettingsToggle(toggleStripMetadata, (settings.STRIP_METADATA || "off") === "on");
+    setSettingsToggle(toggleImageVariants, (settings.IMAGE_VARIANTS || "off") === "on");
+    setSettingsToggle(toggleSkipHidden, (settings.SKIP_HIDDEN_FILES || "on") !== "off");
+    setSettingsToggle(toggleFollowSymlinks, (settings.FOLLOW_SYMLINKS || "off") === "on");
     setSettingsToggle(toggleNotifications, (settings.NOTIFICATIONS || "on") !== "off");
     // Multipart parallelism (default 4)
     const parallelismInput = settingsForm.elements["MULTIPART_PARALLELISM"];
     if (parallelismInput) {
         parallelismInput.value = settings.MULTIPART_PARALLELISM || "4";
     }
+    // Thumbnail size (default 200)
+    const thumbnailInput = settingsForm.elements["IMAGE_THUMBNAIL_SIZE"];
+    if (thumbnailInput) {
+        thumbnailInput.value = settings.IMAGE_THUMBNAIL_SIZE || "200";
+    }
+    // Retries per request (default 4)
+    const retriesInput = settingsForm.elements["UPLOAD_RETRIES"];
+    if (retriesInput) {
+        retriesInput.value = settings.UPLOAD_RETRIES || "4";
+    }
+    destinationList.innerHTML = "";
+    for (const dest of await invoke("get_destinations")) {
+        destinationList.appendChild(createDestinationRow(dest));
+    }
     // Token mode
-    const isDynamic = (settings.TOKEN_MODE || "static") === "dynamic";
-    setSettingsToggle(toggleTokenMode, isDynamic);
-    dynamicTokenSettings.classList.toggle("hidden", !isDynamic);
-    document.querySelectorAll(".static-token-field").forEach(el => {
-        el.classList.toggle("hidden", isDynamic);
-    });
+    await loadSigningKeys();
+    tokenModeSelect.value = settings.TOKEN_MODE || "static";
+    settingsForm.elements["PRESIGNED_DISPOSITION"].value = settings.PRESIGNED_DISPOSITION || "";
+    showTokenModeFields(tokenModeSelect.value);
     if (settings.DEFAULT_TTL) {
         const presetValues = [...defaultTtlSelect.options].map(o => o.value).filter(v => v !== "custom");
         if (presetValues.includes(settings.DEFAULT_TTL)) {
@@ -496,7 +1123,9 @@ settingsBtn.addEventListener("click", async () => {
         }
     }
     showView(settingsView);
-});
+}
+
+settingsBtn.addEventListener("click", openSettings);
 
 backBtn.addEventListener("click", () => showView(mainView));
 
@@ -519,13 +1148,36 @@ settingsForm.addEventListener("submit", async (e) => {
     values.DATE_FOLDERS = toggleDateFolders.classList.contains("on") ? "on" : "off";
     values.UUID_FILENAMES = toggleUuidFilenames.classList.contains("on") ? "on" : "off";
     values.OVERWRITE_UPLOADS = toggleOverwriteUploads.classList.contains("on") ? "yes" : "no";
+    values.CONTENT_ADDRESSED = toggleContentAddressed.classList.contains("on") ? "on" : "off";
+    values.HASH_BEFORE_UPLOAD = toggleHashFirst.classList.contains("on") ? "on" : "off";
+    values.STRIP_METADATA = toggleStripMetadata.classList.contains("on") ? "on" : "off";
+    values.IMAGE_VARIANTS = toggleImageVariants.classList.contains("on") ? "on" : "off";
+    values.SKIP_HIDDEN_FILES = toggleSkipHidden.classList.contains("on") ? "on" : "off";
+    values.FOLLOW_SYMLINKS = toggleFollowSymlinks.classList.contains("on") ? "on" : "off";
     values.NOTIFICATIONS = toggleNotifications.classList.contains("on") ? "on" : "off";
-    values.TOKEN_MODE = toggleTokenMode.classList.contains("on") ? "dynamic" : "static";
     // Clamp multipart parallelism to [1, 16]; default 4
     const pRaw = parseInt(values.MULTIPART_PARALLELISM, 10);
     values.MULTIPART_PARALLELISM = String(
         Number.isFinite(pRaw) ? Math.min(16, Math.max(1, pRaw)) : 4
     );
+    // Part size in MiB within [5, 512] (parts are buffered in memory); empty means auto
+    const psRaw = parseInt(values.MULTIPART_PART_SIZE, 10);
+    values.MULTIPART_PART_SIZE = Number.isFinite(psRaw) && psRaw > 0
+        ? String(Math.min(512, Math.max(5, psRaw)))
+        : "";
+    // Clamp retries to [0, 10]; default 4
+    const rRaw = parseInt(values.UPLOAD_RETRIES, 10);
+    values.UPLOAD_RETRIES = String(
+        Number.isFinite(rRaw) ? Math.min(10, Math.max(0, rRaw)) : 4
+    );
+    // Clamp thumbnail size to [0, 8192]; default 200
+    const tRaw = parseInt(values.IMAGE_THUMBNAIL_SIZE, 10);
+    values.IMAGE_THUMBNAIL_SIZE = String(
+        Number.isFinite(tRaw) ? Math.min(8192, Math.max(0, tRaw)) : 200
+    );
+    // Upload limit in Mbit/s; empty or negative means unlimited
+    const lRaw = parseFloat(values.UPLOAD_LIMIT_MBPS);
+    values.UPLOAD_LIMIT_MBPS = String(Number.isFinite(lRaw) && lRaw > 0 ? lRaw : 0);
     // If default TTL is "custom", use the custom input value
     if (defaultTtlSelect.value === "custom") {
         const customVal = defaultTtlCustom.value;
@@ -535,7 +1187,9 @@ settingsForm.addEventListener("submit", async (e) => {
     }
 
     // Validate required fields
-    const required = ["DOMAIN", "BUCKET_NAME", "S3_ENDPOINT", "B2_APPLICATION_KEY_ID", "B2_APPLICATION_KEY"];
+    // Presigned and B2 links point at the bucket, so no Worker domain is needed
+    const required = ["BUCKET_NAME", "S3_ENDPOINT", "B2_APPLICATION_KEY_ID", "B2_APPLICATION_KEY"];
+    if (values.TOKEN_MODE !== "presigned" && values.TOKEN_MODE !== "b2") required.unshift("DOMAIN");
     let hasError = false;
     settingsForm.querySelectorAll(".input-error").forEach(el => el.classList.remove("input-error"));
     settingsError.classList.add("hidden");
@@ -551,9 +1205,10 @@ settingsForm.addEventListener("submit", async (e) => {
         }
     }
 
-    // Basic hostname validation for S3_ENDPOINT
+    // Basic hostname validation for S3_ENDPOINT. A scheme and port are
+    // allowed, and single-label hosts like "localhost" for local MinIO.
     if (values.S3_ENDPOINT && values.S3_ENDPOINT.trim()) {
-        const endpointPattern = /^[a-zA-Z0-9]([a-zA-Z0-9\-]*[a-zA-Z0-9])?(\.[a-zA-Z0-9]([a-zA-Z0-9\-]*[a-zA-Z0-9])?)+$/;
+        const endpointPattern = /^(https?:\/\/)?[a-zA-Z0-9]([a-zA-Z0-9\-]*[a-zA-Z0-9])?(\.[a-zA-Z0-9]([a-zA-Z0-9\-]*[a-zA-Z0-9])?)*(:\d{1,5})?\/?$/;
         if (!endpointPattern.test(values.S3_ENDPOINT.trim())) {
             const input = settingsForm.elements["S3_ENDPOINT"];
             if (input) input.classList.add("input-error");
@@ -561,13 +1216,99 @@ settingsForm.addEventListener("submit", async (e) => {
         }
     }
 
+    // Port is optional; when set it must be a valid TCP port
+    const port = (values.S3_PORT || "").trim();
+    if (port && !(/^\d+$/.test(port) && +port >= 1 && +port <= 65535)) {
+        settingsForm.elements["S3_PORT"].classList.add("input-error");
+        hasError = true;
+    }
+
     if (hasError) {
         settingsError.textContent = "Please fill in all required connection fields";
         settingsError.classList.remove("hidden");
         return;
     }
 
+    // Every destination needs a name and a key template that parses;
+    // the preview shows why one doesn't
+    const destinationInputs = [];
+    for (const row of destinationList.querySelectorAll(".destination-row")) {
+        const nameInput = row.querySelector(".dest-name");
+        const templateInput = row.querySelector(".dest-template");
+        if (!nameInput.value.trim()) {
+            nameInput.classList.add("input-error");
+            settingsError.textContent = "Every destination needs a name";
+            settingsError.classList.remove("hidden");
+            return;
+        }
+        if (templateInput.value.trim() && !(await updateKeyPreview(row))) {
+            templateInput.classList.add("input-error");
+            settingsError.textContent = "Fix the key template before saving";
+            settingsError.classList.remove("hidden");
+            return;
+        }
+        destinationInputs.push({
+            id: row.dataset.id,
+            name: nameInput.value,
+            prefix: row.querySelector(".dest-prefix").value,
+            keyTemplate: templateInput.value,
+            headers: row.querySelector(".dest-headers").value,
+            imageRules: row.querySelector(".dest-image-rules").value,
+            encryption: row.querySelector(".dest-encryption").value,
+            e2e: row.querySelector(".dest-e2e").value,
+            recipients: row.querySelector(".dest-recipients").value,
+            token: row.querySelector(".dest-token").value,
+            sseKey: row.querySelector(".dest-sse-key").value,
+        });
+    }
+
+    const signingKeyInputs = [...signingKeyList.querySelectorAll(".signing-key-row")].map(row => ({
+        id: row.querySelector(".sk-id").value.trim(),
+        secret: row.querySelector(".sk-secret").value,
+        active: row.querySelector(".sk-active").checked,
+    }));
+
+    // Limit schedule: comma-separated HH:MM-HH:MM=MBPS windows
+    const schedule = (values.UPLOAD_LIMIT_SCHEDULE || "").trim();
+    const windowPattern = /^([01]\d|2[0-3]):[0-5]\d\s*-\s*([01]\d|2[0-3]):[0-5]\d\s*=\s*\d+(\.\d+)?$/;
+    if (schedule && !schedule.split(",").every((w) => windowPattern.test(w.trim()))) {
+        settingsForm.elements["UPLOAD_LIMIT_SCHEDULE"].classList.add("input-error");
+        settingsError.textContent = "Limit schedule entries look like 19:00-07:00=0";
+        settingsError.classList.remove("hidden");
+        return;
+    }
+
+    // Variant widths: comma-separated pixel widths, 16-8192
+    const widths = (values.IMAGE_VARIANT_WIDTHS || "").trim();
+    if (widths && !widths.split(",").every((w) => /^\d+$/.test(w.trim()) && +w >= 16 && +w <= 8192)) {
+        settingsForm.elements["IMAGE_VARIANT_WIDTHS"].classList.add("input-error");
+        settingsError.textContent = "Variant widths are pixel widths from 16 to 8192, like 320, 800, 1600";
+        settingsError.classList.remove("hidden");
+        return;
+    }
+
+    const profileName = profileNameInput.value.trim();
+    if (!profileName) {
+        profileNameInput.classList.add("input-error");
+        settingsError.textContent = "The profile needs a name";
+        settingsError.classList.remove("hidden");
+        return;
+    }
+
     await invoke("save_settings", { values });
+    try {
+        await invoke("save_destinations", { destinations: destinationInputs });
+        await invoke("save_signing_keys", { keys: signingKeyInputs });
+        if (profileName !== activeProfile().name) {
+            await invoke("rename_profile", { id: profiles.active, name: profileName });
+            await loadProfiles();
+        }
+    } catch (err) {
+        settingsError.textContent = err.toString();
+        settingsError.classList.remove("hidden");
+        return;
+    }
+    await loadDestinations();
     // Update saved secret keys -- any newly entered secrets are now saved
     for (const key of SECRET_FIELDS) {
         if (values[key] && values[key].trim()) {
@@ -575,10 +1316,10 @@ settingsForm.addEventListener("submit", async (e) => {
         }
     }
     notificationsEnabled = values.NOTIFICATIONS !== "off";
-    updateModeLabels(values.FOLDER_1, values.FOLDER_2);
+    bundleFormat = values.BUNDLE_UPLOADS || "off";
     // Apply token mode to main view
     applyTokenMode(values.TOKEN_MODE);
-    if (values.TOKEN_MODE === "dynamic" && values.DEFAULT_TTL) {
+    if (values.TOKEN_MODE !== "static" && values.DEFAULT_TTL) {
         // Sync main TTL select: use preset if it matches, otherwise set custom
         const presetValues = [...ttlSelect.options].map(o => o.value).filter(v => v !== "custom");
         if (presetValues.includes(values.DEFAULT_TTL)) {
@@ -608,7 +1349,12 @@ document.getElementById("test-connection-btn").addEventListener("click", async (
         values.DATE_FOLDERS = toggleDateFolders.classList.contains("on") ? "on" : "off";
         values.UUID_FILENAMES = toggleUuidFilenames.classList.contains("on") ? "on" : "off";
         values.OVERWRITE_UPLOADS = toggleOverwriteUploads.classList.contains("on") ? "yes" : "no";
-        values.TOKEN_MODE = toggleTokenMode.classList.contains("on") ? "dynamic" : "static";
+        values.CONTENT_ADDRESSED = toggleContentAddressed.classList.contains("on") ? "on" : "off";
+        values.HASH_BEFORE_UPLOAD = toggleHashFirst.classList.contains("on") ? "on" : "off";
+        values.STRIP_METADATA = toggleStripMetadata.classList.contains("on") ? "on" : "off";
+        values.IMAGE_VARIANTS = toggleImageVariants.classList.contains("on") ? "on" : "off";
+        values.SKIP_HIDDEN_FILES = toggleSkipHidden.classList.contains("on") ? "on" : "off";
+        values.FOLLOW_SYMLINKS = toggleFollowSymlinks.classList.contains("on") ? "on" : "off";
         if (defaultTtlSelect.value === "custom") {
             const customVal = defaultTtlCustom.value;
             if (customVal && parseInt(customVal, 10) > 0) values.DEFAULT_TTL = customVal;
@@ -628,9 +1374,12 @@ document.getElementById("test-connection-btn").addEventListener("click", async (
     }, 3000);
 });
 
-// Cancel button
+// Cancel button: skip queued rows and stop the ones already in flight
 cancelBtn.addEventListener("click", () => {
     uploadCancelled = true;
+    for (const uploadId of uploadRows.keys()) {
+        invoke("cancel_upload", { uploadId }).catch(() => {});
+    }
 });
 
 // Drag and drop - use Tauri's native drag-drop events
@@ -654,18 +1403,34 @@ async function handleFilePaths(paths) {
     resultsBox.classList.remove("hidden");
     showStatus("", "");
 
-    const rows = paths.map((p) => {
-        const name = p.split(/[\\/]/).pop() || p;
+    // Several items go up as one archive when bundling is on. End-to-end
+    // encrypted destinations can't take bundles, so they get a link per file.
+    const dest = destinations.find((d) => d.id === mode);
+    let rows;
+    if (paths.length > 1 && bundleFormat !== "off" && (!dest || !dest.e2e || dest.e2e === "off")) {
+        const name = `${paths.length} items → .${bundleFormat}`;
         const tr = addResultRow(name);
         const uploadId = newUploadId();
         uploadRows.set(uploadId, tr);
-        return {
-            name,
-            path: p,
-            tr,
-            uploadId,
-        };
-    });
+        rows = [{ name, paths, isBundle: true, tr, uploadId }];
+    } else {
+        const dirFlags = await Promise.all(
+            paths.map((p) => invoke("is_directory", { path: p }).catch(() => false))
+        );
+        rows = paths.map((p, i) => {
+            const name = (p.split(/[\\/]/).pop() || p) + (dirFlags[i] ? "/" : "");
+            const tr = addResultRow(name);
+            const uploadId = newUploadId();
+            uploadRows.set(uploadId, tr);
+            return {
+                name,
+                path: p,
+                isDir: dirFlags[i],
+                tr,
+                uploadId,
+            };
+        });
+    }
 
     let succeeded = 0;
     let failed = 0;
@@ -693,19 +1458,46 @@ async function handleFilePaths(paths) {
                 active++;
                 (async () => {
                     try {
-                        const url = await invoke("upload_file", {
-                            uploadId: row.uploadId,
-                            filePath: row.path,
-                            mode,
-                            autoClip: false,
-                            ttl: getCurrentTtl(),
-                        });
-                        setRowSuccess(row.tr, url);
-                        lastResults.push({ file: row.name, url });
+                        if (row.isBundle) {
+                            const result = await invoke("upload_bundle", {
+                                uploadId: row.uploadId,
+                                paths: row.paths,
+                                mode,
+                                autoClip: false,
+                                ttl: getCurrentTtl(),
+                            });
+                            setRowBundleResult(row.tr, result);
+                            lastResults.push({ file: result.name, url: result.url });
+                        } else if (row.isDir) {
+                            const result = await invoke("upload_directory", {
+                                uploadId: row.uploadId,
+                                dirPath: row.path,
+                                mode,
+                                autoClip: false,
+                                ttl: getCurrentTtl(),
+                            });
+                            setRowDirectoryResult(row.tr, result);
+                            lastResults.push({ file: row.name, url: result.prefixUrl });
+                        } else {
+                            const result = await invoke("upload_file", {
+                                uploadId: row.uploadId,
+                                filePath: row.path,
+                                mode,
+                                autoClip: false,
+                                ttl: getCurrentTtl(),
+                            });
+                            setRowFileResult(row.tr, result);
+                            lastResults.push({ file: row.name, url: result.url });
+                        }
                         succeeded++;
                     } catch (err) {
-                        setRowError(row.tr, err.toString());
-                        failed++;
+                        if (isCancelled(err)) {
+                            setRowCancelled(row.tr);
+                            cancelled++;
+                        } else {
+                            setRowError(row.tr, err.toString());
+                            failed++;
+                        }
                     }
                     uploadRows.delete(row.uploadId);
                     active--;
@@ -1095,27 +1887,95 @@ async function runTerminal() {
     }
 }
 
-// Check settings on load
-(async () => {
-    const has = await invoke("has_settings");
-    if (!has) {
-        showStatus("Open settings to configure", "");
+// Interrupted multipart uploads: offer to resume them, otherwise offer to
+// discard them so B2 doesn't keep the orphaned parts.
+async function offerResume() {
+    const pending = await invoke("list_resumable_uploads");
+    if (pending.length === 0) return;
+
+    const names = pending.map((p) => p.file).join(", ");
+    const resume = await window.__TAURI__.dialog.ask(
+        `${pending.length} interrupted upload${pending.length > 1 ? "s" : ""} can be resumed: ${names}`,
+        { title: "B2Upload", kind: "info", okLabel: "Resume", cancelLabel: "Not now" }
+    );
+    if (!resume) {
+        const discard = await window.__TAURI__.dialog.ask(
+            "Discard the interrupted uploads? Parts already uploaded will be deleted.",
+            { title: "B2Upload", kind: "warning", okLabel: "Discard", cancelLabel: "Keep for later" }
+        );
+        if (discard) {
+            for (const p of pending) {
+                try {
+                    await invoke("discard_resumable_upload", { journalId: p.id });
+                } catch (_) {}
+            }
+        }
+        return;
     }
-    // Load folder names and token mode to update UI
-    try {
-        const settings = await invoke("get_settings");
-        notificationsEnabled = (settings.NOTIFICATIONS || "on") !== "off";
-        updateModeLabels(settings.FOLDER_1, settings.FOLDER_2);
-        applyTokenMode(settings.TOKEN_MODE || "static");
-        if (settings.DEFAULT_TTL) {
-            const presetValues = [...ttlSelect.options].map(o => o.value).filter(v => v !== "custom");
-            if (presetValues.includes(settings.DEFAULT_TTL)) {
-                ttlSelect.value = settings.DEFAULT_TTL;
+
+    isUploading = true;
+    uploadCancelled = false;
+    dropZone.classList.add("uploading");
+    dropZone.querySelector("p").textContent = "Resuming...";
+    cancelBtn.classList.remove("hidden");
+    hideResults();
+    resultsBox.classList.remove("hidden");
+    showStatus("", "");
+
+    let succeeded = 0;
+    let failed = 0;
+    let cancelled = 0;
+    for (const p of pending) {
+        const card = addResultRow(p.file);
+        if (uploadCancelled) {
+            setRowCancelled(card);
+            cancelled++;
+            continue;
+        }
+        const uploadId = newUploadId();
+        uploadRows.set(uploadId, card);
+        try {
+            const url = await invoke("resume_upload", {
+                uploadId,
+                journalId: p.id,
+                autoClip: false,
+            });
+            setRowSuccess(card, url);
+            lastResults.push({ file: p.file, url });
+            succeeded++;
+        } catch (err) {
+            if (isCancelled(err)) {
+                setRowCancelled(card);
+                cancelled++;
             } else {
-                ttlSelect.value = "custom";
-                ttlCustom.value = settings.DEFAULT_TTL;
-                ttlCustom.classList.remove("hidden");
+                setRowError(card, err.toString());
+                failed++;
             }
         }
+        uploadRows.delete(uploadId);
+    }
+    cancelBtn.classList.add("hidden");
+
+    const didCopy = autoClip && lastResults.length > 0;
+    if (didCopy) {
+        await invoke("copy_to_clipboard", { text: lastResults.map((r) => r.url).join("\n") });
+    }
+
+    dropZone.classList.remove("uploading");
+    dropZone.querySelector("p").textContent = "Drop or click to upload";
+    const parts = [];
+    if (succeeded > 0) parts.push(`${succeeded} resumed`);
+    if (failed > 0) parts.push(`${failed} failed`);
+    if (cancelled > 0) parts.push(`${cancelled} cancelled`);
+    if (didCopy) parts.push("copied to clipboard");
+    showStatus(parts.join(" · "), failed > 0 ? "error" : cancelled > 0 ? "" : "success");
+    isUploading = false;
+}
+
+// Check settings on load
+(async () => {
+    try {
+        await loadProfiles();
     } catch (_) {}
+    await loadProfileState();
 })();
@@ -1,4 +1,4 @@
-const VERSION = "1.1.2";
+const VERSION = "1.2.1";
 
 export default {
     async fetch(request, env) {
@@ -11,6 +11,7 @@ export default {
 
         const token = url.searchParams.get("token");
         const expires = url.searchParams.get("expires");
+        const kid = url.searchParams.get("kid");
 
         if (!token || !expires) {
             return new Response("Unauthorized", { status: 401 });
@@ -22,12 +23,20 @@ export default {
             return new Response("Link expired", { status: 403 });
         }
 
+        // Links with a kid are signed with that key from SIGNING_KEYS;
+        // older links without one with TOKEN_SECRET
+        // Own string properties only: a kid like "constructor" must not
+        // reach a built-in and turn it into a guessable secret
+        const keys = signingKeys(env);
+        const secret = kid
+            ? (Object.hasOwn(keys, kid) && typeof keys[kid] === "string" ? keys[kid] : null)
+            : env.TOKEN_SECRET;
+        if (!secret) {
+            return new Response("Unauthorized", { status: 401 });
+        }
+
         // Validate token - signed over path + expires together
-        const expectedToken = await generateToken(
-            path,
-            expires,
-            env.TOKEN_SECRET,
-        );
+        const expectedToken = await generateToken(path, expires, secret);
 
         if (token !== expectedToken) {
             return new Response("Unauthorized", { status: 401 });
@@ -36,6 +45,7 @@ export default {
         // Strip params before proxying to B2
         url.searchParams.delete("token");
         url.searchParams.delete("expires");
+        url.searchParams.delete("kid");
 
         const b2Url = `${env.B2_ORIGIN_URL}${path}`;
         const response = await fetch(b2Url);
@@ -50,6 +60,17 @@ export default {
     },
 };
 
+// SIGNING_KEYS is a JSON object from key ID to secret, as copied from the
+// app's settings. Retiring a key means removing it here.
+function signingKeys(env) {
+    try {
+        const keys = JSON.parse(env.SIGNING_KEYS || "{}");
+        return keys && typeof keys === "object" && !Array.isArray(keys) ? keys : {};
+    } catch {
+        return {};
+    }
+}
+
 async function generateToken(path, expires, secret) {
     const message = `${path}:${expires}`;
     const encoder = new TextEncoder();